@@ -0,0 +1,838 @@
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::time::Duration;
+use log::LevelFilter;
+use serde::Serialize;
+use crate::entity::dependency::{ConfigurationTemp, Dependency};
+use crate::entity::output::{CleanOutput, ConfigurationListOutput, ConfigurationOutput, EnvironmentOutput, FilesOutput, FsckOutput, GenerationOutput, HoldsOutput, InstalledOutput, JsonError, JsonOutput, MarkOutput, OwnsOutput, RdependsOutput, RemovedOutput, SearchItem, SearchOutput, UpdateOutput, UsageOutput, VerifyOutput, WhyOutput, JSON_SCHEMA_VERSION};
+use crate::error::global_error::GlobalError;
+use crate::manager::package_manager::VersionMode;
+use crate::tool::arch_unit::{enter_sysroot, host_architecture, set_architecture};
+use crate::tool::journal_unit::journal_unit;
+use crate::tool::lock_unit::{DatabaseLock, LockMode};
+use crate::tool::profile_unit::profile_unit;
+use crate::tool::server_module::DEFAULT_LISTEN;
+use crate::scheduler_module::{scheduler, ChangeSummary, PlanActionKind, TransactionPlan};
+
+// 命令行解析，参数格式见 usage
+
+// 没有指定 --app 时使用的应用
+pub const DEFAULT_APP: &str = "default";
+// 没有指定路径时使用的引导文件
+pub const DEFAULT_BOOTFILE: &str = "file/bootfile.txt";
+
+pub enum Command {
+    Install { app: String, packages: Vec<String> },
+    Remove { app: String, packages: Vec<String> },
+    Purge { app: String, packages: Vec<String> },
+    Upgrade { app: String, packages: Vec<String> },
+    Update,
+    List { app: Option<String>, generations: bool },
+    Show { package: String },
+    Search { keyword: String },
+    Graph { packages: Vec<String> },
+    Rdepends { package: String },
+    Why { package: String },
+    Owns { path: String },
+    Files { package: String },
+    Verify { packages: Vec<String>, fix: bool },
+    Fsck { repair: bool },
+    Build { staging: String, control: String, output: String, lenient: bool },
+    Index { directory: String, key: Option<String> },
+    Serve { directory: String, listen: String },
+    Bootstrap { path: String },
+    GarbageCollection { keep: Option<usize> },
+    Mark { manual: bool, packages: Vec<String> },
+    Autoremove,
+    Hold { packages: Vec<String> },
+    Unhold { packages: Vec<String> },
+    Holds,
+    Env { app: String },
+    Run { app: String, program: Vec<String> },
+    Rollback { app: String },
+    Clean,
+    Autoclean,
+    Help,
+}
+impl Command {
+    // 命令名，用于 json 输出
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Install { .. } => "install",
+            Command::Remove { .. } => "remove",
+            Command::Purge { .. } => "purge",
+            Command::Upgrade { .. } => "upgrade",
+            Command::Update => "update",
+            Command::List { .. } => "list",
+            Command::Show { .. } => "show",
+            Command::Search { .. } => "search",
+            Command::Graph { .. } => "graph",
+            Command::Rdepends { .. } => "rdepends",
+            Command::Why { .. } => "why",
+            Command::Owns { .. } => "owns",
+            Command::Files { .. } => "files",
+            Command::Verify { .. } => "verify",
+            Command::Fsck { .. } => "fsck",
+            Command::Build { .. } => "build",
+            Command::Index { .. } => "index",
+            Command::Serve { .. } => "serve",
+            Command::Bootstrap { .. } => "bootstrap",
+            Command::GarbageCollection { .. } => "gc",
+            Command::Mark { .. } => "mark",
+            Command::Autoremove => "autoremove",
+            Command::Hold { .. } => "hold",
+            Command::Unhold { .. } => "unhold",
+            Command::Holds => "holds",
+            Command::Env { .. } => "env",
+            Command::Run { .. } => "run",
+            Command::Rollback { .. } => "rollback",
+            Command::Clean => "clean",
+            Command::Autoclean => "autoclean",
+            Command::Help => "help",
+        }
+    }
+    // 命令需要的数据目录锁，只读的查询和模拟运行用共享锁，help、build、index 和 serve 不加锁
+    pub fn lock_mode(&self, dry_run: bool) -> Option<LockMode> {
+        match self {
+            Command::Help | Command::Build { .. } | Command::Index { .. } | Command::Serve { .. } => None,
+            Command::List { .. } | Command::Show { .. } | Command::Search { .. } | Command::Graph { .. }
+            | Command::Rdepends { .. } | Command::Why { .. } | Command::Owns { .. } | Command::Files { .. } | Command::Holds
+            | Command::Env { .. } | Command::Run { .. } | Command::Verify { fix: false, .. }
+            | Command::Fsck { repair: false } => Some(LockMode::Shared),
+            _ if dry_run => Some(LockMode::Shared),
+            _ => Some(LockMode::Exclusive),
+        }
+    }
+}
+pub struct Arguments {
+    pub command: Command,
+    // -v 的个数
+    pub verbose: usize,
+    // 只打印执行计划，不修改任何状态
+    pub dry_run: bool,
+    // 以 json 格式输出结果和错误
+    pub json: bool,
+    // 数据目录被占用时等待的秒数，None 表示立即失败
+    pub wait: Option<u64>,
+    // 安装的架构，覆盖配置文件和本机架构
+    pub arch: Option<String>,
+    // 安装到这个目录，数据目录和安装目录都在它下面
+    pub sysroot: Option<String>,
+}
+impl Arguments {
+    pub fn log_level(&self) -> LevelFilter {
+        match self.verbose {
+            0 => LevelFilter::Warn,
+            1 => LevelFilter::Info,
+            _ => LevelFilter::Debug,
+        }
+    }
+}
+pub fn usage() -> String {
+    return "usage: tmp [-v|-vv] [--dry-run] [--json] [--wait SECONDS] [--arch ARCH] [--sysroot DIR] <command> [--app APP] [arguments]
+
+commands:
+  install PACKAGE...      install packages into APP, PACKAGE is archive or archive-version
+  remove PACKAGE...       remove packages from APP, the previous generation keeps them
+  purge PACKAGE...        remove packages, drop old generations of APP and their cached archives
+  upgrade [PACKAGE...]    upgrade packages of APP (all when none given) to the latest version
+  update                  refresh the package list from the sources
+  list [--generations]    list installed packages, or the packages of APP
+  show PACKAGE            show package details
+  search KEYWORD          search the package list
+  bootstrap [PATH]        install the packages listed in the bootstrap file
+  rdepends PACKAGE        list the installed packages and apps that depend on PACKAGE directly
+  why PACKAGE             show the dependency chain from each app down to PACKAGE
+  owns PATH               show which installed package owns PATH, relative to the install root or inside a profile
+  files PACKAGE           list the files an installed package owns
+  verify [--fix] [PACKAGE...]
+                          compare the files of installed packages (all when none given) with their manifests,
+                          --fix reinstalls the broken ones from the cache
+  fsck [--repair]         cross-check the software and package data, the store, the file lists and the reference counts,
+                          --repair fixes what can be rebuilt from the rest
+  build [--output DIR] [--lenient] STAGING CONTROL
+                          package the files under STAGING with CONTROL/control.txt and its maintainer scripts
+                          into DIR/archive-version.tar.gz, DIR defaults to the current directory,
+                          --lenient leaves out dependencies without an exact version and unsupported fields instead of failing
+  index [--key FILE] DIRECTORY
+                          write the package information, dependency lists and index.toml for the archives in DIRECTORY,
+                          signing index.toml with the hex-encoded ed25519 key in FILE when given
+  serve [--listen ADDRESS] DIRECTORY
+                          serve the packages in DIRECTORY with the API of an http source until stopped,
+                          ADDRESS defaults to 127.0.0.1:8080
+  graph [PACKAGE...]      print the dependency graph of the packages, or of the whole system, as DOT
+  gc [--keep N]           remove packages no GC root reaches, keeping only the last N generations of each app when given
+  mark manual|auto PACKAGE...
+                          mark installed packages as installed by hand or as dependencies
+  autoremove              remove packages installed as dependencies that nothing installed by hand needs
+  hold PACKAGE...         keep PACKAGE at its installed version (or the given one) across upgrades
+  unhold PACKAGE...       let PACKAGE be upgraded again
+  holds                   list held packages
+  env                     print a shell script that sets PATH, LD_LIBRARY_PATH, MANPATH and the like to use the profile of APP
+  run [--] COMMAND...     run COMMAND with the environment of APP
+  rollback                switch APP back to its previous generation
+  clean                   remove all cached archives
+  autoclean               remove cached archives the sources no longer offer
+
+APP defaults to \"default\", PATH defaults to file/bootfile.txt
+GC roots are the kept generations of every app and the packages listed in database/gc_roots
+use the profile of an app in a shell with: eval \"$(tmp env --app APP)\"
+--dry-run prints the plan of install, remove, purge, upgrade, bootstrap and rollback, and what gc and autoremove would remove, without changing anything
+--json prints one JSON object per command, see file/json_output.md
+--wait waits up to SECONDS when another run holds the database lock, instead of failing at once
+--arch selects packages built for ARCH instead of the host, a foreign ARCH needs --sysroot
+--sysroot keeps the database and installed packages under DIR, the sources of this machine are used".to_string();
+}
+fn usage_error(message: String) -> GlobalError {
+    return GlobalError::UsageError(message);
+}
+// 解析命令行参数，不包括程序名
+pub fn parse(args: Vec<String>) -> Result<Arguments, GlobalError> {
+    let mut verbose = 0;
+    let mut app: Option<String> = None;
+    let mut generations = false;
+    let mut keep: Option<usize> = None;
+    let mut fix = false;
+    let mut repair = false;
+    let mut lenient = false;
+    let mut output: Option<String> = None;
+    let mut key: Option<String> = None;
+    let mut listen: Option<String> = None;
+    let mut dry_run = false;
+    let mut json = false;
+    let mut help = false;
+    let mut wait: Option<u64> = None;
+    let mut arch: Option<String> = None;
+    let mut sysroot: Option<String> = None;
+    let mut positional: Vec<String> = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-v" => verbose += 1,
+            "-vv" => verbose += 2,
+            "-h" | "--help" => help = true,
+            "--app" => match iter.next() {
+                Some(name) => app = Some(name),
+                None => return Err(usage_error("--app requires a value".to_string())),
+            },
+            "--generations" => generations = true,
+            "--fix" => fix = true,
+            "--repair" => repair = true,
+            "--lenient" => lenient = true,
+            "--key" => match iter.next() {
+                Some(value) => key = Some(value),
+                None => return Err(usage_error("--key requires a value".to_string())),
+            },
+            "--listen" => match iter.next() {
+                Some(value) => listen = Some(value),
+                None => return Err(usage_error("--listen requires a value".to_string())),
+            },
+            "--output" => match iter.next() {
+                Some(value) => output = Some(value),
+                None => return Err(usage_error("--output requires a value".to_string())),
+            },
+            "--keep" => match iter.next().map(|value| value.parse::<usize>()) {
+                Some(Ok(count)) if count > 0 => keep = Some(count),
+                Some(_) => return Err(usage_error("--keep requires a number of generations, at least 1".to_string())),
+                None => return Err(usage_error("--keep requires a value".to_string())),
+            },
+            "--arch" => match iter.next() {
+                Some(value) => arch = Some(value),
+                None => return Err(usage_error("--arch requires a value".to_string())),
+            },
+            "--sysroot" => match iter.next() {
+                Some(value) => sysroot = Some(value),
+                None => return Err(usage_error("--sysroot requires a value".to_string())),
+            },
+            // 后面的参数都属于要运行的命令
+            "--" => positional.extend(&mut iter),
+            "--dry-run" => dry_run = true,
+            "--json" => json = true,
+            "--wait" => match iter.next().map(|value| value.parse::<u64>()) {
+                Some(Ok(seconds)) => wait = Some(seconds),
+                Some(Err(_)) => return Err(usage_error("--wait requires a number of seconds".to_string())),
+                None => return Err(usage_error("--wait requires a value".to_string())),
+            },
+            _ if arg.starts_with('-') => return Err(usage_error(format!("unknown option {}", arg))),
+            _ => positional.push(arg),
+        }
+    }
+    if help {
+        return Ok(Arguments { command: Command::Help, verbose, dry_run: false, json, wait, arch, sysroot });
+    }
+    if positional.is_empty() {
+        return Err(usage_error("missing command".to_string()));
+    }
+    let name = positional.remove(0);
+    let app_or_default = app.clone().unwrap_or_else(|| DEFAULT_APP.to_string());
+    let command = match name.as_str() {
+        "install" | "remove" | "purge" => {
+            if positional.is_empty() {
+                return Err(usage_error(format!("{} requires at least one package", name)));
+            }
+            match name.as_str() {
+                "install" => Command::Install { app: app_or_default, packages: positional },
+                "remove" => Command::Remove { app: app_or_default, packages: positional },
+                _ => Command::Purge { app: app_or_default, packages: positional },
+            }
+        }
+        "upgrade" => Command::Upgrade { app: app_or_default, packages: positional },
+        "update" => Command::Update,
+        "list" => Command::List { app, generations },
+        "show" | "search" | "rdepends" | "why" | "owns" | "files" => {
+            if positional.len() != 1 {
+                return Err(usage_error(format!("{} requires exactly one argument", name)));
+            }
+            let value = positional.remove(0);
+            match name.as_str() {
+                "show" => Command::Show { package: value },
+                "rdepends" => Command::Rdepends { package: value },
+                "why" => Command::Why { package: value },
+                "owns" => Command::Owns { path: value },
+                "files" => Command::Files { package: value },
+                _ => Command::Search { keyword: value },
+            }
+        }
+        "bootstrap" => Command::Bootstrap { path: positional.pop().unwrap_or_else(|| DEFAULT_BOOTFILE.to_string()) },
+        "graph" => Command::Graph { packages: positional },
+        "gc" => Command::GarbageCollection { keep },
+        "mark" => {
+            let manual = match positional.first().map(|s| s.as_str()) {
+                Some("manual") => true,
+                Some("auto") => false,
+                _ => return Err(usage_error("mark requires manual or auto".to_string())),
+            };
+            positional.remove(0);
+            if positional.is_empty() {
+                return Err(usage_error("mark requires at least one package".to_string()));
+            }
+            Command::Mark { manual, packages: positional }
+        }
+        "verify" => Command::Verify { packages: positional, fix },
+        "fsck" => Command::Fsck { repair },
+        "build" => {
+            if positional.len() != 2 {
+                return Err(usage_error("build requires a staging directory and a control directory".to_string()));
+            }
+            let control = positional.pop().unwrap();
+            let staging = positional.pop().unwrap();
+            Command::Build { staging, control, output: output.clone().unwrap_or_else(|| ".".to_string()), lenient }
+        }
+        "index" => {
+            if positional.len() != 1 {
+                return Err(usage_error("index requires exactly one directory".to_string()));
+            }
+            Command::Index { directory: positional.remove(0), key: key.clone() }
+        }
+        "serve" => {
+            if positional.len() != 1 {
+                return Err(usage_error("serve requires exactly one directory".to_string()));
+            }
+            Command::Serve { directory: positional.remove(0), listen: listen.clone().unwrap_or_else(|| DEFAULT_LISTEN.to_string()) }
+        }
+        "autoremove" => Command::Autoremove,
+        "hold" | "unhold" => {
+            if positional.is_empty() {
+                return Err(usage_error(format!("{} requires at least one package", name)));
+            }
+            match name.as_str() {
+                "hold" => Command::Hold { packages: positional },
+                _ => Command::Unhold { packages: positional },
+            }
+        }
+        "holds" => Command::Holds,
+        "env" => Command::Env { app: app_or_default },
+        "run" => {
+            if positional.is_empty() {
+                return Err(usage_error("run requires a command".to_string()));
+            }
+            Command::Run { app: app_or_default, program: positional }
+        }
+        "rollback" => Command::Rollback { app: app_or_default },
+        "clean" => Command::Clean,
+        "autoclean" => Command::Autoclean,
+        "help" => Command::Help,
+        _ => return Err(usage_error(format!("unknown command {}", name))),
+    };
+    let simulated = matches!(command, Command::Install { .. } | Command::Remove { .. } | Command::Purge { .. }
+        | Command::Upgrade { .. } | Command::Bootstrap { .. } | Command::Rollback { .. } | Command::Autoremove
+        | Command::GarbageCollection { .. });
+    if keep.is_some() && !matches!(command, Command::GarbageCollection { .. }) {
+        return Err(usage_error(format!("{} does not support --keep", name)));
+    }
+    if fix && !matches!(command, Command::Verify { .. }) {
+        return Err(usage_error(format!("{} does not support --fix", name)));
+    }
+    if repair && !matches!(command, Command::Fsck { .. }) {
+        return Err(usage_error(format!("{} does not support --repair", name)));
+    }
+    if lenient && !matches!(command, Command::Build { .. }) {
+        return Err(usage_error(format!("{} does not support --lenient", name)));
+    }
+    if output.is_some() && !matches!(command, Command::Build { .. }) {
+        return Err(usage_error(format!("{} does not support --output", name)));
+    }
+    if key.is_some() && !matches!(command, Command::Index { .. }) {
+        return Err(usage_error(format!("{} does not support --key", name)));
+    }
+    if listen.is_some() && !matches!(command, Command::Serve { .. }) {
+        return Err(usage_error(format!("{} does not support --listen", name)));
+    }
+    // 打包、生成索引和提供软件源只读写给出的目录，和安装目录无关
+    if sysroot.is_some() && matches!(command, Command::Build { .. } | Command::Index { .. } | Command::Serve { .. }) {
+        return Err(usage_error(format!("{} does not support --sysroot", name)));
+    }
+    if dry_run && !simulated {
+        return Err(usage_error(format!("{} does not support --dry-run", name)));
+    }
+    // 命令的输出就是被运行的程序的输出，serve 一直运行，没有结果
+    if json && matches!(command, Command::Run { .. } | Command::Serve { .. }) {
+        return Err(usage_error(format!("{} does not support --json", name)));
+    }
+    // 其他架构的包不能装进本机
+    if let Some(arch) = arch.as_ref() {
+        if sysroot.is_none() && arch != host_architecture() {
+            return Err(usage_error(format!("installing for {} requires --sysroot", arch)));
+        }
+    }
+    return Ok(Arguments { command, verbose, dry_run, json, wait, arch, sysroot });
+}
+// 解析 archive 或 archive-version
+fn parse_package(spec: &str) -> Result<(String, VersionMode), GlobalError> {
+    if !spec.contains('-') {
+        return Ok((spec.to_string(), VersionMode::Latest));
+    }
+    match Dependency::from_string(spec) {
+        Ok(dependency) => return Ok((dependency.archive, VersionMode::Specific(dependency.version_wrapper))),
+        Err(e) => return Err(usage_error(format!("invalid package {}: {}", spec, e))),
+    }
+}
+// 把字节数转换成便于阅读的格式
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        return format!("{} B", size);
+    }
+    return format!("{:.1} {}", value, UNITS[unit]);
+}
+fn print_plan(plan: &TransactionPlan) {
+    println!("plan for {} (generation {}):", plan.app, plan.generation);
+    for action in plan.actions.iter() {
+        let kind = match action.kind {
+            PlanActionKind::Install => "install",
+            PlanActionKind::Upgrade => "upgrade",
+            PlanActionKind::Downgrade => "downgrade",
+            PlanActionKind::Remove => "remove",
+        };
+        let target = match action.previous.as_ref() {
+            Some(previous) => format!("{} -> {}", previous, action.package),
+            None => action.package.clone(),
+        };
+        match action.kind {
+            PlanActionKind::Remove => println!("  {:<10}{}\tfrees {}", kind, target, format_size(action.installed_size)),
+            _ => println!("  {:<10}{}\tdownload {}, installed {}", kind, target, format_size(action.download_size), format_size(action.installed_size)),
+        }
+    }
+    if plan.actions.is_empty() {
+        println!("  no packages to install or remove");
+    }
+    if !plan.reference_changes.is_empty() {
+        println!("reference counts:");
+        for change in plan.reference_changes.iter() {
+            let before = change.before.map_or("new".to_string(), |count| count.to_string());
+            println!("  {}\t{} -> {}", change.package, before, change.after);
+        }
+    }
+    let sign = if plan.installed_size_change < 0 { "-" } else { "+" };
+    println!("download size: {}", format_size(plan.download_size));
+    println!("installed size change: {}{}", sign, format_size(plan.installed_size_change.unsigned_abs()));
+    println!("dry run, nothing was changed");
+}
+fn print_summary(summary: &ChangeSummary) {
+    if let Some(plan) = summary.plan.as_ref() {
+        print_plan(plan);
+        return;
+    }
+    for name in summary.installed.iter() {
+        println!("installed {}", name);
+    }
+    for name in summary.removed.iter() {
+        println!("removed {}", name);
+    }
+    if summary.generation > 0 {
+        println!("{} is now at generation {}", summary.app, summary.generation);
+    } else if summary.installed.is_empty() && summary.removed.is_empty() {
+        println!("nothing to do");
+    }
+}
+fn print_holds(output: &HoldsOutput) {
+    for hold in output.holds.iter() {
+        println!("{}\theld", hold.to_string());
+    }
+    if output.holds.is_empty() {
+        println!("no packages are held");
+    }
+}
+// 打印可以用 eval 执行的 shell 脚本
+fn print_environment(output: &EnvironmentOutput) {
+    println!("# profile of {}: {}", output.app, output.profile);
+    for variable in output.variables.iter() {
+        println!("{}", variable.to_shell());
+    }
+}
+// 按输出模式打印结果，json 模式输出带版本号的对象，否则由 human 打印文本
+fn output<T: Serialize>(json: bool, command: &str, data: T, human: impl FnOnce(&T)) {
+    if !json {
+        human(&data);
+        return;
+    }
+    let output = JsonOutput {
+        schema_version: JSON_SCHEMA_VERSION,
+        command: command.to_string(),
+        status: "ok".to_string(),
+        data: Some(data),
+        error: None,
+    };
+    match serde_json::to_string(&output) {
+        Ok(s) => println!("{}", s),
+        Err(e) => log::error!("serialize output: {}", e),
+    }
+}
+// 打印错误，json 模式下错误也输出到标准输出，便于调用方统一解析
+pub fn print_error(json: bool, command: &str, error: &GlobalError) {
+    if !json {
+        match error {
+            GlobalError::UsageError(message) => eprintln!("{}\n\n{}", message, usage()),
+            _ => eprintln!("error: {}", error),
+        }
+        return;
+    }
+    let output : JsonOutput<()> = JsonOutput {
+        schema_version: JSON_SCHEMA_VERSION,
+        command: command.to_string(),
+        status: "error".to_string(),
+        data: None,
+        error: Some(JsonError {
+            kind: error.kind().to_string(),
+            exit_code: error.exit_code(),
+            message: error.to_string(),
+        }),
+    };
+    match serde_json::to_string(&output) {
+        Ok(s) => println!("{}", s),
+        Err(e) => log::error!("serialize output: {}", e),
+    }
+}
+// 参数中是否要求 json 输出，用于参数解析失败时选择错误格式
+pub fn wants_json(args: &[String]) -> bool {
+    return args.iter().any(|arg| arg == "--json");
+}
+// 把配置转换成输出格式，generations 为假时只保留当前版本
+fn configuration_output(configuration: ConfigurationTemp, generations: bool) -> ConfigurationOutput {
+    let count = configuration.generations.len();
+    let mut list = Vec::new();
+    for (index, packages) in configuration.generations.into_iter().enumerate() {
+        let current = index + 1 == count;
+        if !generations && !current {
+            continue;
+        }
+        list.push(GenerationOutput {
+            generation: configuration.age + index + 1 - count,
+            current,
+            packages: packages.packages,
+        });
+    }
+    let profile = profile_unit().link_path(&configuration.archive);
+    return ConfigurationOutput { app: configuration.archive, current: configuration.age, profile, generations: list };
+}
+// 执行命令，输出结果
+pub fn run(mut arguments: Arguments) -> Result<(), GlobalError> {
+    match arguments.sysroot.as_ref() {
+        Some(sysroot) => {
+            // 引导文件的路径相对于当前目录，切换前先转换成绝对路径
+            if let Command::Bootstrap { path } = &mut arguments.command {
+                if let Ok(absolute) = std::fs::canonicalize(&path) {
+                    *path = absolute.display().to_string();
+                }
+            }
+            enter_sysroot(sysroot, arguments.arch.as_deref())?;
+        }
+        None => {
+            if let Some(arch) = arguments.arch.as_ref() {
+                set_architecture(arch);
+            }
+        }
+    }
+    // 加锁后才读取数据文件，锁在命令结束时释放
+    let wait = arguments.wait.map(Duration::from_secs);
+    let mut lock = match arguments.command.lock_mode(arguments.dry_run) {
+        Some(mode) => Some(DatabaseLock::acquire(mode, wait)?),
+        None => None,
+    };
+    // 先处理上次中断的事务，恢复要修改数据，只持有共享锁时先换成独占锁
+    if let Some(lock) = lock.as_mut() {
+        if journal_unit()?.pending().is_some() {
+            // 换锁时短暂释放了锁，别的进程可能已经恢复完了，重新读取日志再判断
+            lock.upgrade(wait)?;
+            journal_unit()?.reload()?;
+            if scheduler().recover()? {
+                log::warn!("recovered from an interrupted transaction");
+            }
+        }
+    }
+    let dry_run = arguments.dry_run;
+    let json = arguments.json;
+    let command = arguments.command.name();
+    match arguments.command {
+        Command::Install { app, packages } => {
+            let mut list = Vec::new();
+            for spec in packages.iter() {
+                list.push(parse_package(spec)?);
+            }
+            output(json, command, scheduler().install(&app, list, dry_run)?, print_summary);
+        }
+        Command::Remove { app, packages } => output(json, command, scheduler().remove(&app, &packages, dry_run)?, print_summary),
+        Command::Purge { app, packages } => output(json, command, scheduler().purge(&app, &packages, dry_run)?, print_summary),
+        Command::Upgrade { app, packages } => output(json, command, scheduler().upgrade(&app, &packages, dry_run)?, print_summary),
+        Command::Update => {
+            let packages = scheduler().update()?;
+            output(json, command, UpdateOutput { packages }, |o| println!("{} packages available", o.packages));
+        }
+        Command::List { app: None, generations: false } => {
+            let softwares = scheduler().list_installed()?;
+            output(json, command, InstalledOutput { softwares }, |o| {
+                for software in o.softwares.iter() {
+                    println!("{}-{}\treferences={}\t{:?}\t{}", software.archive, software.version.to_string(), software.reference_count, software.status, if software.manual { "manual" } else { "auto" });
+                }
+            });
+        }
+        Command::List { app, generations } => {
+            let configurations = scheduler().list_configurations(app.as_deref())?.into_iter()
+                .map(|configuration| configuration_output(configuration, generations))
+                .collect();
+            output(json, command, ConfigurationListOutput { configurations }, |o| {
+                for configuration in o.configurations.iter() {
+                    println!("{} profile {}", configuration.app, configuration.profile);
+                    for generation in configuration.generations.iter() {
+                        println!("{} generation {}{}", configuration.app, generation.generation, if generation.current { " (current)" } else { "" });
+                        for package in generation.packages.iter() {
+                            println!("  {}", package.to_string());
+                        }
+                    }
+                }
+            });
+        }
+        Command::Show { package } => output(json, command, scheduler().show(&package)?, |detail| {
+            let package = &detail.package;
+            println!("archive: {}", package.archive);
+            println!("version: {}", package.version_wrapper.to_string());
+            println!("component: {}", package.component);
+            println!("origin: {}", package.origin);
+            println!("label: {}", package.label);
+            println!("architecture: {}", package.architecture);
+            println!("download: {}", package.download);
+            match detail.installed.as_ref() {
+                Some(software) => {
+                    println!("installed: yes");
+                    println!("references: {}", software.reference_count);
+                    println!("installed by: {}", if software.manual { "hand" } else { "dependency" });
+                    println!("dependencies: {}", software.dependencies.join(", "));
+                }
+                None => println!("installed: no"),
+            }
+        }),
+        Command::Search { keyword } => {
+            let packages = scheduler().search(&keyword)?.into_iter()
+                .map(|(package, installed)| SearchItem { package, installed })
+                .collect();
+            output(json, command, SearchOutput { packages }, |o| {
+                for item in o.packages.iter() {
+                    println!("{}\t{}{}", item.package.to_string(), item.package.architecture, if item.installed { "\t[installed]" } else { "" });
+                }
+            });
+        }
+        Command::Rdepends { package } => output(json, command, RdependsOutput { packages: scheduler().rdepends(&package)? }, |o| {
+            for item in o.packages.iter() {
+                println!("{}:", item.package);
+                for dependent in item.dependents.iter() {
+                    println!("  {}", dependent);
+                }
+                for app in item.apps.iter() {
+                    println!("  app {}", app);
+                }
+                if item.dependents.is_empty() && item.apps.is_empty() {
+                    println!("  (nothing)");
+                }
+            }
+        }),
+        Command::Why { package } => output(json, command, WhyOutput { packages: scheduler().why(&package)? }, |o| {
+            for item in o.packages.iter() {
+                if item.chains.is_empty() {
+                    println!("{} is not used by any app, gc will remove it", item.package);
+                }
+                for chain in item.chains.iter() {
+                    let generation = if chain.current { "" } else { " (previous generation)" };
+                    println!("{}{}: {}", chain.app, generation, chain.chain.join(" -> "));
+                }
+            }
+        }),
+        Command::Owns { path } => {
+            let owners = scheduler().owns(&path)?;
+            output(json, command, OwnsOutput { path, owners }, |o| {
+                for owner in o.owners.iter() {
+                    println!("{}: {}", owner.package, owner.file.path);
+                }
+            });
+        }
+        Command::Files { package } => output(json, command, FilesOutput { packages: scheduler().files(&package)? }, |o| {
+            for manifest in o.packages.iter() {
+                println!("{}:", manifest.package);
+                for file in manifest.files.iter() {
+                    match file.target.as_ref() {
+                        Some(target) => println!("  {} -> {}", file.path, target),
+                        None => println!("  {}", file.path),
+                    }
+                }
+            }
+        }),
+        Command::Verify { packages, fix } => output(json, command, VerifyOutput { packages: scheduler().verify(&packages, fix)? }, |o| {
+            let broken : Vec<_> = o.packages.iter().filter(|report| report.broken()).collect();
+            for report in broken.iter() {
+                if !report.recorded {
+                    println!("{}: no manifest recorded", report.package);
+                }
+                for (problem, paths) in [("missing", &report.missing), ("modified", &report.modified), ("extra", &report.extra)] {
+                    for path in paths.iter() {
+                        println!("{}: {} {}", report.package, problem, path);
+                    }
+                }
+                if report.fixed {
+                    println!("{}: reinstalled", report.package);
+                }
+            }
+            println!("{} packages checked, {} broken", o.packages.len(), broken.len());
+        }),
+        Command::Fsck { repair } => output(json, command, FsckOutput { issues: scheduler().fsck(repair)? }, |o| {
+            for issue in o.issues.iter() {
+                println!("{}: {}{}", issue.subject, issue.detail, if issue.repaired { " (repaired)" } else { "" });
+            }
+            if o.issues.is_empty() {
+                println!("no problems found");
+            }
+        }),
+        Command::Build { staging, control, output: directory, lenient } => {
+            let built = scheduler().build(Path::new(&staging), Path::new(&control), Path::new(&directory), lenient)?;
+            output(json, command, built, |o| {
+                for item in o.dropped.iter() {
+                    println!("left out {}", item);
+                }
+                println!("built {}: {}", o.package, o.path);
+                println!("{} files, {} bytes installed, {} bytes packed", o.files, o.installed_size, o.size);
+                println!("sha256 {}", o.sha256);
+            });
+        }
+        Command::Index { directory, key } => output(json, command, scheduler().index(Path::new(&directory), key.as_deref().map(Path::new))?, |o| {
+            for name in o.packages.iter() {
+                println!("indexed {}", name);
+            }
+            println!("wrote {} with {} packages", o.index, o.packages.len());
+            if let (Some(signature), Some(public_key)) = (o.signature.as_ref(), o.public_key.as_ref()) {
+                println!("signed {}, public key {}", signature, public_key);
+            }
+        }),
+        Command::Serve { directory, listen } => {
+            let server = scheduler().serve(Path::new(&directory), &listen)?;
+            println!("serving {} on http://{}", directory, server.address());
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+            server.run()?;
+        }
+        Command::Graph { packages } => {
+            let mut list = Vec::new();
+            for spec in packages.iter() {
+                list.push(parse_package(spec)?);
+            }
+            output(json, command, scheduler().graph(list)?, |graph| print!("{}", graph.to_dot()));
+        }
+        Command::Bootstrap { path } => output(json, command, scheduler().bootstrap(path, dry_run)?, print_summary),
+        Command::GarbageCollection { keep } => output(json, command, scheduler().garbage_collection(keep, dry_run)?, |o| {
+            if o.dropped_generations > 0 {
+                println!("{} {} old generations", if dry_run { "would drop" } else { "dropped" }, o.dropped_generations);
+            }
+            for name in o.removed.iter() {
+                println!("{} {}", if dry_run { "would remove" } else { "removed" }, name);
+            }
+            if o.removed.is_empty() {
+                println!("nothing to collect");
+            }
+        }),
+        Command::Mark { manual, packages } => {
+            let packages = scheduler().mark(&packages, manual)?;
+            output(json, command, MarkOutput { manual, packages }, |o| {
+                for name in o.packages.iter() {
+                    println!("{} marked as {}", name, if o.manual { "manual" } else { "auto" });
+                }
+            });
+        }
+        Command::Autoremove => {
+            let removed = scheduler().autoremove(dry_run)?;
+            output(json, command, RemovedOutput { removed }, |o| {
+                for name in o.removed.iter() {
+                    println!("{} {}", if dry_run { "would remove" } else { "removed" }, name);
+                }
+                if o.removed.is_empty() {
+                    println!("nothing to do");
+                }
+            });
+        }
+        Command::Hold { packages } => output(json, command, HoldsOutput { holds: scheduler().hold(&packages)? }, print_holds),
+        Command::Unhold { packages } => output(json, command, HoldsOutput { holds: scheduler().unhold(&packages)? }, print_holds),
+        Command::Holds => output(json, command, HoldsOutput { holds: scheduler().holds()? }, print_holds),
+        Command::Env { app } => output(json, command, scheduler().environment(&app)?, print_environment),
+        Command::Run { app, program } => {
+            let environment = scheduler().environment(&app)?;
+            let mut child = std::process::Command::new(&program[0]);
+            child.args(&program[1..]);
+            for variable in environment.variables.iter() {
+                child.env(&variable.name, variable.resolve(std::env::var(&variable.name).ok()));
+            }
+            // 用要运行的程序替换当前进程，退出码和信号都由它决定，先释放数据目录锁
+            drop(lock.take());
+            let e = child.exec();
+            return Err(GlobalError::Io(program[0].clone(), e));
+        }
+        Command::Rollback { app } => output(json, command, scheduler().rollback(&app, dry_run)?, print_summary),
+        Command::Clean => output(json, command, CleanOutput { removed: scheduler().clean()? }, |o| println!("removed {} cached archives", o.removed)),
+        Command::Autoclean => output(json, command, CleanOutput { removed: scheduler().autoclean()? }, |o| println!("removed {} cached archives", o.removed)),
+        Command::Help => output(json, command, UsageOutput { usage: usage() }, |o| println!("{}", o.usage)),
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::global_error::GlobalError;
+    use crate::tool::arch_unit::host_architecture;
+    use super::parse;
+
+    fn arguments(list: &[&str]) -> Vec<String> {
+        return list.iter().map(|arg| arg.to_string()).collect();
+    }
+    #[test]
+    fn a_foreign_architecture_needs_a_sysroot() {
+        match parse(arguments(&["--arch", "not-this-machine", "install", "a"])) {
+            Err(GlobalError::UsageError(message)) => assert!(message.contains("requires --sysroot")),
+            other => panic!("unexpected result {:?}", other.map(|arguments| arguments.command.name())),
+        }
+        let parsed = parse(arguments(&["--arch", "not-this-machine", "--sysroot", "/srv/target", "install", "a"])).unwrap();
+        assert_eq!(parsed.arch.as_deref(), Some("not-this-machine"));
+        assert_eq!(parsed.sysroot.as_deref(), Some("/srv/target"));
+        // 本机架构不需要 sysroot
+        assert!(parse(arguments(&["--arch", host_architecture(), "install", "a"])).is_ok());
+    }
+}
@@ -1,13 +1,11 @@
-use semver::{Error, Version};
+use semver::Version;
 use serde_derive::{Deserialize, Serialize};
-use std::path::Component;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::hash::{Hash, Hasher};
 use crate::entity::version_wrapper::VersionWrapper;
 use crate::error::software_error::SoftwareManagerError;
 
-use super::version_wrapper;
 
 
 #[derive(Clone, Debug, Deserialize,Serialize,PartialEq, Eq,Hash)]
@@ -25,11 +23,50 @@ impl Dependency {
     pub fn to_string(&self) -> String {
         return format!("{}-{}", self.archive, self.version_wrapper.to_string());
     }
+    // 从 archive-version 格式的字符串解析
+    pub fn from_string(dep_str: &str) -> Result<Dependency, SoftwareManagerError> {
+        let parts : Vec<&str> = dep_str.splitn(2, '-').collect();
+        if parts.len() < 2 {
+            return Err(SoftwareManagerError::ParseDependencyError(dep_str.to_string()));
+        }
+        match Version::from_str(parts[1]) {
+            Ok(version) => return Ok(Dependency::new(parts[0].to_string(), VersionWrapper::new(version))),
+            Err(e) => return Err(SoftwareManagerError::ParseDependencyError(format!("{}: {}", dep_str, e))),
+        }
+    }
 }
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DependencyListTemp{
     pub dependencies : Vec<String>
 }
+// 旧格式的依赖列表 archive-主版本号.txt，每个依赖是一个带 version 表的软件包描述
+// 只用到 archive 和 version，其他字段忽略
+#[derive(Deserialize, Debug, Clone)]
+pub struct LegacyDependencyListTemp{
+    #[serde(default)]
+    pub dependencies : Vec<LegacyDependencyTemp>
+}
+#[derive(Deserialize, Debug, Clone)]
+pub struct LegacyDependencyTemp{
+    pub archive : String,
+    pub version : LegacyVersionTemp,
+}
+#[derive(Deserialize, Debug, Clone)]
+pub struct LegacyVersionTemp{
+    pub version : String,
+}
+impl LegacyDependencyListTemp{
+    // 转换成 archive-version 格式，只写了主版本号的补齐成 x.0.0
+    pub fn to_dependencies(&self) -> Vec<String> {
+        return self.dependencies.iter().map(|dependency| {
+            let mut version = dependency.version.version.trim().to_string();
+            while version.split('.').count() < 3 {
+                version.push_str(".0");
+            }
+            format!("{}-{}", dependency.archive, version)
+        }).collect();
+    }
+}
 #[derive(Clone, Debug, Deserialize,Serialize,PartialEq, Eq,Hash)]
 pub struct BootstrapConfigurationTemp {
     depends : Vec<String>,
@@ -44,6 +81,7 @@ impl BootstrapConfiguration {
     pub fn get_depends(&self) -> Vec<Arc<Dependency>>{
         return self.depends.clone();
     }
+    #[allow(dead_code)]
     pub fn get_recommends(&self) -> Vec<Arc<Dependency>>{
         return self.recommends.clone();
     }
@@ -79,8 +117,8 @@ impl BootstrapConfiguration {
             }
         }
         return Ok(BootstrapConfiguration{
-            depends : depends,
-            recommends : recommends
+            depends,
+            recommends
         });
     }
 
@@ -97,8 +135,24 @@ pub struct Package
     pub architecture: String,
     pub download : String,
     pub others: String,
+    // 压缩包大小（字节）
+    #[serde(default)]
+    pub size: u64,
+    // 安装后占用的大小（字节）
+    #[serde(default)]
+    pub installed_size: u64,
+    // 压缩包的sha256，为空表示不检查
+    #[serde(default)]
+    pub sha256: String,
+    // 使用这个包时要设置的环境变量，NAME=VALUE 或 NAME+=VALUE，${profile} 表示应用的 profile
+    #[serde(default)]
+    pub environment: Vec<String>,
+    // 返回这个包的软件源地址，由下载器填写，下载压缩包时只使用这个源
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub source: String,
 }
 impl Package{
+    #[allow(clippy::too_many_arguments)]
     pub fn new (archive: String, version_wrapper: VersionWrapper, component: String, origin: String, label: String, architecture: String, download: String, others: String) -> Package{
         Package{
             archive,
@@ -108,7 +162,12 @@ impl Package{
             label,
             architecture,
             download,
-            others
+            others,
+            size: 0,
+            installed_size: 0,
+            sha256: String::new(),
+            environment: Vec::new(),
+            source: String::new(),
         }
     }
     pub fn to_string(&self) -> String{
@@ -117,6 +176,9 @@ impl Package{
     pub fn download(&self) -> String{
         return self.download.clone();
     }
+    pub fn to_dependency(&self) -> Dependency {
+        return Dependency::new(self.archive.clone(), self.version_wrapper.clone());
+    }
 }
 
 // 表示一个程序的所依赖的软件包集合
@@ -132,7 +194,7 @@ impl PackageList{
         return  PackageList{
             dependencies : vec
         .into_iter()
-        .map(|dep| Arc::new(dep))
+        .map(Arc::new)
         .collect()
         }
     }
@@ -142,10 +204,19 @@ impl PackageList{
             dependencies: vec,
         }
     }
+    pub fn to_dependencies(&self) -> Vec<Arc<Dependency>> {
+        return self.dependencies.iter().map(|package| Arc::new(package.to_dependency())).collect();
+    }
+    pub fn to_temp(&self) -> PackageListTemp {
+        return PackageListTemp {
+            packages: self.dependencies.iter().map(|package| package.as_ref().clone()).collect(),
+        };
+    }
 }
 #[derive(Clone, Debug, Deserialize,Serialize,PartialEq, Eq,Hash)]
 pub struct PackageListTemp
 {
+    #[serde(default)]
     pub packages: Vec<Package>
 }
 
@@ -178,6 +249,22 @@ impl InnerConfiguration{
         self.vec.push(list);
         self.add();
     }
+    // 当前使用的版本
+    pub fn current(&self) -> Option<PackageList> {
+        return self.vec.last().cloned();
+    }
+    // 版本号，最新的版本号为 age
+    pub fn generation(&self, index: usize) -> usize {
+        return self.age + index + 1 - self.vec.len();
+    }
+    // 只保留最近的 keep 个版本，返回被丢弃的版本
+    pub fn prune(&mut self, keep: usize) -> Vec<PackageList> {
+        if self.vec.len() <= keep {
+            return Vec::new();
+        }
+        let count = self.vec.len() - keep;
+        return self.vec.drain(0..count).collect();
+    }
 }
 // 实现 PartialEq 和 Eq trait
 impl PartialEq for Configuration {
@@ -198,18 +285,47 @@ impl Hash for Configuration {
 impl  Configuration  {
     // 传入依赖列表
     pub fn new(list : PackageList, archive : String, age : usize) -> Arc<Configuration>{
-        let mut vec : Vec<PackageList> = Vec::new();
-        vec.push(list);
+        let vec : Vec<PackageList> = vec![list];
         return Arc::new(Configuration{
-            archive : archive,
+            archive,
             inner : Mutex::new(InnerConfiguration{
-                vec : vec,
-                age : age,
+                vec,
+                age,
             })
         });
     }
 
-    pub fn inner(&self) -> Option<MutexGuard<InnerConfiguration>>{
+    pub fn inner(&self) -> Option<MutexGuard<'_, InnerConfiguration>>{
         self.inner.lock().ok()
     }
+    pub fn from_temp(temp : ConfigurationTemp) -> Arc<Configuration> {
+        return Arc::new(Configuration{
+            archive : temp.archive,
+            inner : Mutex::new(InnerConfiguration{
+                vec : temp.generations.into_iter().map(|list| PackageList::new(list.packages)).collect(),
+                age : temp.age,
+            })
+        });
+    }
+    // 转换成用于保存到数据文件的格式
+    pub fn to_temp(&self) -> Option<ConfigurationTemp> {
+        let guard = self.inner()?;
+        return Some(ConfigurationTemp {
+            archive : self.archive.clone(),
+            age : guard.age(),
+            generations : guard.vec.iter().map(|list| list.to_temp()).collect(),
+        });
+    }
+}
+// 配置在数据文件中的格式
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ConfigurationTemp {
+    pub archive: String,
+    pub age: usize,
+    pub generations: Vec<PackageListTemp>,
+}
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ConfigurationListTemp {
+    #[serde(default)]
+    pub configuration_temp: Vec<ConfigurationTemp>,
 }
\ No newline at end of file
@@ -0,0 +1,140 @@
+use serde_derive::Serialize;
+
+// 使用应用的 profile 时要设置的环境变量
+
+// 目录变量：变量名，profile 下的目录，原来没有设置时接在后面的值
+// MANPATH 以冒号结尾表示还要查找系统默认的目录，XDG_DATA_DIRS 没有设置时默认是 /usr/local/share:/usr/share
+pub const PROFILE_PATHS: &[(&str, &[&str], Option<&str>)] = &[
+    ("PATH", &["bin", "sbin", "usr/bin", "usr/sbin"], None),
+    ("LD_LIBRARY_PATH", &["lib", "lib64", "usr/lib", "usr/lib64"], None),
+    ("LIBRARY_PATH", &["lib", "lib64", "usr/lib", "usr/lib64"], None),
+    ("CPATH", &["include", "usr/include"], None),
+    ("PKG_CONFIG_PATH", &["lib/pkgconfig", "usr/lib/pkgconfig", "usr/share/pkgconfig"], None),
+    ("MANPATH", &["share/man", "usr/share/man"], Some("")),
+    ("INFOPATH", &["share/info", "usr/share/info"], None),
+    ("XDG_DATA_DIRS", &["share", "usr/share"], Some("/usr/local/share:/usr/share")),
+];
+// 软件包元数据中的环境变量里用这个表示 profile 的绝对路径
+pub const PROFILE_PLACEHOLDER: &str = "${profile}";
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct EnvironmentVariable {
+    pub name: String,
+    // prepend 为真时 value 是冒号分隔的目录，加在原来的值前面，否则直接覆盖原来的值
+    pub value: String,
+    pub prepend: bool,
+    // 原来没有设置时接在 value 后面的值，没有时只用 value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<String>,
+}
+// 变量名只能由字母、数字和下划线组成，且不以数字开头
+fn valid_name(name: &str) -> bool {
+    return !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+}
+// 放进 shell 的双引号中
+fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    return quoted;
+}
+impl EnvironmentVariable {
+    // 解析软件包声明的环境变量，NAME=VALUE 覆盖原来的值，NAME+=VALUE 把目录加在原来的值前面
+    pub fn parse(entry: &str, profile: &str) -> Option<EnvironmentVariable> {
+        let (name, value) = entry.split_once('=')?;
+        let (name, prepend) = match name.strip_suffix('+') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let name = name.trim();
+        if !valid_name(name) {
+            return None;
+        }
+        let value = value.replace(PROFILE_PLACEHOLDER, profile);
+        return Some(EnvironmentVariable { name: name.to_string(), value, prepend, fallback: None });
+    }
+    // 结合变量原来的值 current 算出新的值
+    pub fn resolve(&self, current: Option<String>) -> String {
+        if !self.prepend {
+            return self.value.clone();
+        }
+        match (current.filter(|value| !value.is_empty()), self.fallback.as_ref()) {
+            (Some(current), _) => return format!("{}:{}", self.value, current),
+            (None, Some(fallback)) => return format!("{}:{}", self.value, fallback),
+            (None, None) => return self.value.clone(),
+        }
+    }
+    // 设置这个变量的 shell 语句
+    pub fn to_shell(&self) -> String {
+        if !self.prepend {
+            return format!("export {}={}", self.name, quote(&self.value));
+        }
+        let rest = match self.fallback.as_ref() {
+            Some(fallback) => format!(":${{{}:-{}}}", self.name, fallback),
+            None => format!("${{{}:+:${}}}", self.name, self.name),
+        };
+        let value = quote(&self.value);
+        return format!("export {}={}{}\"", self.name, &value[..value.len() - 1], rest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use super::EnvironmentVariable;
+
+    fn directories(name: &str, value: &str, fallback: Option<&str>) -> EnvironmentVariable {
+        return EnvironmentVariable { name: name.to_string(), value: value.to_string(), prepend: true, fallback: fallback.map(|f| f.to_string()) };
+    }
+    // 在 shell 中执行 to_shell 的语句，返回变量的值，current 为变量原来的值
+    fn in_shell(variable: &EnvironmentVariable, current: Option<&str>) -> String {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(format!("{}; printf %s \"${}\"", variable.to_shell(), variable.name));
+        match current {
+            Some(value) => shell.env(&variable.name, value),
+            None => shell.env_remove(&variable.name),
+        };
+        return String::from_utf8(shell.output().unwrap().stdout).unwrap();
+    }
+    #[test]
+    fn package_entries_are_parsed() {
+        let set = EnvironmentVariable::parse("PYTHONHOME=${profile}/usr", "/p").unwrap();
+        assert_eq!(set, EnvironmentVariable { name: "PYTHONHOME".to_string(), value: "/p/usr".to_string(), prepend: false, fallback: None });
+        let prepend = EnvironmentVariable::parse(" GI_TYPELIB_PATH +=${profile}/lib/girepository", "/p").unwrap();
+        assert_eq!(prepend, directories("GI_TYPELIB_PATH", "/p/lib/girepository", None));
+        for entry in ["NO_VALUE", "=value", "1ST=value", "A-B=value", "A B=value"] {
+            assert!(EnvironmentVariable::parse(entry, "/p").is_none(), "{}", entry);
+        }
+    }
+    #[test]
+    fn directories_go_in_front_of_the_current_value() {
+        let path = directories("PATH", "/p/bin", None);
+        assert_eq!(path.resolve(Some("/usr/bin".to_string())), "/p/bin:/usr/bin");
+        assert_eq!(path.resolve(Some(String::new())), "/p/bin");
+        assert_eq!(path.resolve(None), "/p/bin");
+        let manpath = directories("MANPATH", "/p/share/man", Some(""));
+        assert_eq!(manpath.resolve(None), "/p/share/man:");
+        let set = EnvironmentVariable { name: "HOME_DIR".to_string(), value: "/p".to_string(), prepend: false, fallback: None };
+        assert_eq!(set.resolve(Some("/old".to_string())), "/p");
+    }
+    #[test]
+    fn the_shell_script_matches_run() {
+        let variables = [
+            directories("TEST_PATH", "/p/bin:/p/usr/bin", None),
+            directories("TEST_DATA_DIRS", "/p/share", Some("/usr/local/share:/usr/share")),
+            EnvironmentVariable { name: "TEST_QUOTED".to_string(), value: "a \"$b\" `c` \\d".to_string(), prepend: false, fallback: None },
+        ];
+        for variable in variables.iter() {
+            for current in [None, Some(""), Some("/old")] {
+                assert_eq!(in_shell(variable, current), variable.resolve(current.map(|c| c.to_string())), "{} {:?}", variable.name, current);
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+use serde_derive::Serialize;
+
+// 依赖图，graph 命令导出为 Graphviz DOT 或 json
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    // 应用，只在导出整个系统时出现
+    App,
+    Package,
+}
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeRelation {
+    // 包依赖另一个包
+    Depends,
+    // 应用的当前版本使用这个包
+    Requires,
+    // 应用保留的旧版本使用这个包，回退时需要
+    Keeps,
+}
+#[derive(Clone, Debug, Serialize)]
+pub struct GraphNode {
+    // 包为 archive-version，应用为 app:名字
+    pub id: String,
+    pub kind: NodeKind,
+    pub archive: String,
+    // 应用为空
+    pub version: String,
+    // 没有安装时为空
+    pub reference_count: Option<u32>,
+    pub in_cycle: bool,
+}
+#[derive(Clone, Debug, Serialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub relation: EdgeRelation,
+    pub in_cycle: bool,
+}
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DependencyGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    // check 发现的环，按依赖顺序排列，首尾相同；没有环时为空
+    pub cycle: Vec<String>,
+}
+impl DependencyGraph {
+    pub fn contains(&self, id: &str) -> bool {
+        return self.nodes.iter().any(|node| node.id == id);
+    }
+    pub fn add_node(&mut self, node: GraphNode) {
+        if !self.contains(&node.id) {
+            self.nodes.push(node);
+        }
+    }
+    pub fn add_edge(&mut self, from: &str, to: &str, relation: EdgeRelation) {
+        if self.edges.iter().any(|edge| edge.from == from && edge.to == to && edge.relation == relation) {
+            return;
+        }
+        self.edges.push(GraphEdge { from: from.to_string(), to: to.to_string(), relation, in_cycle: false });
+    }
+    // 标记环上的节点和边
+    pub fn mark_cycle(&mut self, cycle: Vec<String>) {
+        let members: HashSet<&String> = cycle.iter().collect();
+        for node in self.nodes.iter_mut() {
+            node.in_cycle = members.contains(&node.id);
+        }
+        for pair in cycle.windows(2) {
+            for edge in self.edges.iter_mut() {
+                if edge.relation == EdgeRelation::Depends && edge.from == pair[0] && edge.to == pair[1] {
+                    edge.in_cycle = true;
+                }
+            }
+        }
+        self.cycle = cycle;
+    }
+    // 转换成 Graphviz DOT 格式，环上的节点和边标红
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n    rankdir=LR;\n    node [shape=box];\n");
+        for node in self.nodes.iter() {
+            let mut label = match node.kind {
+                NodeKind::App => node.archive.clone(),
+                NodeKind::Package => format!("{}\\n{}", node.archive, node.version),
+            };
+            if let Some(count) = node.reference_count {
+                label.push_str(&format!("\\nrefs={}", count));
+            }
+            let mut attributes = vec![format!("label=\"{}\"", label)];
+            if node.kind == NodeKind::App {
+                attributes.push("shape=ellipse".to_string());
+            }
+            if node.reference_count.is_none() && node.kind == NodeKind::Package {
+                attributes.push("style=dashed".to_string());
+            }
+            if node.in_cycle {
+                attributes.push("color=red".to_string());
+            }
+            dot.push_str(&format!("    \"{}\" [{}];\n", node.id, attributes.join(", ")));
+        }
+        for edge in self.edges.iter() {
+            let mut attributes = vec![format!("label=\"{}\"", relation_name(edge.relation))];
+            if edge.relation == EdgeRelation::Keeps {
+                attributes.push("style=dotted".to_string());
+            }
+            if edge.in_cycle {
+                attributes.push("color=red".to_string());
+                attributes.push("penwidth=2".to_string());
+            }
+            dot.push_str(&format!("    \"{}\" -> \"{}\" [{}];\n", edge.from, edge.to, attributes.join(", ")));
+        }
+        dot.push_str("}\n");
+        return dot;
+    }
+}
+fn relation_name(relation: EdgeRelation) -> &'static str {
+    match relation {
+        EdgeRelation::Depends => "depends",
+        EdgeRelation::Requires => "requires",
+        EdgeRelation::Keeps => "keeps",
+    }
+}
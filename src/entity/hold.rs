@@ -0,0 +1,21 @@
+use serde_derive::{Deserialize, Serialize};
+use crate::entity::version_wrapper::VersionWrapper;
+
+// 锁定的软件包在数据文件中的格式，对应 database/hold_data.toml
+
+// 锁定在某个版本的软件，升级时保持不变
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct HoldTemp {
+    pub archive: String,
+    pub version: VersionWrapper,
+}
+impl HoldTemp {
+    pub fn to_string(&self) -> String {
+        return format!("{}-{}", self.archive, self.version.to_string());
+    }
+}
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HoldListTemp {
+    #[serde(default)]
+    pub holds: Vec<HoldTemp>,
+}
@@ -0,0 +1,47 @@
+use serde_derive::{Deserialize, Serialize};
+
+// 事务日志在数据文件中的格式，对应 database/journal.toml
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub enum JournalState {
+    // 还没有记录新版本，恢复时回滚
+    Prepared,
+    // 新版本已经记录，恢复时继续完成
+    Committed,
+}
+// 一步要做的操作，写进日志时为 install 或 remove
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalAction {
+    Install,
+    Remove,
+}
+// 事务中的一步
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalStep {
+    pub action: JournalAction,
+    // archive-version
+    pub package: String,
+    // 已经开始修改安装目录，删除开始后文件无法恢复
+    #[serde(default)]
+    pub started: bool,
+    pub done: bool,
+}
+// 事务开始前的数据文件，回滚时写回
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SnapshotFile {
+    pub path: String,
+    // 文件原来不存在时回滚要删除它
+    pub exists: bool,
+    pub content: String,
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalTemp {
+    // 操作的说明
+    pub operation: String,
+    pub state: JournalState,
+    #[serde(default)]
+    pub steps: Vec<JournalStep>,
+    #[serde(default)]
+    pub snapshot: Vec<SnapshotFile>,
+}
@@ -0,0 +1,55 @@
+use serde_derive::{Deserialize, Serialize};
+
+// 已安装软件包的文件清单，每个包一个文件 database/manifests/archive-version.toml
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    // 设备文件等其他类型
+    Other,
+}
+// 包中的一个文件，路径相对于安装目录
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct FileEntry {
+    pub path: String,
+    pub kind: FileKind,
+    // 权限位
+    pub mode: u32,
+    pub size: u64,
+    // 普通文件内容的sha256，其他类型为空
+    #[serde(default)]
+    pub hash: String,
+    // 符号链接指向的路径
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+}
+// 安装目录和文件清单的比较结果
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub package: String,
+    // 是否有文件清单，没有时无法检查
+    pub recorded: bool,
+    // 清单中有但安装目录中没有的文件
+    pub missing: Vec<String>,
+    // 类型、内容、权限或链接目标和清单不同的文件
+    pub modified: Vec<String>,
+    // 安装目录中有但清单中没有的文件
+    pub extra: Vec<String>,
+    // 重新安装后检查通过
+    pub fixed: bool,
+}
+impl VerifyReport {
+    pub fn broken(&self) -> bool {
+        return !self.recorded || !self.missing.is_empty() || !self.modified.is_empty() || !self.extra.is_empty();
+    }
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ManifestTemp {
+    // archive-version
+    pub package: String,
+    #[serde(default)]
+    pub files: Vec<FileEntry>,
+}
@@ -1,3 +1,12 @@
 pub mod dependency;
+pub mod environment;
+pub mod graph;
+pub mod hold;
+pub mod journal;
+pub mod manifest;
+pub mod output;
+pub mod repository;
+pub mod response;
 pub mod software;
+pub mod source_config;
 pub mod version_wrapper;
\ No newline at end of file
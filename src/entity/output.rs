@@ -0,0 +1,191 @@
+use serde_derive::Serialize;
+use crate::entity::dependency::Package;
+use crate::entity::environment::EnvironmentVariable;
+use crate::entity::hold::HoldTemp;
+use crate::entity::manifest::{FileEntry, ManifestTemp, VerifyReport};
+use crate::entity::software::SoftwareTemp;
+
+// 命令行 --json 输出的格式，字段说明见 file/json_output.md
+// 删除或修改字段的含义时增加版本号，只增加字段时不变
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+// 每个命令只输出一个对象，成功时有 data，失败时有 error
+#[derive(Serialize)]
+pub struct JsonOutput<T> {
+    pub schema_version: u32,
+    pub command: String,
+    // "ok" 或 "error"
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonError>,
+}
+#[derive(Serialize)]
+pub struct JsonError {
+    // 错误类型，对应 GlobalError 的成员名
+    pub kind: String,
+    pub exit_code: i32,
+    pub message: String,
+}
+// update 的结果
+#[derive(Serialize)]
+pub struct UpdateOutput {
+    pub packages: usize,
+}
+// list 不带 --app 和 --generations 的结果
+#[derive(Serialize)]
+pub struct InstalledOutput {
+    pub softwares: Vec<SoftwareTemp>,
+}
+// list --app / --generations 的结果
+#[derive(Serialize)]
+pub struct ConfigurationOutput {
+    pub app: String,
+    pub current: usize,
+    // 指向当前版本 profile 的符号链接
+    pub profile: String,
+    pub generations: Vec<GenerationOutput>,
+}
+#[derive(Serialize)]
+pub struct GenerationOutput {
+    pub generation: usize,
+    pub current: bool,
+    pub packages: Vec<Package>,
+}
+#[derive(Serialize)]
+pub struct ConfigurationListOutput {
+    pub configurations: Vec<ConfigurationOutput>,
+}
+// env 和 run 使用的环境变量
+#[derive(Serialize)]
+pub struct EnvironmentOutput {
+    pub app: String,
+    // profile 的绝对路径，指向当前版本，回退后不用重新设置
+    pub profile: String,
+    pub variables: Vec<EnvironmentVariable>,
+}
+// search 的结果
+#[derive(Serialize)]
+pub struct SearchOutput {
+    pub packages: Vec<SearchItem>,
+}
+#[derive(Serialize)]
+pub struct SearchItem {
+    pub package: Package,
+    pub installed: bool,
+}
+// rdepends 中的一个包，dependents 是直接依赖它的包，apps 是当前版本直接使用它的应用
+#[derive(Serialize)]
+pub struct ReverseDependencies {
+    pub package: String,
+    pub dependents: Vec<String>,
+    pub apps: Vec<String>,
+}
+// why 中的一个包，每个使用这个包的应用给出一条从根到这个包的依赖链
+#[derive(Serialize)]
+pub struct InstallReason {
+    pub package: String,
+    pub chains: Vec<DependencyChain>,
+}
+#[derive(Serialize)]
+pub struct DependencyChain {
+    pub app: String,
+    // 是否来自应用的当前版本，否则来自保留的旧版本
+    pub current: bool,
+    // 第一个是应用直接安装的包，最后一个是查询的包
+    pub chain: Vec<String>,
+}
+// rdepends 的结果
+#[derive(Serialize)]
+pub struct RdependsOutput {
+    pub packages: Vec<ReverseDependencies>,
+}
+// owns 的结果
+#[derive(Serialize)]
+pub struct OwnsOutput {
+    pub path: String,
+    pub owners: Vec<FileOwner>,
+}
+#[derive(Serialize)]
+pub struct FileOwner {
+    pub package: String,
+    pub file: FileEntry,
+}
+// files 的结果
+#[derive(Serialize)]
+pub struct FilesOutput {
+    pub packages: Vec<ManifestTemp>,
+}
+// verify 的结果
+#[derive(Serialize)]
+pub struct VerifyOutput {
+    pub packages: Vec<VerifyReport>,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsckIssueKind {
+    // software_data.toml 中重复的记录
+    DuplicateSoftware,
+    // package_data.toml 中重复的记录
+    DuplicatePackage,
+    // 已安装的软件依赖没有安装的包
+    DanglingDependency,
+    // 应用保留的版本中有没有安装的包
+    DanglingRoot,
+    // 已安装的软件没有软件包记录
+    MissingPackageRecord,
+    // 软件包记录没有对应的已安装软件，不会被回收
+    OrphanPackageRecord,
+    // 安装目录中不属于任何软件包的条目
+    OrphanStorePath,
+    // 不属于任何软件包的文件清单
+    OrphanManifest,
+    // 记录的引用计数和按 GC 根算出的不同
+    WrongReferenceCount,
+}
+// fsck 发现的一个问题，subject 是出问题的包、应用或路径
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckIssue {
+    pub kind: FsckIssueKind,
+    pub subject: String,
+    pub detail: String,
+    // 修复模式下已经修复
+    pub repaired: bool,
+}
+// fsck 的结果
+#[derive(Serialize)]
+pub struct FsckOutput {
+    pub issues: Vec<FsckIssue>,
+}
+// why 的结果
+#[derive(Serialize)]
+pub struct WhyOutput {
+    pub packages: Vec<InstallReason>,
+}
+// mark 的结果
+#[derive(Serialize)]
+pub struct MarkOutput {
+    pub manual: bool,
+    pub packages: Vec<String>,
+}
+// hold、unhold 和 holds 的结果，操作之后所有锁定的软件
+#[derive(Serialize)]
+pub struct HoldsOutput {
+    pub holds: Vec<HoldTemp>,
+}
+// gc 和 autoremove 的结果
+#[derive(Serialize)]
+pub struct RemovedOutput {
+    pub removed: Vec<String>,
+}
+// clean 和 autoclean 的结果
+#[derive(Serialize)]
+pub struct CleanOutput {
+    pub removed: usize,
+}
+// help 的结果
+#[derive(Serialize)]
+pub struct UsageOutput {
+    pub usage: String,
+}
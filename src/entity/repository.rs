@@ -0,0 +1,19 @@
+use serde_derive::{Deserialize, Serialize};
+use crate::entity::dependency::Package;
+
+// index 命令生成的仓库索引 index.toml，可以直接作为 PackageListTemp 读取
+
+// 索引中的一个软件包，在详细信息之外带上依赖列表
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IndexEntryTemp {
+    #[serde(flatten)]
+    pub package: Package,
+    // archive-version
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RepositoryIndexTemp {
+    #[serde(default)]
+    pub packages: Vec<IndexEntryTemp>,
+}
@@ -0,0 +1,36 @@
+use serde_derive::{Deserialize, Serialize};
+
+// 服务器接口返回的数据格式
+
+// 服务器出错时返回的格式
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ErrorResponse {
+    pub status_code: u16,
+    pub message: String,
+}
+// /api/v1/dependency/get 的返回格式，data 是toml格式的依赖列表
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DependencyResponse {
+    pub data: String,
+}
+// /api/v1/software/list 的返回格式，data 是toml格式的软件包列表，signature 是它的签名，没有签名时为空
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IndexResponse {
+    pub data: String,
+    #[serde(default)]
+    pub signature: String,
+}
+// 下载接口的返回格式，data 是base64编码的压缩包
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DownloadResponse {
+    pub data: String,
+}
+// json接口的返回结果，要么是出错信息，要么是数据
+// 出错信息放在前面，因为它的字段是必须的
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ApiResponse<T> {
+    Failure(ErrorResponse),
+    Success(T),
+}
+// /api/v1/software/information 直接返回toml格式的 Package，出错时返回 ErrorResponse
@@ -2,9 +2,7 @@ use semver::Version;
 use serde_derive::{Deserialize, Serialize};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex, MutexGuard};
-use std::hash::{Hash, Hasher};
 use crate::entity::version_wrapper::VersionWrapper;
-use crate::entity::dependency::{PackageList,Package};
 use crate::error::software_error::SoftwareManagerError;
 
 use super::dependency::Dependency;
@@ -16,7 +14,7 @@ pub struct Software {
     pub version: VersionWrapper,
     pub dependencies: Vec<Dependency>
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum SoftwareStatus {
     // 软件包是可用的
     Available,
@@ -28,50 +26,67 @@ pub enum SoftwareStatus {
 }
 #[derive(Debug, Clone)]
 pub struct InnerSoftware {
-    // 引用计数。 包括new version和 last version
+    // 引用计数。 包括new version和 last version，由 GC 根和依赖关系计算得到
     reference_count: u32,
     // 
     status: SoftwareStatus,
+    // 是否由应用直接安装，否则是作为依赖安装的，autoremove 只删除后者
+    manual: bool,
 }
 
 impl InnerSoftware{
     pub fn reference_count(&self) -> u32 {
         return self.reference_count;
     }
-    // 对软件包的引用计数做修改
-    pub fn add(&mut self) {
-        self.reference_count += 1;
+    pub fn status(&self) -> SoftwareStatus {
+        return self.status.clone();
+    }
+    pub fn manual(&self) -> bool {
+        return self.manual;
+    }
+    pub fn set_manual(&mut self, manual: bool) {
+        self.manual = manual;
+    }
+    pub fn set_reference_count(&mut self, count: u32) {
+        self.reference_count = count;
     }
     pub fn remove(&mut self) {
         self.status = SoftwareStatus::Removed;
     }
-    pub fn descrease(&mut self) -> u32{
-        if self.reference_count > 0 {
-            self.reference_count -= 1;
-            return self.reference_count;
-        }
-        else {
-            return 0;
-        }
-    }
 }
 
 impl Software {
-    pub fn new(archive : String, version : VersionWrapper, deps : Vec<Dependency>, count :u32, status: SoftwareStatus) -> Arc<Software> {
+    pub fn new(archive : String, version : VersionWrapper, deps : Vec<Dependency>, count :u32, status: SoftwareStatus, manual: bool) -> Arc<Software> {
         return Arc::new(Software {
             inner: Mutex::new(InnerSoftware {
                 reference_count: count,
-                status: status, 
+                status, 
+                manual,
             }),
-            archive : archive,
-            version : version,
+            archive,
+            version,
             dependencies: deps,
         });
     }
 
-    pub fn inner(&self) -> Option<MutexGuard<InnerSoftware>>{
+    pub fn inner(&self) -> Option<MutexGuard<'_, InnerSoftware>>{
         self.inner.lock().ok()
     }
+    pub fn to_string(&self) -> String {
+        return format!("{}-{}", self.archive, self.version.to_string());
+    }
+    // 转换成用于保存到数据文件的格式
+    pub fn to_temp(&self) -> Option<SoftwareTemp> {
+        let guard = self.inner()?;
+        return Some(SoftwareTemp::new(
+            self.archive.clone(),
+            self.version.clone(),
+            self.dependencies.iter().map(|dep| dep.to_string()).collect(),
+            guard.reference_count(),
+            guard.status(),
+            guard.manual(),
+        ));
+    }
    
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -81,20 +96,28 @@ pub struct SoftwareTemp {
     pub dependencies: Vec<String>,
     pub reference_count: u32, 
     pub status: SoftwareStatus,
+    // 旧的数据文件没有这一项，当作手动安装，autoremove 不会删除
+    #[serde(default = "default_manual")]
+    pub manual: bool,
+}
+fn default_manual() -> bool {
+    return true;
 }
 impl SoftwareTemp {
-    pub fn new(archive: String, version: VersionWrapper, dependencies: Vec<String>, reference_count: u32, status: SoftwareStatus) -> SoftwareTemp{
+    pub fn new(archive: String, version: VersionWrapper, dependencies: Vec<String>, reference_count: u32, status: SoftwareStatus, manual: bool) -> SoftwareTemp{
         return SoftwareTemp {
             archive,
             version,
             dependencies,
             reference_count,
             status,
+            manual,
         };
     }
 }
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SoftwareListTemp {
+    #[serde(default)]
     pub software_temp: Vec<SoftwareTemp>,
 }
 impl SoftwareListTemp {
@@ -104,8 +127,9 @@ impl SoftwareListTemp {
             let mut depends : Vec<Dependency> = Vec::new();
             let archive = tmp.archive.clone();
             let version = tmp.version.clone();
-            let count = tmp.reference_count.clone();
+            let count = tmp.reference_count;
             let status = tmp.status.clone();
+            let manual = tmp.manual;
             for dep_str in tmp.dependencies.iter() {
             let parts : Vec<&str> = dep_str.split('-').collect();
                 if parts.len() < 2 {
@@ -120,7 +144,7 @@ impl SoftwareListTemp {
                     }
                 }
             }
-            softwares.push(Software::new(archive, version, depends, count, status))
+            softwares.push(Software::new(archive, version, depends, count, status, manual))
         }
         return Ok(softwares);
     }
@@ -0,0 +1,107 @@
+use std::{env, fmt};
+use serde_derive::{Deserialize, Serialize};
+
+// 软件源和网络设置，对应 file/config.toml
+
+// 一个软件源
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct Source {
+    // 源的名字
+    pub name: String,
+    // 源的地址
+    pub url: String,
+    // 签名索引的 ed25519 公钥（hex），不为空时只接受签名正确的索引
+    #[serde(default)]
+    pub public_key: String,
+}
+// 访问软件源时使用的网络设置
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NetworkConfiguration {
+    // 代理地址，如 http://proxy.example.com:3128，为空表示使用系统代理设置
+    pub proxy: String,
+    // 不走代理的地址，对配置中的和环境变量中的代理都生效
+    pub no_proxy: Vec<String>,
+    // 额外信任的CA证书（pem格式）路径
+    pub ca_certificates: Vec<String>,
+    // 客户端证书（pem格式）路径
+    pub client_certificate: String,
+    // 客户端证书的私钥（pkcs8 pem格式）路径
+    pub client_key: String,
+}
+impl NetworkConfiguration {
+    // 用环境变量覆盖配置文件中的设置
+    pub fn apply_env(&mut self) {
+        if let Ok(proxy) = env::var("PACKAGE_MANAGER_PROXY") {
+            self.proxy = proxy;
+        }
+        if let Ok(no_proxy) = env::var("PACKAGE_MANAGER_NO_PROXY") {
+            self.no_proxy = split_list(&no_proxy, ',');
+        }
+        if let Ok(certificates) = env::var("PACKAGE_MANAGER_CA_CERTIFICATES") {
+            self.ca_certificates = split_list(&certificates, ':');
+        }
+        if let Ok(certificate) = env::var("PACKAGE_MANAGER_CLIENT_CERTIFICATE") {
+            self.client_certificate = certificate;
+        }
+        if let Ok(key) = env::var("PACKAGE_MANAGER_CLIENT_KEY") {
+            self.client_key = key;
+        }
+    }
+}
+fn split_list(value: &str, separator: char) -> Vec<String> {
+    return value.split(separator)
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+}
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SourceConfiguration {
+    // 按顺序查找的软件源
+    pub sources: Vec<Source>,
+    #[serde(default)]
+    pub network: NetworkConfiguration,
+    // 安装的架构，为空表示使用本机架构
+    #[serde(default)]
+    pub architecture: String,
+}
+impl SourceConfiguration {
+    // 没有配置文件时使用本地的默认源
+    pub fn default_configuration() -> SourceConfiguration {
+        return SourceConfiguration {
+            sources: vec![Source { name: "local".to_string(), url: "http://127.0.0.1:8080".to_string(), public_key: String::new() }],
+            network: NetworkConfiguration::default(),
+            architecture: String::new(),
+        };
+    }
+}
+// 私有软件源的认证信息，保存在单独的只允许root读取的文件中
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Credential {
+    // 对应的软件源名字
+    pub source: String,
+    // bearer token，不为空时优先使用
+    #[serde(default)]
+    pub token: String,
+    // basic auth 的用户名和密码
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
+// 认证信息不能出现在日志里，Debug 只输出源的名字
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credential")
+            .field("source", &self.source)
+            .field("token", &"***")
+            .field("username", &"***")
+            .field("password", &"***")
+            .finish()
+    }
+}
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CredentialList {
+    #[serde(default)]
+    pub credentials: Vec<Credential>,
+}
@@ -1,8 +1,9 @@
-use std::{collections::LinkedList, sync::Arc};
+use std::{collections::LinkedList, fmt, io::ErrorKind, sync::Arc};
 
 use crate::entity::dependency::{Dependency, Package};
 use crate::manager::package_manager::PackageManagerError;
 use crate::error::software_error::SoftwareManagerError;
+use crate::error::system_error::SystemError;
 
 #[derive(Debug)]
 pub enum GlobalError {
@@ -11,8 +12,10 @@ pub enum GlobalError {
     // 依赖不存在
     DependencyNotFound(Arc<Package>),
     // 依赖包已经下载
+    #[allow(dead_code)]
     DependencyAlreadyInstalled(Arc<Package>),
     // 依赖包未下载
+    #[allow(dead_code)]
     DependencyNotInstalled(Arc<Package>),
     // 下载时遇到错误
     DownloadError(String),
@@ -22,21 +25,297 @@ pub enum GlobalError {
     ParseDependencyError(String),
     // 读取本地文件失败
     ReadLocalFileError(String),
+    // 生成本地文件的内容失败(路径，原因)
+    WriteLocalFileError(String, toml::ser::Error),
     // 删除依赖时遇到错误
+    #[allow(dead_code)]
     RemoveDependencyError(String),
     // software加锁失败
     SoftwareLockError(String),
+    // 软件包不存在
+    PackageNotFound(String),
+    // 没有当前架构的软件包(包，包的架构，当前架构)
+    ArchitectureMismatch(String, String, String),
+    // 要改变锁定的软件包(锁定的包，需要的版本和原因)
+    PackageHeld(String, String),
+    // 和已安装的包的文件冲突(包，冲突的路径，拥有这个路径的包)
+    FileConflict(String, String, String),
+    // 打包时控制文件或暂存目录不合法(路径，原因)
+    InvalidControl(String, String),
+    // 校验和或签名和软件源给出的不符(地址，原因)
+    IntegrityError(String, String),
+    // 安装软件包失败(包，原因)
+    PackageInstallFailed(String, Box<GlobalError>),
+    // 卸载软件包失败(包，原因)
+    PackageUninstallFailed(String, Box<GlobalError>),
+    // 解压失败(安装目录，原因)
+    DecompressError(String, std::io::Error),
+    // 读写本地文件失败(路径，原因)
+    Io(String, std::io::Error),
+    // 本地文件的格式错误(路径，原因)
+    ParseLocalFileError(String, toml::de::Error),
+    // 签名密钥文件不是 hex 编码的 32 字节(路径)
+    InvalidKey(String),
+    // 目录中有两个压缩包是同一个软件包(压缩包的路径，软件包)
+    DuplicatePackage(String, String),
+    // 上一个事务还没有完成(事务的操作)
+    TransactionPending(String),
     //****************************************************/
     // Configuration重复存在
+    #[allow(dead_code)]
     DuplicateConfiguration,
     // 配置不存在
     ConfigurationNotFound(String),
     // 更新失败
-    ConfigurationUpdateFailed,
+    ConfigurationUpdateFailed(String),
     // 加锁失败
     ConfigurationLockFailed,
+    // 数据目录被其他进程锁定(持有锁的进程号)
+    DatabaseLocked(Option<u32>),
+    // 数据目录加锁失败
+    DatabaseLockError(String),
     //****************************************************/
-    
+    // 服务器返回了非成功的状态码(地址，状态码，返回内容摘要)
+    HttpStatusError(String, u16, String),
+    // 服务器返回了错误信息(地址，状态码，错误信息)
+    ServerError(String, u16, String),
+    // 返回内容不符合接口格式(地址，返回内容摘要)
+    MalformedResponse(String, String),
+    // 压缩包的base64编码无法解析(地址，返回内容摘要)
+    InvalidArchiveEncoding(String, String),
+    // 请求没有得到响应(地址，原因)
+    RequestError(String, reqwest::Error),
+    // 按网络设置创建 http 客户端失败(出错的设置，原因)
+    ClientError(String, reqwest::Error),
+    // 读写本地缓存失败(路径，原因)
+    CacheError(String, std::io::Error),
+    // 私有软件源认证失败(地址，状态码)
+    AuthenticationError(String, u16),
+    //****************************************************/
+    // 命令行参数错误
+    UsageError(String),
+}
+// 命令行退出码，按错误类别区分
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NOT_FOUND: i32 = 3;
+pub const EXIT_NETWORK: i32 = 4;
+pub const EXIT_DEPENDENCY: i32 = 5;
+pub const EXIT_LOCAL_FILE: i32 = 6;
+pub const EXIT_LOCK: i32 = 7;
+pub const EXIT_CONFIGURATION: i32 = 8;
+impl GlobalError {
+    // 错误对应的退出码
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // 带上下文的错误按原因分类
+            GlobalError::PackageInstallFailed(_, e) | GlobalError::PackageUninstallFailed(_, e) => e.exit_code(),
+            GlobalError::UsageError(_) => EXIT_USAGE,
+            GlobalError::DependencyNotFound(_)
+            | GlobalError::DependencyNotInstalled(_)
+            | GlobalError::PackageNotFound(_)
+            | GlobalError::ArchitectureMismatch(_, _, _) => EXIT_NOT_FOUND,
+            GlobalError::DownloadError(_)
+            | GlobalError::HttpStatusError(_, _, _)
+            | GlobalError::ServerError(_, _, _)
+            | GlobalError::MalformedResponse(_, _)
+            | GlobalError::InvalidArchiveEncoding(_, _)
+            | GlobalError::IntegrityError(_, _)
+            | GlobalError::RequestError(_, _)
+            | GlobalError::ClientError(_, _)
+            | GlobalError::AuthenticationError(_, _) => EXIT_NETWORK,
+            GlobalError::CircularDependency(_)
+            | GlobalError::ParseDependencyError(_)
+            | GlobalError::PackageHeld(_, _)
+            | GlobalError::FileConflict(_, _, _)
+            | GlobalError::DependencyAlreadyInstalled(_) => EXIT_DEPENDENCY,
+            GlobalError::InstallDependencyError(_)
+            | GlobalError::RemoveDependencyError(_)
+            | GlobalError::ReadLocalFileError(_)
+            | GlobalError::WriteLocalFileError(_, _)
+            | GlobalError::DecompressError(_, _)
+            | GlobalError::InvalidControl(_, _)
+            | GlobalError::Io(_, _)
+            | GlobalError::ParseLocalFileError(_, _)
+            | GlobalError::InvalidKey(_)
+            | GlobalError::DuplicatePackage(_, _)
+            | GlobalError::CacheError(_, _) => EXIT_LOCAL_FILE,
+            GlobalError::SoftwareLockError(_)
+            | GlobalError::ConfigurationLockFailed
+            | GlobalError::DatabaseLocked(_)
+            | GlobalError::DatabaseLockError(_)
+            | GlobalError::TransactionPending(_) => EXIT_LOCK,
+            GlobalError::DuplicateConfiguration
+            | GlobalError::ConfigurationNotFound(_)
+            | GlobalError::ConfigurationUpdateFailed(_) => EXIT_CONFIGURATION,
+        }
+    }
+    // 错误类型名，用于 json 输出，带上下文的错误使用原因的类型
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GlobalError::PackageInstallFailed(_, e) | GlobalError::PackageUninstallFailed(_, e) => e.kind(),
+            GlobalError::DecompressError(_, _) => "DecompressError",
+            GlobalError::ArchitectureMismatch(_, _, _) => "ArchitectureMismatch",
+            GlobalError::PackageHeld(_, _) => "PackageHeld",
+            GlobalError::FileConflict(_, _, _) => "FileConflict",
+            GlobalError::InvalidControl(_, _) => "InvalidControl",
+            GlobalError::IntegrityError(_, _) => "IntegrityError",
+            GlobalError::Io(_, _) => "Io",
+            GlobalError::ParseLocalFileError(_, _) => "ParseLocalFileError",
+            GlobalError::InvalidKey(_) => "InvalidKey",
+            GlobalError::DuplicatePackage(_, _) => "DuplicatePackage",
+            GlobalError::TransactionPending(_) => "TransactionPending",
+            GlobalError::RequestError(_, _) => "RequestError",
+            GlobalError::ClientError(_, _) => "ClientError",
+            GlobalError::CircularDependency(_) => "CircularDependency",
+            GlobalError::DependencyNotFound(_) => "DependencyNotFound",
+            GlobalError::DependencyAlreadyInstalled(_) => "DependencyAlreadyInstalled",
+            GlobalError::DependencyNotInstalled(_) => "DependencyNotInstalled",
+            GlobalError::DownloadError(_) => "DownloadError",
+            GlobalError::InstallDependencyError(_) => "InstallDependencyError",
+            GlobalError::ParseDependencyError(_) => "ParseDependencyError",
+            GlobalError::ReadLocalFileError(_) => "ReadLocalFileError",
+            GlobalError::WriteLocalFileError(_, _) => "WriteLocalFileError",
+            GlobalError::RemoveDependencyError(_) => "RemoveDependencyError",
+            GlobalError::SoftwareLockError(_) => "SoftwareLockError",
+            GlobalError::PackageNotFound(_) => "PackageNotFound",
+            GlobalError::DuplicateConfiguration => "DuplicateConfiguration",
+            GlobalError::ConfigurationNotFound(_) => "ConfigurationNotFound",
+            GlobalError::ConfigurationUpdateFailed(_) => "ConfigurationUpdateFailed",
+            GlobalError::ConfigurationLockFailed => "ConfigurationLockFailed",
+            GlobalError::DatabaseLocked(_) => "DatabaseLocked",
+            GlobalError::DatabaseLockError(_) => "DatabaseLockError",
+            GlobalError::HttpStatusError(_, _, _) => "HttpStatusError",
+            GlobalError::ServerError(_, _, _) => "ServerError",
+            GlobalError::MalformedResponse(_, _) => "MalformedResponse",
+            GlobalError::InvalidArchiveEncoding(_, _) => "InvalidArchiveEncoding",
+            GlobalError::CacheError(_, _) => "CacheError",
+            GlobalError::AuthenticationError(_, _) => "AuthenticationError",
+            GlobalError::UsageError(_) => "UsageError",
+        }
+    }
+    // 对应的 errno，供嵌入 DragonOS 的调用方使用
+    #[allow(dead_code)]
+    pub fn to_system_error(&self) -> SystemError {
+        match self {
+            GlobalError::PackageInstallFailed(_, e) | GlobalError::PackageUninstallFailed(_, e) => e.to_system_error(),
+            GlobalError::Io(_, e) | GlobalError::DecompressError(_, e) | GlobalError::CacheError(_, e) => io_system_error(e),
+            GlobalError::UsageError(_)
+            | GlobalError::InvalidControl(_, _)
+            | GlobalError::ParseLocalFileError(_, _)
+            | GlobalError::InvalidKey(_)
+            | GlobalError::ClientError(_, _)
+            | GlobalError::ParseDependencyError(_) => SystemError::EINVAL,
+            GlobalError::DependencyNotFound(_)
+            | GlobalError::DependencyNotInstalled(_)
+            | GlobalError::PackageNotFound(_)
+            | GlobalError::ConfigurationNotFound(_) => SystemError::ENOENT,
+            GlobalError::CircularDependency(_) => SystemError::ELOOP,
+            GlobalError::ArchitectureMismatch(_, _, _) => SystemError::ENOEXEC,
+            GlobalError::PackageHeld(_, _) => SystemError::EPERM,
+            GlobalError::DependencyAlreadyInstalled(_)
+            | GlobalError::FileConflict(_, _, _)
+            | GlobalError::DuplicatePackage(_, _)
+            | GlobalError::DuplicateConfiguration => SystemError::EEXIST,
+            GlobalError::DownloadError(_)
+            | GlobalError::RequestError(_, _) => SystemError::ENETUNREACH,
+            GlobalError::HttpStatusError(_, _, _)
+            | GlobalError::ServerError(_, _, _)
+            | GlobalError::MalformedResponse(_, _)
+            | GlobalError::InvalidArchiveEncoding(_, _) => SystemError::EPROTO,
+            GlobalError::AuthenticationError(_, _) => SystemError::EACCES,
+            GlobalError::IntegrityError(_, _) => SystemError::EBADMSG,
+            GlobalError::DatabaseLocked(_) => SystemError::EAGAIN_OR_EWOULDBLOCK,
+            GlobalError::SoftwareLockError(_)
+            | GlobalError::ConfigurationLockFailed
+            | GlobalError::DatabaseLockError(_) => SystemError::ENOLCK,
+            GlobalError::TransactionPending(_) => SystemError::EBUSY,
+            GlobalError::InstallDependencyError(_)
+            | GlobalError::RemoveDependencyError(_)
+            | GlobalError::ReadLocalFileError(_)
+            | GlobalError::WriteLocalFileError(_, _)
+            | GlobalError::ConfigurationUpdateFailed(_) => SystemError::EIO,
+        }
+    }
+}
+// io 错误对应的 errno，有操作系统错误码时直接使用
+fn io_system_error(error: &std::io::Error) -> SystemError {
+    if let Some(errno) = error.raw_os_error() {
+        if let Some(e) = <SystemError as num_traits::FromPrimitive>::from_i32(errno) {
+            return e;
+        }
+    }
+    match error.kind() {
+        ErrorKind::NotFound => SystemError::ENOENT,
+        ErrorKind::PermissionDenied => SystemError::EACCES,
+        ErrorKind::AlreadyExists => SystemError::EEXIST,
+        ErrorKind::WouldBlock => SystemError::EAGAIN_OR_EWOULDBLOCK,
+        ErrorKind::InvalidInput | ErrorKind::InvalidData => SystemError::EINVAL,
+        _ => SystemError::EIO,
+    }
+}
+impl fmt::Display for GlobalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            GlobalError::CircularDependency(l) => {
+                let cycle : Vec<String> = l.iter().map(|dep| dep.to_string()).collect();
+                format!("circular dependency: {}", cycle.join(" -> "))
+            }
+            GlobalError::DependencyNotFound(p) => format!("dependency not found: {}", p.to_string()),
+            GlobalError::DependencyAlreadyInstalled(p) => format!("dependency already installed: {}", p.to_string()),
+            GlobalError::DependencyNotInstalled(p) => format!("dependency not installed: {}", p.to_string()),
+            GlobalError::DownloadError(s) => format!("download error: {}", s),
+            GlobalError::InstallDependencyError(s) => format!("install error: {}", s),
+            GlobalError::ParseDependencyError(s) => format!("parse dependency error: {}", s),
+            GlobalError::ReadLocalFileError(s) => format!("read local file error: {}", s),
+            GlobalError::WriteLocalFileError(path, e) => format!("write local file error: {}: {}", path, e),
+            GlobalError::RemoveDependencyError(s) => format!("remove error: {}", s),
+            GlobalError::SoftwareLockError(s) => format!("software lock error: {}", s),
+            GlobalError::PackageNotFound(s) => format!("package not found: {}", s),
+            GlobalError::ArchitectureMismatch(p, package_arch, arch) => format!("{} is built for {}, not {}", p, package_arch, arch),
+            GlobalError::PackageHeld(held, reason) => format!("{} is held, but {}; run unhold first", held, reason),
+            GlobalError::FileConflict(p, path, owner) => format!("{} would overwrite {}, which belongs to {}", p, path, owner),
+            GlobalError::InvalidControl(path, reason) => format!("invalid package {}: {}", path, reason),
+            GlobalError::IntegrityError(url, reason) => format!("integrity check of {} failed: {}", url, reason),
+            GlobalError::PackageInstallFailed(p, e) => format!("install {} failed: {}", p, e),
+            GlobalError::PackageUninstallFailed(p, e) => format!("uninstall {} failed: {}", p, e),
+            GlobalError::DecompressError(path, e) => format!("decompress error: {}: {}", path, e),
+            GlobalError::Io(path, e) => format!("{}: {}", path, e),
+            GlobalError::ParseLocalFileError(path, e) => format!("{} is corrupted: {}", path, e),
+            GlobalError::InvalidKey(path) => format!("{}: the key must be 32 hex-encoded bytes", path),
+            GlobalError::DuplicatePackage(path, p) => format!("{}: {} is given twice", path, p),
+            GlobalError::TransactionPending(operation) => format!("transaction \"{}\" is not finished", operation),
+            GlobalError::DuplicateConfiguration => "duplicate configuration".to_string(),
+            GlobalError::ConfigurationNotFound(s) => format!("configuration not found: {}", s),
+            GlobalError::ConfigurationUpdateFailed(s) => format!("configuration update failed: {}", s),
+            GlobalError::ConfigurationLockFailed => "configuration lock failed".to_string(),
+            GlobalError::DatabaseLocked(Some(pid)) => format!("database is locked by PID {}", pid),
+            GlobalError::DatabaseLocked(None) => "database is locked by another process".to_string(),
+            GlobalError::DatabaseLockError(s) => format!("database lock error: {}", s),
+            GlobalError::HttpStatusError(url, code, body) => format!("http status {} from {}: {}", code, url, body),
+            GlobalError::ServerError(url, code, message) => format!("server error {} from {}: {}", code, url, message),
+            GlobalError::MalformedResponse(url, body) => format!("malformed response from {}: {}", url, body),
+            GlobalError::InvalidArchiveEncoding(url, body) => format!("invalid base64 archive from {}: {}", url, body),
+            GlobalError::RequestError(url, e) => format!("request {} failed: {}", url, e),
+            GlobalError::ClientError(setting, e) => format!("invalid network setting {}: {}", setting, e),
+            GlobalError::CacheError(path, e) => format!("cache error: {}: {}", path, e),
+            GlobalError::AuthenticationError(url, code) => format!("authentication failed ({}) for {}", code, url),
+            GlobalError::UsageError(s) => s.clone(),
+        };
+        write!(f, "{}", message)
+    }
+}
+impl std::error::Error for GlobalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GlobalError::PackageInstallFailed(_, e) | GlobalError::PackageUninstallFailed(_, e) => Some(e.as_ref()),
+            GlobalError::Io(_, e) | GlobalError::DecompressError(_, e) | GlobalError::CacheError(_, e) => Some(e),
+            GlobalError::WriteLocalFileError(_, e) => Some(e),
+            GlobalError::RequestError(_, e) | GlobalError::ClientError(_, e) => Some(e),
+            GlobalError::ParseLocalFileError(_, e) => Some(e),
+            _ => None,
+        }
+    }
 }
 impl From<SoftwareManagerError> for GlobalError {
     fn from(error: SoftwareManagerError) -> Self {
@@ -46,26 +325,49 @@ impl From<SoftwareManagerError> for GlobalError {
             SoftwareManagerError::DownloadError(d) => GlobalError::DownloadError(d),
             SoftwareManagerError::ParseDependencyError(d) => GlobalError::ParseDependencyError(d),
             SoftwareManagerError::SoftwareLockError(s) => GlobalError::SoftwareLockError(s),
-            SoftwareManagerError::ReadLocalSoftwareFileError(_) => todo!(),
-            SoftwareManagerError::PackageInstalled => todo!(),
-            SoftwareManagerError::PackageNotFound(_) => todo!(),
-            SoftwareManagerError::PackageLockFailed => todo!(),
-            SoftwareManagerError::PackageInstallFailed => todo!(),
-            SoftwareManagerError::PackageUninstallFailed => todo!(),
-            SoftwareManagerError::ReadLocalPackageFileError(_) => todo!(),
-            SoftwareManagerError::ReadLocalOtherFileError(_) => todo!(),
+            SoftwareManagerError::ReadLocalSoftwareFileError(s) => GlobalError::ReadLocalFileError(s),
+            SoftwareManagerError::ReadLocalOtherFileError(s) => GlobalError::ReadLocalFileError(s),
+            SoftwareManagerError::Package(e) => GlobalError::from(e),
         }
     }
 }
 impl From<PackageManagerError> for GlobalError {
     fn from(error: PackageManagerError) -> Self {
         match error {
-            PackageManagerError::PackageInstalled => todo!(),
-            PackageManagerError::PackageNotFound(_) => todo!(),
-            PackageManagerError::PackageLockFailed => todo!(),
-            PackageManagerError::PackageInstallFailed => todo!(),
-            PackageManagerError::PackageUninstallFailed => todo!(),
-            PackageManagerError::ReadLocalPackageFileError(_) => todo!(),
+            PackageManagerError::PackageInstalled => GlobalError::InstallDependencyError("package already installed".to_string()),
+            PackageManagerError::PackageNotFound(s) => GlobalError::PackageNotFound(s),
+            PackageManagerError::PackageLockFailed => GlobalError::SoftwareLockError("package".to_string()),
+            PackageManagerError::PackageInstallFailed(p, e) => GlobalError::PackageInstallFailed(p, Box::new(GlobalError::from(*e))),
+            PackageManagerError::PackageUninstallFailed(p, e) => GlobalError::PackageUninstallFailed(p, Box::new(GlobalError::from(*e))),
+            PackageManagerError::DownloadError(s) => GlobalError::DownloadError(s),
+            PackageManagerError::RequestError(url, e) => GlobalError::RequestError(url, e),
+            PackageManagerError::ClientError(setting, e) => GlobalError::ClientError(setting, e),
+            PackageManagerError::HttpStatusError(url, code, body) => GlobalError::HttpStatusError(url, code, body),
+            PackageManagerError::ServerError(url, code, message) => GlobalError::ServerError(url, code, message),
+            PackageManagerError::MalformedResponse(url, body) => GlobalError::MalformedResponse(url, body),
+            PackageManagerError::InvalidArchiveEncoding(url, body) => GlobalError::InvalidArchiveEncoding(url, body),
+            PackageManagerError::CacheError(path, e) => GlobalError::CacheError(path, e),
+            PackageManagerError::DecompressError(path, e) => GlobalError::DecompressError(path, e),
+            PackageManagerError::AuthenticationError(url, code) => GlobalError::AuthenticationError(url, code),
+            PackageManagerError::WriteLocalFileError(path, e) => GlobalError::WriteLocalFileError(path, e),
+            PackageManagerError::Io(path, e) => GlobalError::Io(path, e),
+            PackageManagerError::ParseLocalFileError(path, e) => GlobalError::ParseLocalFileError(path, e),
+            PackageManagerError::ConfigurationNotFound(s) => GlobalError::ConfigurationNotFound(s),
+            PackageManagerError::ConfigurationUpdateFailed(s) => GlobalError::ConfigurationUpdateFailed(s),
+            PackageManagerError::ArchitectureMismatch(p, package_arch, arch) => GlobalError::ArchitectureMismatch(p, package_arch, arch),
+            PackageManagerError::PackageHeld(held, reason) => GlobalError::PackageHeld(held, reason),
+            PackageManagerError::FileConflict(p, path, owner) => GlobalError::FileConflict(p, path, owner),
+            PackageManagerError::InvalidControl(path, reason) => GlobalError::InvalidControl(path, reason),
+            PackageManagerError::IntegrityError(url, reason) => GlobalError::IntegrityError(url, reason),
+            PackageManagerError::ParseDependencyError(s) => GlobalError::ParseDependencyError(s),
+            PackageManagerError::TransactionPending(operation) => GlobalError::TransactionPending(operation),
+            PackageManagerError::InvalidKey(path) => GlobalError::InvalidKey(path),
+            PackageManagerError::DuplicatePackage(path, p) => GlobalError::DuplicatePackage(path, p),
         }
     }
 }
+impl From<reqwest::Error> for GlobalError {
+    fn from(error: reqwest::Error) -> Self {
+        return GlobalError::from(PackageManagerError::from(error));
+    }
+}
@@ -1,94 +1,70 @@
-use std::{collections::LinkedList, sync::Arc};
+use std::{collections::LinkedList, fmt, sync::Arc};
 
 use crate::{entity::dependency::{Dependency, Package}, manager::package_manager::PackageManagerError};
 
+#[derive(Debug)]
 pub enum SoftwareManagerError {
     // 检测到环形依赖
     CircularDependency(LinkedList<Arc<Dependency>>),
     // 依赖包未找到
+    #[allow(dead_code)]
     SoftwareNotFound(Arc<Package>),
     // 下载时遇到错误
     DownloadError(String),
     // 解析依赖时遇到错误
     ParseDependencyError(String),
     // 读取software本地文件错误
+    #[allow(dead_code)]
     ReadLocalSoftwareFileError(String),
-    // 
+    //
     ReadLocalOtherFileError(String),
     // software加锁失败
     SoftwareLockError(String),
-    // (下面的都是底层出现的错误)重复存在
-    PackageInstalled,
-    // 不存在
-    PackageNotFound(String),
-    // 加锁失败
-    PackageLockFailed,
-    // 安装失败
-    PackageInstallFailed,
-    // 卸载失败
-    PackageUninstallFailed,
-    // 读取package本地文件错误
-    ReadLocalPackageFileError(String),
+    // (下面的都是底层出现的错误)底层包管理器的错误，保留原来的错误
+    Package(PackageManagerError),
 }
 impl From<PackageManagerError> for SoftwareManagerError {
     fn from(err: PackageManagerError) -> Self {
-        match err {
-            PackageManagerError::PackageInstalled => SoftwareManagerError::PackageInstalled,
-            PackageManagerError::PackageNotFound(s) => SoftwareManagerError::PackageNotFound(s),
-            PackageManagerError::PackageLockFailed => SoftwareManagerError::PackageLockFailed,
-            PackageManagerError::PackageInstallFailed => SoftwareManagerError::PackageInstallFailed,
-            PackageManagerError::PackageUninstallFailed => SoftwareManagerError::PackageUninstallFailed,
-            PackageManagerError::ReadLocalPackageFileError(s) => SoftwareManagerError::ReadLocalPackageFileError(s),
-        }
+        return SoftwareManagerError::Package(err);
+    }
+}
+impl From<reqwest::Error> for SoftwareManagerError {
+    fn from(err: reqwest::Error) -> Self {
+        return SoftwareManagerError::Package(PackageManagerError::from(err));
     }
-    
 }
 impl SoftwareManagerError {
-    pub fn to_string(&self) -> String {
+    // 软件源中没有这个包，可以继续查找下一个源
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            SoftwareManagerError::Package(e) => e.is_not_found(),
+            _ => false,
+        }
+    }
+}
+impl fmt::Display for SoftwareManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             SoftwareManagerError::CircularDependency(s) => {
-                let mut str = String::new();
-                for i in s.iter() {
-                    str.push_str(&i.to_string());
-                }
-                str
-            }
-            SoftwareManagerError::SoftwareNotFound(s) => {
-                format!("software not found: {}", s.to_string())
-            }
-            SoftwareManagerError::DownloadError(s) => {
-                format!("download error: {}", s)
-            }
-            SoftwareManagerError::ParseDependencyError(s) => {
-                format!("parse dependency error: {}", s)
-            }
-            SoftwareManagerError::ReadLocalSoftwareFileError(s) => {
-                format!("read local software file error: {}", s)
-            }
-            SoftwareManagerError::ReadLocalOtherFileError(s) => {
-                format!("read local other file error: {}", s)
-            }
-            SoftwareManagerError::PackageInstalled => {
-                format!("package installed")
-            }
-            SoftwareManagerError::PackageNotFound(s) => {
-                format!("package not found: {}", s)
-            }
-            SoftwareManagerError::PackageLockFailed => {
-                format!("package lock failed")
-            }
-            SoftwareManagerError::PackageInstallFailed => {
-                format!("package install failed")
-            }
-            SoftwareManagerError::PackageUninstallFailed => {
-                format!("package uninstall failed")
-            }
-            SoftwareManagerError::ReadLocalPackageFileError(s) => {
-                format!("read local package file error: {}", s)
-            }
-            SoftwareManagerError::SoftwareLockError(s) => {
-                format!("software lock error: {}", s)
+                let cycle : Vec<String> = s.iter().map(|dep| dep.to_string()).collect();
+                write!(f, "circular dependency: {}", cycle.join(" -> "))
             }
+            SoftwareManagerError::SoftwareNotFound(s) => write!(f, "software not found: {}", s.to_string()),
+            SoftwareManagerError::DownloadError(s) => write!(f, "download error: {}", s),
+            SoftwareManagerError::ParseDependencyError(s) => write!(f, "parse dependency error: {}", s),
+            SoftwareManagerError::ReadLocalSoftwareFileError(s) => write!(f, "read local software file error: {}", s),
+            SoftwareManagerError::ReadLocalOtherFileError(s) => write!(f, "read local other file error: {}", s),
+            SoftwareManagerError::SoftwareLockError(s) => write!(f, "software lock error: {}", s),
+            SoftwareManagerError::Package(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for SoftwareManagerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            // 底层错误只是包装，直接使用它的原因
+            SoftwareManagerError::Package(e) => e.source(),
+            _ => None,
         }
     }
-}
\ No newline at end of file
+}
@@ -2,7 +2,7 @@ use num_derive::ToPrimitive;
 use num_derive::FromPrimitive;
 #[repr(i32)]
 #[derive(Debug, FromPrimitive, ToPrimitive, PartialEq, Eq, Clone)]
-#[allow(dead_code, non_camel_case_types)]
+#[allow(dead_code, non_camel_case_types, clippy::upper_case_acronyms)]
 pub enum SystemError {
     /// 操作不被允许 Operation not permitted.
     EPERM = 1,
@@ -1,9 +1,8 @@
-use std::{fs::File, io::Write, sync::Arc};
+// 代码统一写出 return，错误用 match 逐个返回，实体用 to_string 生成标识
+#![allow(clippy::needless_return, clippy::question_mark, clippy::single_match, clippy::inherent_to_string)]
+use std::{env, process};
 
-use entity::{dependency::{Dependency, Package}, software::{Software, SoftwareTemp}, version_wrapper::VersionWrapper};
-use semver::Version;
 use simple_logger::{SimpleLogger};
-use toml::to_string_pretty;
 // 大致流程，每个应用程序有个按照规约的配置文件，读取文件，检查依赖，下载未拥有的依赖，
 // 然后把地址给程序，用户选择依赖升级，保存版本链，并支持回退。
 // 最后，删除不再使用的软件包。
@@ -13,76 +12,28 @@ mod tool;
 mod test;
 mod entity;
 mod scheduler_module;
-use reqwest;
+mod cli_module;
 
-use crate::{manager::{package_manager::package_manager, software_manager::software_manager}, scheduler_module::scheduler, tool::resolve_file::profile_handler};
+use crate::error::global_error::EXIT_SUCCESS;
 
 fn main() {
-    // 开启日志
-    SimpleLogger::new().init().unwrap();
-    //get_software_toml();
-    //test_toml();
-    log::info!("god bless me");
-    let version = VersionWrapper::new(Version::new(1, 0, 1));
-    let mut dependencies = Vec::new();
-    dependencies.push(Arc::new(Dependency::new("testA".to_string(), version.clone())));
-    match software_manager().lock().unwrap().install_package(dependencies) {
-        Ok(_) => {},
+    let args : Vec<String> = env::args().skip(1).collect();
+    let json = cli_module::wants_json(&args);
+    let arguments = match cli_module::parse(args) {
+        Ok(a) => a,
         Err(e) => {
-            log::error!("{}", e.to_string());
-            return;
+            cli_module::print_error(json, "", &e);
+            process::exit(e.exit_code());
         }
     };
-    //get_package_toml();
-    
-}
-
-fn test_toml() {
-    let str = match profile_handler().get_local_file("database/output.toml".to_string()) {
-        Ok(s) => s,
-        Err(e) => {
-            log::error!("{}", e.to_string());
-            return;
-        }
-    };
-    let data = match profile_handler().from_string_to_dependencies(str) {
-        Ok(s) => {
-            for d in s {
-                println!("{:?}", d);
-            }
-        },
+    // 开启日志，日志输出到标准错误，不影响命令的输出
+    SimpleLogger::new().with_level(arguments.log_level()).init().unwrap();
+    let command = arguments.command.name();
+    match cli_module::run(arguments) {
+        Ok(_) => process::exit(EXIT_SUCCESS),
         Err(e) => {
-            log::error!("{}", e.to_string());
-            return;
+            cli_module::print_error(json, command, &e);
+            process::exit(e.exit_code());
         }
-    };
-}
-fn get_software_toml() {
-    // 假设有一个对象需要输出为 TOML 格式
-    let mut my_object : Vec<SoftwareTemp> = Vec::new();
-    let mut vec : Vec<String> = Vec::new();
-    let version = VersionWrapper::new(Version::new(1, 0, 1));
-    vec.push(Dependency::new("testC".to_string(), version.clone()).to_string());
-    my_object.push(SoftwareTemp::new("testB".to_string(), version.clone(), vec.clone(), 1, entity::software::SoftwareStatus::Available));
-    vec.push(Dependency::new("testB".to_string(), version.clone()).to_string());
-    my_object.push(SoftwareTemp::new("testA".to_string(), version.clone(), vec.clone(), 1, entity::software::SoftwareStatus::Available));
-    // 将对象转换为 TOML 格式
-    let toml_value = toml::to_string(&my_object).unwrap();
-    // 将 TOML 格式字符串写入文件
-    let mut file = File::create("database/software_data.toml").unwrap();
-    file.write_all(toml_value.as_bytes()).unwrap();
-}
-
-fn get_package_toml() {
-    // 假设有一个对象需要输出为 TOML 格式
-    let mut my_object : Vec<Package> = Vec::new();
-    let version = VersionWrapper::new(Version::new(1, 0, 1));
-    my_object.push(Package::new("testA".to_string(), version.clone(), "component".to_string(), "origin".to_string(), "label".to_string(), "architecture".to_string(), "download".to_string(), "others".to_string()));
-    my_object.push(Package::new("testB".to_string(), version.clone(), "component".to_string(), "origin".to_string(), "label".to_string(), "architecture".to_string(), "download".to_string(), "others".to_string()));
-    
-    // 将对象转换为 TOML 格式
-    let toml_value = toml::to_string(&my_object).unwrap();
-    // 将 TOML 格式字符串写入文件
-    let mut file = File::create("database/package_data.toml").unwrap();
-    file.write_all(toml_value.as_bytes()).unwrap();
+    }
 }
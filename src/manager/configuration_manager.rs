@@ -0,0 +1,267 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex}};
+use lazy_static::lazy_static;
+use crate::entity::dependency::{Configuration, ConfigurationListTemp, PackageList};
+use crate::tool::resolve_file::profile_handler;
+use super::package_manager::PackageManagerError;
+
+// 配置管理器作为单例
+lazy_static! {
+    static ref CONFIGURATION_MANAGER: Arc<Mutex<ConfigurationManager>> =
+        Arc::new(Mutex::new(ConfigurationManager::new()));
+}
+#[inline(always)]
+#[allow(dead_code)]
+pub fn configuration_manager() -> &'static Arc<Mutex<ConfigurationManager>> {
+    &CONFIGURATION_MANAGER
+}
+// 配置的数据文件
+pub const CONFIGURATION_DATA_PATH: &str = "database/configuration_data.toml";
+// 每个应用保留的版本数，包括 new version 和 last version
+pub const KEEP_GENERATIONS: usize = 2;
+// 管理每个应用程序的配置和它的版本链
+pub struct ConfigurationManager {
+    configurations: Vec<Arc<Configuration>>,
+    archive_hashmap: HashMap<String, Arc<Configuration>>,
+}
+impl ConfigurationManager {
+    fn new() -> ConfigurationManager {
+        let list = match profile_handler().analyse_configuration_file(CONFIGURATION_DATA_PATH.to_string()) {
+            Ok(l) => l,
+            Err(err) => {
+                panic!("{}", err.to_string());
+            }
+        };
+        let mut configurations : Vec<Arc<Configuration>> = Vec::new();
+        let mut map : HashMap<String, Arc<Configuration>> = HashMap::new();
+        for temp in list.configuration_temp {
+            let configuration = Configuration::from_temp(temp);
+            map.insert(configuration.archive.clone(), configuration.clone());
+            configurations.push(configuration);
+        }
+        return ConfigurationManager { configurations, archive_hashmap: map };
+    }
+    // 重新读取数据文件，回滚事务后调用
+    pub fn reload(&mut self) {
+        *self = ConfigurationManager::new();
+    }
+    pub fn get(&self, archive: &str) -> Option<Arc<Configuration>> {
+        return self.archive_hashmap.get(archive).cloned();
+    }
+    pub fn configurations(&self) -> Vec<Arc<Configuration>> {
+        return self.configurations.clone();
+    }
+    // 应用当前使用的依赖列表，应用不存在时为空
+    pub fn current(&self, archive: &str) -> Result<PackageList, PackageManagerError> {
+        let configuration = match self.get(archive) {
+            Some(c) => c,
+            None => return Ok(PackageList::new(Vec::new())),
+        };
+        let guard = match configuration.inner() {
+            Some(g) => g,
+            None => return Err(PackageManagerError::PackageLockFailed),
+        };
+        return Ok(guard.current().unwrap_or_else(|| PackageList::new(Vec::new())));
+    }
+    // 所有应用保留的全部版本
+    pub fn generations(&self) -> Result<Vec<PackageList>, PackageManagerError> {
+        let mut lists = Vec::new();
+        for configuration in self.configurations.iter() {
+            match configuration.inner() {
+                Some(guard) => lists.extend(guard.vec()),
+                None => return Err(PackageManagerError::PackageLockFailed),
+            }
+        }
+        return Ok(lists);
+    }
+    // 每个应用只保留最近的 keep 个版本时剩下的版本，不修改配置
+    pub fn kept_generations(&self, keep: usize) -> Result<Vec<PackageList>, PackageManagerError> {
+        let mut lists = Vec::new();
+        for configuration in self.configurations.iter() {
+            let vec = match configuration.inner() {
+                Some(guard) => guard.vec(),
+                None => return Err(PackageManagerError::PackageLockFailed),
+            };
+            lists.extend(vec.into_iter().rev().take(keep));
+        }
+        return Ok(lists);
+    }
+    // 应用添加新版本 list 并只保留 keep 个版本之后，所有应用保留的版本，不修改配置
+    pub fn generations_after(&self, archive: &str, list: &PackageList, keep: usize) -> Result<Vec<PackageList>, PackageManagerError> {
+        let (_, dropped) = self.simulate_update(archive, keep)?;
+        let mut lists = Vec::new();
+        for configuration in self.configurations.iter() {
+            let mut vec = match configuration.inner() {
+                Some(guard) => guard.vec(),
+                None => return Err(PackageManagerError::PackageLockFailed),
+            };
+            if configuration.archive == archive {
+                vec.drain(0..dropped.len());
+            }
+            lists.extend(vec);
+        }
+        lists.push(list.clone());
+        return Ok(lists);
+    }
+    // 应用的上一个版本，用于回退
+    pub fn previous(&self, archive: &str) -> Result<PackageList, PackageManagerError> {
+        let configuration = match self.get(archive) {
+            Some(c) => c,
+            None => return Err(PackageManagerError::ConfigurationNotFound(archive.to_string())),
+        };
+        let guard = match configuration.inner() {
+            Some(g) => g,
+            None => return Err(PackageManagerError::PackageLockFailed),
+        };
+        let vec = guard.vec();
+        if vec.len() < 2 {
+            return Err(PackageManagerError::ConfigurationUpdateFailed(format!("{} has no previous generation", archive)));
+        }
+        return Ok(vec[vec.len() - 2].clone());
+    }
+    // 应用当前的版本号
+    pub fn generation(&self, archive: &str) -> Result<usize, PackageManagerError> {
+        let configuration = match self.get(archive) {
+            Some(c) => c,
+            None => return Err(PackageManagerError::ConfigurationNotFound(archive.to_string())),
+        };
+        let guard = match configuration.inner() {
+            Some(g) => g,
+            None => return Err(PackageManagerError::PackageLockFailed),
+        };
+        return Ok(guard.age());
+    }
+    // 给应用添加新的版本，应用不存在时创建，返回因为超出保留数量而被丢弃的版本
+    pub fn update(&mut self, archive: &str, list: PackageList) -> Result<Vec<PackageList>, PackageManagerError> {
+        let configuration = match self.get(archive) {
+            Some(c) => c,
+            None => {
+                let configuration = Configuration::new(list, archive.to_string(), 1);
+                self.archive_hashmap.insert(archive.to_string(), configuration.clone());
+                self.configurations.push(configuration);
+                return Ok(Vec::new());
+            }
+        };
+        let mut guard = match configuration.inner() {
+            Some(g) => g,
+            None => return Err(PackageManagerError::PackageLockFailed),
+        };
+        guard.update(list);
+        return Ok(guard.prune(KEEP_GENERATIONS));
+    }
+    // 模拟添加新版本后只保留 keep 个版本，返回新的版本号和会被丢弃的版本，不修改配置
+    pub fn simulate_update(&self, archive: &str, keep: usize) -> Result<(usize, Vec<PackageList>), PackageManagerError> {
+        let configuration = match self.get(archive) {
+            Some(c) => c,
+            None => return Ok((1, Vec::new())),
+        };
+        let guard = match configuration.inner() {
+            Some(g) => g,
+            None => return Err(PackageManagerError::PackageLockFailed),
+        };
+        let mut vec = guard.vec();
+        // 加上新版本之后超出的部分
+        let count = (vec.len() + 1).saturating_sub(keep).min(vec.len());
+        return Ok((guard.age() + 1, vec.drain(0..count).collect()));
+    }
+    // 只保留应用最近的 keep 个版本，返回被丢弃的版本
+    pub fn prune(&mut self, archive: &str, keep: usize) -> Result<Vec<PackageList>, PackageManagerError> {
+        let configuration = match self.get(archive) {
+            Some(c) => c,
+            None => return Err(PackageManagerError::ConfigurationNotFound(archive.to_string())),
+        };
+        let mut guard = match configuration.inner() {
+            Some(g) => g,
+            None => return Err(PackageManagerError::PackageLockFailed),
+        };
+        return Ok(guard.prune(keep));
+    }
+    // 从所有应用保留的版本中去掉这些包，不产生新版本
+    pub fn drop_packages(&mut self, names: &HashSet<String>) -> Result<(), PackageManagerError> {
+        for configuration in self.configurations.iter() {
+            let mut guard = match configuration.inner() {
+                Some(g) => g,
+                None => return Err(PackageManagerError::PackageLockFailed),
+            };
+            for list in guard.vec.iter_mut() {
+                list.dependencies.retain(|package| !names.contains(&package.to_string()));
+            }
+        }
+        return Ok(());
+    }
+    // 把配置写回数据文件
+    pub fn save(&self) -> Result<(), PackageManagerError> {
+        let mut list = ConfigurationListTemp::default();
+        for configuration in self.configurations.iter() {
+            match configuration.to_temp() {
+                Some(temp) => list.configuration_temp.push(temp),
+                None => return Err(PackageManagerError::PackageLockFailed),
+            }
+        }
+        let content = match toml::to_string(&list) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::WriteLocalFileError(CONFIGURATION_DATA_PATH.to_string(), e)),
+        };
+        match profile_handler().write_local_file(CONFIGURATION_DATA_PATH, &content) {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::entity::dependency::{Configuration, Dependency, Package, PackageList};
+    use super::ConfigurationManager;
+
+    fn list(names: &[&str]) -> PackageList {
+        let packages = names.iter().map(|name| {
+            let dependency = Dependency::from_string(name).unwrap();
+            return Package::new(dependency.archive, dependency.version_wrapper, "main".to_string(), "local".to_string(), "local".to_string(), "x86_64".to_string(), String::new(), String::new());
+        }).collect();
+        return PackageList::new(packages);
+    }
+    // one 有三个版本，two 有一个版本
+    fn manager() -> ConfigurationManager {
+        let one = Configuration::new(list(&["x-1.0.0"]), "one".to_string(), 1);
+        one.inner().unwrap().update(list(&["x-2.0.0"]));
+        one.inner().unwrap().update(list(&["x-3.0.0", "z-1.0.0"]));
+        let two = Configuration::new(list(&["y-1.0.0", "z-1.0.0"]), "two".to_string(), 1);
+        let configurations = vec![one, two];
+        let archive_hashmap: HashMap<String, _> = configurations.iter().map(|configuration| (configuration.archive.clone(), configuration.clone())).collect();
+        return ConfigurationManager { configurations, archive_hashmap };
+    }
+    fn names(lists: &[PackageList]) -> Vec<Vec<String>> {
+        return lists.iter().map(|list| list.dependencies.iter().map(|package| package.to_string()).collect()).collect();
+    }
+    #[test]
+    fn every_kept_generation_is_a_root() {
+        let manager = manager();
+        assert_eq!(names(&manager.generations().unwrap()), vec![
+            vec!["x-1.0.0"], vec!["x-2.0.0"], vec!["x-3.0.0", "z-1.0.0"], vec!["y-1.0.0", "z-1.0.0"],
+        ]);
+    }
+    #[test]
+    fn keep_drops_older_generations_of_every_app() {
+        let manager = manager();
+        assert_eq!(names(&manager.kept_generations(1).unwrap()), vec![vec!["x-3.0.0", "z-1.0.0"], vec!["y-1.0.0", "z-1.0.0"]]);
+        assert_eq!(names(&manager.kept_generations(2).unwrap()), vec![
+            vec!["x-3.0.0", "z-1.0.0"], vec!["x-2.0.0"], vec!["y-1.0.0", "z-1.0.0"],
+        ]);
+        assert_eq!(manager.kept_generations(5).unwrap().len(), 4);
+        // 只是计算，不修改配置
+        assert_eq!(manager.generations().unwrap().len(), 4);
+    }
+    #[test]
+    fn a_new_generation_pushes_out_the_oldest() {
+        let manager = manager();
+        let (generation, dropped) = manager.simulate_update("one", 2).unwrap();
+        assert_eq!(generation, 4);
+        assert_eq!(names(&dropped), vec![vec!["x-1.0.0"], vec!["x-2.0.0"]]);
+        assert_eq!(names(&manager.generations_after("one", &list(&["x-4.0.0"]), 2).unwrap()), vec![
+            vec!["x-3.0.0", "z-1.0.0"], vec!["y-1.0.0", "z-1.0.0"], vec!["x-4.0.0"],
+        ]);
+        // 新应用从第一个版本开始
+        assert_eq!(manager.simulate_update("three", 2).unwrap().0, 1);
+    }
+}
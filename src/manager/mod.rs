@@ -1,2 +1,3 @@
+pub mod configuration_manager;
 pub mod package_manager;
-pub mod software_manager;
\ No newline at end of file
+pub mod software_manager;
@@ -1,53 +1,135 @@
-use std::{collections::HashMap, fmt, hash::{Hash, Hasher}, ops::Deref, str::FromStr, sync::{Arc, Mutex, MutexGuard}};
+use std::{collections::{HashMap, HashSet}, fmt, fs, io::ErrorKind, sync::{Arc, Mutex}};
 use lazy_static::lazy_static;
-use semver::Version;
-use serde_derive::{Deserialize, Serialize};
-use crate::{entity::dependency::{self, Configuration, Dependency, Package, PackageList}, tool::{network_module::download_unit, resolve_file::profile_handler}};
-use crate::entity::software::{Software};
+use crate::{entity::dependency::{Dependency, Package, PackageList, PackageListTemp}, tool::{arch_unit::{architecture, is_compatible}, cache_unit::{cache_unit, sha256_hex}, hold_unit::hold_unit, manifest_unit::manifest_unit, network_module::{decompress_unit, download_unit}, resolve_file::profile_handler}};
 use crate::entity::version_wrapper::VersionWrapper;
 
 // 
 
 
+#[derive(Debug)]
 pub enum PackageManagerError {
     // 重复存在
+    #[allow(dead_code)]
     PackageInstalled,
     // 不存在
     PackageNotFound(String),
     // 加锁失败
     PackageLockFailed,
-    // 安装失败
-    PackageInstallFailed,
-    // 卸载失败
-    PackageUninstallFailed,
-    // 读取本地文件错误
-    ReadLocalPackageFileError(String),
+    // 安装失败(包，原因)
+    PackageInstallFailed(String, Box<PackageManagerError>),
+    // 卸载失败(包，原因)
+    PackageUninstallFailed(String, Box<PackageManagerError>),
+    // 请求服务器失败
+    DownloadError(String),
+    // 请求没有得到响应(地址，原因)
+    RequestError(String, reqwest::Error),
+    // 按网络设置创建 http 客户端失败(出错的设置，原因)
+    ClientError(String, reqwest::Error),
+    // 服务器返回了非成功的状态码(地址，状态码，返回内容摘要)
+    HttpStatusError(String, u16, String),
+    // 服务器返回了错误信息(地址，状态码，错误信息)
+    ServerError(String, u16, String),
+    // 返回内容不符合接口格式(地址，返回内容摘要)
+    MalformedResponse(String, String),
+    // 压缩包的base64编码无法解析(地址，返回内容摘要)
+    InvalidArchiveEncoding(String, String),
+    // 读写本地缓存失败(路径，原因)
+    CacheError(String, std::io::Error),
+    // 解压失败(安装目录，原因)
+    DecompressError(String, std::io::Error),
+    // 私有软件源认证失败(地址，状态码)
+    AuthenticationError(String, u16),
+    // 生成本地文件的内容失败(路径，原因)
+    WriteLocalFileError(String, toml::ser::Error),
+    // 读写本地文件失败(路径，原因)
+    Io(String, std::io::Error),
+    // 本地文件的格式错误(路径，原因)
+    ParseLocalFileError(String, toml::de::Error),
+    // 配置不存在
+    ConfigurationNotFound(String),
+    // 配置更新失败
+    ConfigurationUpdateFailed(String),
+    // 没有当前架构的软件包(包，包的架构，当前架构)
+    ArchitectureMismatch(String, String, String),
+    // 要改变锁定的软件包(锁定的包，需要的版本和原因)
+    PackageHeld(String, String),
+    // 和已安装的包的文件冲突(包，冲突的路径，拥有这个路径的包)
+    FileConflict(String, String, String),
+    // 打包时控制文件或暂存目录不合法(路径，原因)
+    InvalidControl(String, String),
+    // 校验和或签名和软件源给出的不符(地址，原因)
+    IntegrityError(String, String),
+    // 软件包名不是 archive-version 格式
+    ParseDependencyError(String),
+    // 上一个事务还没有完成(事务的操作)
+    TransactionPending(String),
+    // 签名密钥文件不是 hex 编码的 32 字节(路径)
+    InvalidKey(String),
+    // 目录中有两个压缩包是同一个软件包(压缩包的路径，软件包)
+    DuplicatePackage(String, String),
 }
 impl PackageManagerError {
-    pub fn to_string(&self) -> String {
+    // 软件源中没有这个包，可以继续查找下一个源
+    pub fn is_not_found(&self) -> bool {
+        return matches!(self,
+            PackageManagerError::PackageNotFound(_)
+            | PackageManagerError::HttpStatusError(_, 404, _)
+            | PackageManagerError::ServerError(_, 404, _));
+    }
+}
+impl fmt::Display for PackageManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            PackageManagerError::PackageInstalled => {
-                return "Package already installed".to_string();
-            },
-            PackageManagerError::PackageNotFound(s) => {
-                return format!("Package {} not found", s);
-            },
-            PackageManagerError::PackageLockFailed => {
-                return "Package lock failed".to_string();
-            },
-            PackageManagerError::PackageInstallFailed => {
-                return "Package install failed".to_string();
-            }
-            PackageManagerError::PackageUninstallFailed => {
-                return "Package uninstall failed".to_string();
-            }
-            PackageManagerError::ReadLocalPackageFileError(s) => {
-                return format!("Read local package file error {}", s);
-            }
-
+            PackageManagerError::PackageInstalled => write!(f, "Package already installed"),
+            PackageManagerError::PackageNotFound(s) => write!(f, "Package {} not found", s),
+            PackageManagerError::PackageLockFailed => write!(f, "Package lock failed"),
+            PackageManagerError::PackageInstallFailed(p, e) => write!(f, "Package {} install failed: {}", p, e),
+            PackageManagerError::PackageUninstallFailed(p, e) => write!(f, "Package {} uninstall failed: {}", p, e),
+            PackageManagerError::DownloadError(s) => write!(f, "Download error {}", s),
+            PackageManagerError::RequestError(url, e) => write!(f, "Request {} failed: {}", url, e),
+            PackageManagerError::ClientError(setting, e) => write!(f, "Invalid network setting {}: {}", setting, e),
+            PackageManagerError::HttpStatusError(url, code, body) => write!(f, "Http status {} from {}: {}", code, url, body),
+            PackageManagerError::ServerError(url, code, message) => write!(f, "Server error {} from {}: {}", code, url, message),
+            PackageManagerError::MalformedResponse(url, body) => write!(f, "Malformed response from {}: {}", url, body),
+            PackageManagerError::InvalidArchiveEncoding(url, body) => write!(f, "Invalid base64 archive from {}: {}", url, body),
+            PackageManagerError::CacheError(path, e) => write!(f, "Cache error {}: {}", path, e),
+            PackageManagerError::DecompressError(path, e) => write!(f, "Decompress error {}: {}", path, e),
+            PackageManagerError::AuthenticationError(url, code) => write!(f, "Authentication failed ({}) for {}", code, url),
+            PackageManagerError::WriteLocalFileError(path, e) => write!(f, "Write local file error {}: {}", path, e),
+            PackageManagerError::Io(path, e) => write!(f, "{}: {}", path, e),
+            PackageManagerError::ParseLocalFileError(path, e) => write!(f, "{} is corrupted: {}", path, e),
+            PackageManagerError::ConfigurationNotFound(s) => write!(f, "Configuration {} not found", s),
+            PackageManagerError::ConfigurationUpdateFailed(s) => write!(f, "Configuration update failed {}", s),
+            PackageManagerError::ArchitectureMismatch(p, package_arch, arch) => write!(f, "Package {} is built for {}, not {}", p, package_arch, arch),
+            PackageManagerError::PackageHeld(held, reason) => write!(f, "Package {} is held, but {}", held, reason),
+            PackageManagerError::FileConflict(p, path, owner) => write!(f, "Package {} would overwrite {}, which belongs to {}", p, path, owner),
+            PackageManagerError::InvalidControl(path, reason) => write!(f, "Invalid package {}: {}", path, reason),
+            PackageManagerError::IntegrityError(url, reason) => write!(f, "Integrity check of {} failed: {}", url, reason),
+            PackageManagerError::ParseDependencyError(s) => write!(f, "Invalid package name {}", s),
+            PackageManagerError::TransactionPending(operation) => write!(f, "Transaction \"{}\" is not finished", operation),
+            PackageManagerError::InvalidKey(path) => write!(f, "{}: the key must be 32 hex-encoded bytes", path),
+            PackageManagerError::DuplicatePackage(path, p) => write!(f, "{}: {} is given twice", path, p),
         }
     }
 }
+impl std::error::Error for PackageManagerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PackageManagerError::PackageInstallFailed(_, e) | PackageManagerError::PackageUninstallFailed(_, e) => Some(e.as_ref()),
+            PackageManagerError::RequestError(_, e) | PackageManagerError::ClientError(_, e) => Some(e),
+            PackageManagerError::DecompressError(_, e) | PackageManagerError::Io(_, e) | PackageManagerError::CacheError(_, e) => Some(e),
+            PackageManagerError::WriteLocalFileError(_, e) => Some(e),
+            PackageManagerError::ParseLocalFileError(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<reqwest::Error> for PackageManagerError {
+    fn from(error: reqwest::Error) -> Self {
+        let url = error.url().map(|u| u.to_string()).unwrap_or_default();
+        return PackageManagerError::RequestError(url, error);
+    }
+}
 // 底层包管理器作为单例
 lazy_static! {
     static ref PACKAGE_MANAGER : Arc<Mutex<PackageManager>> = Arc::new(Mutex::new(PackageManager::new()));
@@ -57,6 +139,40 @@ lazy_static! {
 pub fn package_manager() -> &'static Arc<Mutex<PackageManager>> {
     &PACKAGE_MANAGER
 }
+// 已安装软件包的数据文件
+pub const PACKAGE_DATA_PATH: &str = "database/package_data.toml";
+// 软件源的软件包列表，由 update 命令刷新
+const INDEX_DATA_PATH: &str = "database/index_data.toml";
+// 软件包的安装目录
+pub const STORE_PATH: &str = "database/store";
+pub fn store_path(archive : &str, version : &VersionWrapper) -> String {
+    return format!("{}/{}-{}", STORE_PATH, archive, version.to_string());
+}
+// 检查软件包是否是当前架构的
+fn check_architecture(package : Arc<Package>) -> Result<Arc<Package>, PackageManagerError> {
+    if is_compatible(&package.architecture) {
+        return Ok(package);
+    }
+    return Err(PackageManagerError::ArchitectureMismatch(package.to_string(), package.architecture.clone(), architecture()));
+}
+// 软件包列表中某个软件当前架构的最新版本
+fn latest_compatible(index : &[Arc<Package>], archive : &str) -> Result<Arc<Package>, PackageManagerError> {
+    let candidates : Vec<&Arc<Package>> = index.iter().filter(|package| package.archive == archive).collect();
+    match candidates.iter()
+        .filter(|package| is_compatible(&package.architecture))
+        .max_by(|a, b| a.version_wrapper.version.cmp(&b.version_wrapper.version)) {
+        Some(p) => return Ok((*p).clone()),
+        None => {},
+    }
+    if candidates.is_empty() {
+        return Err(PackageManagerError::PackageNotFound(format!("{} (run update to refresh the package list)", archive)));
+    }
+    // 其他架构有这个软件
+    let mut architectures : Vec<String> = candidates.iter().map(|package| package.architecture.clone()).collect();
+    architectures.sort();
+    architectures.dedup();
+    return Err(PackageManagerError::ArchitectureMismatch(archive.to_string(), architectures.join(", "), architecture()));
+}
 // 内部类
 pub enum VersionMode {
     // 最新版本
@@ -76,13 +192,13 @@ pub struct PackageManager {
     packages : Vec<Arc<Package>>,
     // 用map记录
     package_hashmap : HashMap<String, Arc<Package>>,
-    
+    // 软件源中可用的软件包
+    index : Vec<Arc<Package>>,
 }
 impl PackageManager {
     fn new() -> PackageManager {
         // 初始化时检查数据文件，没有则创建，有则根据文件恢复数据
-        let path = "database/package_data.toml";
-        let packages = match profile_handler().analyse_package_file(path.to_string()) {
+        let packages = match profile_handler().analyse_package_file(PACKAGE_DATA_PATH.to_string()) {
             Ok(s) => s,
             Err(err) => {
                 panic!("{}",err.to_string());
@@ -93,40 +209,220 @@ impl PackageManager {
             let str = format!("{}-{}", package.archive, package.version_wrapper.to_string());
             map.insert(str, package.clone());
         }
-        return PackageManager{ packages : packages ,  package_hashmap : map};
+        // 还没有执行过 update 时列表为空
+        let index = match fs::metadata(INDEX_DATA_PATH) {
+            Ok(_) => match profile_handler().analyse_package_file(INDEX_DATA_PATH.to_string()) {
+                Ok(i) => i,
+                Err(err) => {
+                    panic!("{}",err.to_string());
+                }
+            },
+            Err(_) => Vec::new(),
+        };
+        return PackageManager{ packages ,  package_hashmap : map, index };
+    }
+    // 重新读取数据文件，回滚事务后调用
+    pub fn reload(&mut self) {
+        *self = PackageManager::new();
+    }
+    pub fn packages(&self) -> Vec<Arc<Package>> {
+        return self.packages.clone();
+    }
+    // 按 archive-version 查找已安装的软件包
+    pub fn get(&self, name : &str) -> Option<Arc<Package>> {
+        return self.package_hashmap.get(name).cloned();
+    }
+    // 数据文件中重复的记录只保留第一条，返回被去掉的包
+    pub fn remove_duplicates(&mut self) -> Vec<String> {
+        let mut seen : HashSet<String> = HashSet::new();
+        let mut removed = Vec::new();
+        self.packages.retain(|package| {
+            if seen.insert(package.to_string()) {
+                return true;
+            }
+            removed.push(package.to_string());
+            return false;
+        });
+        self.package_hashmap = self.packages.iter().map(|package| (package.to_string(), package.clone())).collect();
+        return removed;
+    }
+    // 补上丢失的已安装软件包记录
+    pub fn restore(&mut self, package : Arc<Package>) {
+        if self.package_hashmap.insert(package.to_string(), package.clone()).is_none() {
+            self.packages.push(package);
+        }
+    }
+    // 安装目录下的所有条目名
+    pub fn store_entries(&self) -> Result<Vec<String>, PackageManagerError> {
+        let entries = match fs::read_dir(STORE_PATH) {
+            Ok(e) => e,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(PackageManagerError::Io(STORE_PATH.to_string(), e)),
+        };
+        let mut names : Vec<String> = entries.flatten().map(|entry| entry.file_name().to_string_lossy().to_string()).collect();
+        names.sort();
+        return Ok(names);
+    }
+    pub fn index(&self) -> Vec<Arc<Package>> {
+        return self.index.clone();
+    }
+    // 从软件源刷新软件包列表，返回列表中软件包的数量
+    pub fn update_index(&mut self) -> Result<usize, PackageManagerError> {
+        let packages = download_unit()?.get_index()?;
+        let content = match toml::to_string(&PackageListTemp { packages : packages.clone() }) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::WriteLocalFileError(INDEX_DATA_PATH.to_string(), e)),
+        };
+        profile_handler().write_local_file(INDEX_DATA_PATH, &content)?;
+        self.index = packages.into_iter().map(Arc::new).collect();
+        return Ok(self.index.len());
+    }
+    // 软件包列表中某个软件当前架构的最新版本
+    pub fn latest(&self, archive : &str) -> Option<Arc<Package>> {
+        return latest_compatible(&self.index, archive).ok();
+    }
+    // 按名字查找软件包列表，忽略其他架构的包
+    pub fn search(&self, keyword : &str) -> Vec<Arc<Package>> {
+        return self.index.iter()
+            .filter(|package| package.archive.contains(keyword) && is_compatible(&package.architecture))
+            .cloned()
+            .collect();
+    }
+    // 把已安装的软件包写回数据文件
+    pub fn save(&self) -> Result<(), PackageManagerError> {
+        let list = PackageListTemp { packages : self.packages.iter().map(|package| package.as_ref().clone()).collect() };
+        let content = match toml::to_string(&list) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::WriteLocalFileError(PACKAGE_DATA_PATH.to_string(), e)),
+        };
+        match profile_handler().write_local_file(PACKAGE_DATA_PATH, &content) {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(e),
+        }
     }
     fn get_package(&self, dependency: Arc<Dependency>) -> Result<Arc<Package>, PackageManagerError> {
         // 网络获取详细信息
-        return download_unit().get_package_information(dependency);
+        return check_architecture(download_unit()?.get_package_information(dependency)?);
+    }
+    // 软件包的压缩包，先查本地缓存，没有再调用下载器下载
+    fn fetch(&self, package: &Arc<Package>) -> Result<Vec<u8>,PackageManagerError> {
+        // 缓存只返回和软件源给出的sha256一致的压缩包
+        if let Some(d) = cache_unit().lookup(package) {
+            return Ok(d);
+        }
+        let d = match download_unit()?.download_software(package.clone()) {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(PackageManagerError::PackageInstallFailed(package.to_string(), Box::new(e)));
+            }
+        };
+        // 软件源给出了sha256时，下载的压缩包要和它一致
+        if !package.sha256.is_empty() && sha256_hex(&d) != package.sha256 {
+            let reason = format!("sha256 is {}, the source says {}", sha256_hex(&d), package.sha256);
+            return Err(PackageManagerError::PackageInstallFailed(package.to_string(), Box::new(PackageManagerError::IntegrityError(package.download(), reason))));
+        }
+        match cache_unit().store(package, &d) {
+            Ok(_) => {},
+            // 缓存失败不影响安装
+            Err(e) => log::warn!("{}", e),
+        }
+        return Ok(d);
     }
-    pub fn install_package(&mut self, dependency: Arc<Dependency>) -> Result<(),PackageManagerError> {
-        // 安装地址
-        let path = "/database";
+    // profile 为安装后和这个包在同一个 profile 中的包，只有它们的文件会和这个包冲突
+    pub fn install_package(&mut self, dependency: Arc<Dependency>, profile : &[String]) -> Result<(),PackageManagerError> {
         let package = match self.get_package(dependency.clone()) {
             Ok(p) => p,
             Err(e) => {
                 return Err(e);
             }
         };
-        // 调用下载器下载包并解压
-        match download_unit().download_software(package.clone(), path) {
+        let data = match decompress_unit().decode(self.fetch(&package)?) {
+            Ok(d) => d,
+            Err(e) => return Err(PackageManagerError::PackageInstallFailed(package.to_string(), Box::new(PackageManagerError::DecompressError(package.download(), e)))),
+        };
+        // 解压前检查文件是否和同一个 profile 中其他软件的文件冲突，同一个软件的不同版本装在不同目录，不算冲突
+        let path = store_path(&package.archive, &package.version_wrapper);
+        let files = match manifest_unit().read_archive(&data) {
+            Ok(f) => f,
+            Err(e) => return Err(PackageManagerError::PackageInstallFailed(package.to_string(), Box::new(PackageManagerError::DecompressError(path, e)))),
+        };
+        if let Err(e) = manifest_unit().check_archive(&package.to_string(), &data, &files) {
+            return Err(PackageManagerError::PackageInstallFailed(package.to_string(), Box::new(e)));
+        }
+        let others : Vec<String> = profile.iter()
+            .filter(|name| self.package_hashmap.get(*name).is_some_and(|installed| installed.archive != package.archive))
+            .cloned()
+            .collect();
+        if let Some((file, owner)) = manifest_unit().conflict(&files, &others) {
+            return Err(PackageManagerError::FileConflict(package.to_string(), file, owner));
+        }
+        // 解压到安装地址
+        match decompress_unit().install(data, &path) {
             Ok(_) => {
-                self.packages.push(package.clone());
+                manifest_unit().record(&package.to_string(), files)?;
+                if !self.package_hashmap.contains_key(&package.to_string()) {
+                    self.packages.push(package.clone());
+                }
                 self.package_hashmap.insert(package.to_string(), package);
-                // doto 添加到数据文件中
             },
             Err(e) => {
-                return Err(PackageManagerError::PackageInstallFailed);
+                return Err(PackageManagerError::PackageInstallFailed(package.to_string(), Box::new(e)));
             }
         }
         // todo 执行安装脚本
         
         return Ok(());
     }
+    // 重新解压已安装的包，修复被改动或删除的文件，删掉多出来的文件，并重新记录文件清单
+    pub fn reinstall_package(&self, name : &str) -> Result<(),PackageManagerError> {
+        let package = match self.get(name) {
+            Some(p) => p,
+            None => return Err(PackageManagerError::PackageNotFound(name.to_string())),
+        };
+        let path = store_path(&package.archive, &package.version_wrapper);
+        let data = match decompress_unit().decode(self.fetch(&package)?) {
+            Ok(d) => d,
+            Err(e) => return Err(PackageManagerError::PackageInstallFailed(name.to_string(), Box::new(PackageManagerError::DecompressError(package.download(), e)))),
+        };
+        let files = match manifest_unit().read_archive(&data) {
+            Ok(f) => f,
+            Err(e) => return Err(PackageManagerError::PackageInstallFailed(name.to_string(), Box::new(PackageManagerError::DecompressError(path, e)))),
+        };
+        if let Err(e) = manifest_unit().check_archive(name, &data, &files) {
+            return Err(PackageManagerError::PackageInstallFailed(name.to_string(), Box::new(e)));
+        }
+        match fs::remove_dir_all(&path) {
+            Ok(_) => {},
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => return Err(PackageManagerError::PackageInstallFailed(name.to_string(), Box::new(PackageManagerError::Io(path, e)))),
+        }
+        if let Err(e) = decompress_unit().install(data, &path) {
+            return Err(PackageManagerError::PackageInstallFailed(name.to_string(), Box::new(e)));
+        }
+        return manifest_unit().record(name, files);
+    }
+    // 删除所有缓存的压缩包
+    pub fn clean_cache(&self) -> Result<usize, PackageManagerError> {
+        return cache_unit().clean();
+    }
+    // 删除仓库中不再提供的压缩包
+    pub fn autoclean_cache(&self) -> Result<usize, PackageManagerError> {
+        return cache_unit().autoclean();
+    }
 
-    pub fn uninstall_package(& self, archive : String, version : VersionWrapper) -> Result<(),PackageManagerError> {
-        // todo 卸载软件，执行卸载脚本，删除文件
-        // todo 修改数据文件
+    pub fn uninstall_package(&mut self, archive : String, version : VersionWrapper) -> Result<(),PackageManagerError> {
+        // todo 执行卸载脚本
+        // 删除安装目录，目录已经不存在时视为卸载成功
+        let path = store_path(&archive, &version);
+        let name = format!("{}-{}", archive, version.to_string());
+        match fs::remove_dir_all(&path) {
+            Ok(_) => {},
+            Err(e) if e.kind() == ErrorKind::NotFound => log::warn!("{} already removed", path),
+            Err(e) => return Err(PackageManagerError::PackageUninstallFailed(name, Box::new(PackageManagerError::Io(path, e)))),
+        }
+        manifest_unit().remove(&name)?;
+        self.packages.retain(|package| package.to_string() != name);
+        self.package_hashmap.remove(&name);
         return Ok(());
     }
     // 加入新的配置文件。一般是在第一次解析时添加
@@ -182,18 +478,92 @@ impl ConfigurationUpdateUnit {
     pub fn new() -> ConfigurationUpdateUnit {
         ConfigurationUpdateUnit
     }
-    pub fn get_new_configuration(&self, configuration : Arc<Configuration>, mode : ConfigurationUpdateMode) -> Result<PackageList,PackageManagerError>{
-        let inner_guard = configuration.inner();
-        if inner_guard.is_none() {
-            return Err(PackageManagerError::PackageLockFailed);
+    // 根据更新模式生成新的依赖列表，index 是软件源的软件包列表，用于查找最新版本
+    // 部分更新中不在当前列表里的软件会被加入新列表
+    // 全部更新时跳过锁定的软件，部分更新要改变锁定的软件时返回错误
+    pub fn get_new_configuration(&self, current : PackageList, mode : ConfigurationUpdateMode, index : &[Arc<Package>]) -> Result<PackageList,PackageManagerError>{
+        let holds = match hold_unit() {
+            Ok(unit) => unit,
+            Err(e) => return Err(e),
+        };
+        let latest = |archive : &str| -> Result<Arc<Package>, PackageManagerError> {
+            return latest_compatible(index, archive);
+        };
+        let resolve = |archive : &str, version_mode : &VersionMode| -> Result<Arc<Package>, PackageManagerError> {
+            let package = match version_mode {
+                VersionMode::Latest => latest(archive)?,
+                VersionMode::Specific(version) => {
+                    let dependency = Arc::new(Dependency::new(archive.to_string(), version.clone()));
+                    check_architecture(download_unit()?.get_package_information(dependency)?)?
+                }
+            };
+            holds.check(archive, &package.version_wrapper, &format!("{} was requested", package.to_string()))?;
+            return Ok(package);
+        };
+        let mut packages : Vec<Arc<Package>> = Vec::new();
+        match mode {
+            ConfigurationUpdateMode::AllUpdateLatest => {
+                for package in current.dependencies.iter() {
+                    if holds.held(&package.archive).is_some() {
+                        packages.push(package.clone());
+                        continue;
+                    }
+                    packages.push(latest(&package.archive)?);
+                }
+            }
+            ConfigurationUpdateMode::PartialUpdate(map) => {
+                for package in current.dependencies.iter() {
+                    match map.get(&package.archive) {
+                        Some(version_mode) => packages.push(resolve(&package.archive, version_mode)?),
+                        None => packages.push(package.clone()),
+                    }
+                }
+                // 按名字排序，保证新加入的软件顺序稳定
+                let mut archives : Vec<&String> = map.keys()
+                    .filter(|archive| !current.dependencies.iter().any(|package| &package.archive == *archive))
+                    .collect();
+                archives.sort();
+                for archive in archives {
+                    packages.push(resolve(archive, &map[archive])?);
+                }
+            }
         }
-        let guard = inner_guard.unwrap();
-        // todo 上网更新
-        let mut list = PackageList::new(Vec::new());
-        if let tmp = guard.vec().last().is_some() {
-            list = guard.vec().last().unwrap().clone();
+        return Ok(PackageList::with_dependencies(packages));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::entity::dependency::{Dependency, Package};
+    use crate::tool::arch_unit::host_architecture;
+    use super::{latest_compatible, PackageManagerError};
+
+    fn package(name: &str, architecture: &str) -> Arc<Package> {
+        let dependency = Dependency::from_string(name).unwrap();
+        return Arc::new(Package::new(dependency.archive, dependency.version_wrapper, String::new(), String::new(), String::new(), architecture.to_string(), String::new(), String::new()));
+    }
+    #[test]
+    fn the_latest_version_for_this_architecture_is_chosen() {
+        let index = vec![
+            package("a-1.0.0", host_architecture()),
+            package("a-2.0.0", "not-this-machine"),
+            package("b-1.0.0", "all"),
+            package("b-1.1.0", host_architecture()),
+        ];
+        assert_eq!(latest_compatible(&index, "a").unwrap().to_string(), "a-1.0.0");
+        assert_eq!(latest_compatible(&index, "b").unwrap().to_string(), "b-1.1.0");
+    }
+    #[test]
+    fn packages_only_built_for_other_architectures_are_reported() {
+        let index = vec![package("a-1.0.0", "not-this-machine"), package("a-1.1.0", "other-machine"), package("a-1.2.0", "other-machine")];
+        match latest_compatible(&index, "a") {
+            Err(PackageManagerError::ArchitectureMismatch(archive, architectures, _)) => {
+                assert_eq!(archive, "a");
+                assert_eq!(architectures, "not-this-machine, other-machine");
+            }
+            other => panic!("unexpected result {:?}", other),
         }
-        // 表示更新成功
-        return Ok(list);
+        assert!(matches!(latest_compatible(&index, "b"), Err(PackageManagerError::PackageNotFound(_))));
     }
-}
\ No newline at end of file
+}
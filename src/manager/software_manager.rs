@@ -1,21 +1,17 @@
 
-use base64::decode;
 use lazy_static::lazy_static;
-use serde::de;
-use reqwest::Error;
-use simple_logger::SimpleLogger;
-use tokio::runtime::Runtime;
 use std::{
-    collections::{HashMap, HashSet, LinkedList}, fs::File, io::Write, ptr::null, result, sync::{Arc, Mutex, MutexGuard}
+    collections::{HashMap, HashSet, LinkedList, VecDeque}, sync::{Arc, Mutex}
 };
-use serde_json::{Value, json};
 
-use crate::{entity::{dependency::{self, Dependency, Package, PackageList}, software}, error::software_error::SoftwareManagerError, tool::{network_module::download_unit, resolve_file::profile_handler}};
-use crate::entity::software::{Software};
-use crate::entity::version_wrapper::VersionWrapper;
+use crate::{entity::dependency::{Dependency, PackageList}, error::software_error::SoftwareManagerError, tool::{hold_unit::hold_unit, journal_unit::journal_unit, network_module::download_unit, resolve_file::profile_handler}};
+use crate::entity::graph::{DependencyGraph, EdgeRelation, GraphNode, NodeKind};
+use crate::entity::journal::JournalAction;
+use crate::entity::software::{Software, SoftwareListTemp, SoftwareStatus};
 
-use super::package_manager::{self, package_manager, PackageManagerError};
+use super::package_manager::{package_manager, PackageManagerError};
 // 安装的软件包
+pub const SOFTWARE_DATA_PATH: &str = "database/software_data.toml";
 
 
 // 管理器作为单例
@@ -29,140 +25,372 @@ pub fn software_manager() -> &'static Arc<Mutex<SoftwareManager>> {
     &SOFTWARE_MANAGER
 }
 // 有一个对软件包做管理的类，持有所有下载的软件包
-// 垃圾回收，删除所有引用为0的软件包
+// 垃圾回收，删除从 GC 根到达不了的软件包
 pub struct SoftwareManager {
     softwares: Vec<Arc<Software>>,
     softwares_hashmap: HashMap<String, Arc<Software>>,
+    // 反向依赖：包 -> 直接依赖它的已安装的包
+    reverse_dependencies: HashMap<String, Vec<String>>,
+}
+// 把软件的依赖记录到反向依赖中
+fn index_reverse_dependencies(reverse_dependencies: &mut HashMap<String, Vec<String>>, software: &Software) {
+    let name = software.to_string();
+    for dependency in software.dependencies.iter() {
+        let dependents = reverse_dependencies.entry(dependency.to_string()).or_default();
+        if !dependents.contains(&name) {
+            dependents.push(name.clone());
+        }
+    }
+}
+// 从 roots 沿依赖能到达的包
+fn reachable(roots: &[String], dependencies: &HashMap<String, Vec<String>>) -> HashSet<String> {
+    let mut reachable: HashSet<String> = HashSet::new();
+    let mut stack: Vec<String> = roots.to_vec();
+    while let Some(name) = stack.pop() {
+        if !reachable.insert(name.clone()) {
+            continue;
+        }
+        if let Some(deps) = dependencies.get(&name) {
+            stack.extend(deps.iter().cloned());
+        }
+    }
+    return reachable;
+}
+// 引用计数：GC 根中出现的次数加上依赖它的包的个数
+fn reference_counts(roots: &[String], dependencies: &HashMap<String, Vec<String>>) -> HashMap<String, u32> {
+    let mut counts: HashMap<String, u32> = HashMap::new();
+    for name in roots.iter().chain(dependencies.values().flatten()) {
+        *counts.entry(name.clone()).or_insert(0) += 1;
+    }
+    return counts;
+}
+// 模拟的结果：要安装的包，会被删除的包，以及引用计数的变化(包，修改前，修改后)，修改前为空表示新安装
+#[derive(Clone, Debug, Default)]
+pub struct Simulation {
+    pub steps: Vec<InstallStep>,
+    pub removed: Vec<String>,
+    pub reference_changes: Vec<(String, Option<u32>, u32)>,
+}
+// 安装计划中的一项，要安装的依赖和它直接依赖的包
+#[derive(Clone, Debug)]
+pub struct InstallStep {
+    pub dependency: Arc<Dependency>,
+    pub dependencies: Vec<Arc<Dependency>>,
 }
 impl SoftwareManager {
     fn new() -> SoftwareManager {
         // 作为一个上层管理器，包括依赖解决、引用计数，更新软件从这里开始，安装软件由次获得依赖关系，删除软件由此获得哪些包要被删除
         // 在这里用数据文件保存已存在的包的引用计数，每个包最新的版本
-        let path = "database/software_data.toml";
-        let softwares = match profile_handler().analyse_software_file(path.to_string()) {
+        let softwares = match profile_handler().analyse_software_file(SOFTWARE_DATA_PATH.to_string()) {
             Ok(s) => s,
             Err(err) => {
                 panic!("{}", err.to_string());
             }
         };
         let mut map : HashMap<String, Arc<Software>> = HashMap::new();
+        let mut reverse_dependencies : HashMap<String, Vec<String>> = HashMap::new();
         log::debug!("SoftwareManager Start");
         for software in softwares.iter() {
             let str = format!("{}-{}", software.archive, software.version.to_string());
             map.insert(str, software.clone());
+            index_reverse_dependencies(&mut reverse_dependencies, software);
         }
         return SoftwareManager {
-            softwares: softwares,
+            softwares,
             softwares_hashmap: map,
+            reverse_dependencies,
         };
     }
-    fn hashmap(&self) -> &HashMap<String, Arc<Software>> {
-        return &self.softwares_hashmap;
-    }
-    // 删除所有引用数为0的依赖包
-    pub fn garbage_collection(& self) -> Result<(), SoftwareManagerError> {
-        // 检查引用是否为0 ，为0则删除文件，再从数组移除
-        for software in self.softwares.clone() {
-            let inner_guard = software.inner();
-            if inner_guard.is_some() {
-                let mut guard = inner_guard.unwrap();
-                if guard.reference_count() == 0 {
-                    // 调用迭代算法，删除无用文件
-                    
-                    self.remove_software(software.clone());
-                }
+    // 重新读取数据文件，回滚事务后调用
+    pub fn reload(&mut self) {
+        *self = SoftwareManager::new();
+    }
+    pub fn softwares(&self) -> Vec<Arc<Software>> {
+        return self.softwares.clone();
+    }
+    // 按 archive-version 查找已安装的软件
+    pub fn get(&self, name: &str) -> Option<Arc<Software>> {
+        return self.softwares_hashmap.get(name).cloned();
+    }
+    // 删除从 GC 根出发沿依赖到达不了的包，再重新计算引用计数，返回被删除的包，依赖它们的包排在前面
+    pub fn collect(&mut self, roots: &[String]) -> Result<Vec<String>, SoftwareManagerError> {
+        let unused = self.unreachable(roots);
+        let mut package_manager_guard = package_manager().lock().unwrap();
+        for name in unused.iter() {
+            let software = match self.softwares_hashmap.get(name) {
+                Some(s) => s.clone(),
+                None => continue,
+            };
+            // 删除开始后文件无法恢复，中断时不能回滚这个包
+            journal_unit()?.mark_started(JournalAction::Remove, name)?;
+            // 调用底层管理器删除文件
+            match package_manager_guard.uninstall_package(software.archive.clone(), software.version.clone()) {
+                Ok(_) => {},
+                Err(err) => return Err(SoftwareManagerError::from(err)),
+            };
+            match software.inner() {
+                Some(mut guard) => guard.remove(),
+                None => return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone())),
+            }
+            journal_unit()?.mark_done(JournalAction::Remove, name)?;
+        }
+        self.purge_removed();
+        // 根据操作之后的结果，生成新的数据文件覆盖原来的
+        package_manager_guard.save()?;
+        self.recompute_references(roots)?;
+        return Ok(unused);
+    }
+    // 从 GC 根出发沿依赖到达不了的已安装的包，依赖它们的包排在前面
+    pub fn unreachable(&self, roots: &[String]) -> Vec<String> {
+        let reachable = reachable(roots, &self.dependency_map());
+        return self.softwares.iter()
+            .rev()
+            .map(|software| software.to_string())
+            .filter(|name| !reachable.contains(name))
+            .collect();
+    }
+    // roots 和它们依赖的所有已安装的包，按广度优先排列，roots 排在前面
+    pub fn closure(&self, roots: &[String]) -> Vec<String> {
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut queue: VecDeque<String> = roots.iter().cloned().collect();
+        let mut closure = Vec::new();
+        while let Some(name) = queue.pop_front() {
+            if !visited.insert(name.clone()) {
+                continue;
             }
-            else {
-                return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone()));
+            if let Some(software) = self.softwares_hashmap.get(&name) {
+                queue.extend(software.dependencies.iter().map(|dep| dep.to_string()));
+                closure.push(name);
             }
         }
-        // todo 根据操作之后的结果，生成新的数据文件覆盖原来的
-        return Ok(());
+        return closure;
     }
-    // 迭代卸载
-    fn remove_software(& self, software: Arc<Software>) -> Result<(), SoftwareManagerError> {
-        let package_manager_guard = package_manager().lock().unwrap(); 
-        // 调用底层管理器删除文件
-        match package_manager_guard.uninstall_package(software.archive.clone(), software.version.clone()) {
-            Ok(_) => {},
-            Err(err) => return Err(SoftwareManagerError::from(err)),
+    // 已安装的包和它们直接依赖的包
+    fn dependency_map(&self) -> HashMap<String, Vec<String>> {
+        return self.softwares.iter()
+            .map(|software| (software.to_string(), software.dependencies.iter().map(|dep| dep.to_string()).collect()))
+            .collect();
+    }
+    // 删除软件的记录，不删除文件，用于文件已经被删掉的包
+    pub fn forget(&mut self, names: &HashSet<String>) -> Result<(), SoftwareManagerError> {
+        for name in names.iter() {
+            let software = match self.softwares_hashmap.get(name) {
+                Some(s) => s.clone(),
+                None => continue,
+            };
+            match software.inner() {
+                Some(mut guard) => guard.remove(),
+                None => return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone())),
+            };
+        }
+        self.purge_removed();
+        return self.save();
+    }
+    // 从列表中移除已经删除的软件
+    fn purge_removed(&mut self) {
+        let removed = |software: &Arc<Software>| -> bool {
+            match software.inner() {
+                Some(guard) => guard.status() == SoftwareStatus::Removed,
+                None => false,
+            }
         };
-        // 修改数据文件信息
-        let inner_guard = software.inner();
-        if inner_guard.is_some() {
-            let mut guard = inner_guard.unwrap();
-            guard.remove();
-        }
-        else {
-            return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone()));
-        }
-        // 遍历它的依赖包
-        for dep in software.dependencies.iter() {
-            let dependency_software = match self.softwares_hashmap.get(&dep.to_string()) {
-                Some(s) => s,
-                None => continue
-            };           
-            let dep_soft_inner_guard = dependency_software.inner();
-            if dep_soft_inner_guard.is_some() {
-                let mut dep_guard = dep_soft_inner_guard.unwrap();
-                // 减少引用计数，并判断是否为0，是则继续删除这个包
-                if dep_guard.descrease() == 0 {
-                    match self.remove_software(dependency_software.clone()) {
-                        Ok(_) => {},
-                        Err(err) => return Err(err),
-                    }
+        self.softwares_hashmap.retain(|_, software| !removed(software));
+        self.softwares.retain(|software| !removed(software));
+        self.reverse_dependencies.clear();
+        for software in self.softwares.iter() {
+            index_reverse_dependencies(&mut self.reverse_dependencies, software);
+        }
+    }
+    // 数据文件中重复的记录只保留第一条，返回被去掉的包
+    pub fn remove_duplicates(&mut self) -> Vec<String> {
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut removed = Vec::new();
+        self.softwares.retain(|software| {
+            let name = software.to_string();
+            if seen.insert(name.clone()) {
+                return true;
+            }
+            removed.push(name);
+            return false;
+        });
+        self.softwares_hashmap = self.softwares.iter().map(|software| (software.to_string(), software.clone())).collect();
+        self.reverse_dependencies.clear();
+        for software in self.softwares.iter() {
+            index_reverse_dependencies(&mut self.reverse_dependencies, software);
+        }
+        return removed;
+    }
+    // 按 GC 根算出的每个包应有的引用计数
+    pub fn expected_references(&self, roots: &[String]) -> HashMap<String, u32> {
+        return reference_counts(roots, &self.dependency_map());
+    }
+    // 按 archive-version 或 archive 查找已安装的软件
+    pub fn find(&self, name: &str) -> Vec<Arc<Software>> {
+        if let Some(software) = self.softwares_hashmap.get(name) {
+            return vec![software.clone()];
+        }
+        return self.softwares.iter().filter(|software| software.archive == name).cloned().collect();
+    }
+    // 直接依赖这个包的已安装的包
+    pub fn rdepends(&self, name: &str) -> Vec<String> {
+        let mut dependents = self.reverse_dependencies.get(name).cloned().unwrap_or_default();
+        dependents.sort();
+        return dependents;
+    }
+    // 从 roots 中的某个包沿依赖到达 name 的最短路径，第一个是根，最后一个是 name
+    pub fn why(&self, name: &str, roots: &HashSet<String>) -> Option<Vec<String>> {
+        // 从 name 沿反向依赖广度优先搜索，记录每个包是从哪个包找到的
+        let mut previous: HashMap<String, String> = HashMap::new();
+        let mut queue: VecDeque<String> = VecDeque::new();
+        queue.push_back(name.to_string());
+        previous.insert(name.to_string(), String::new());
+        while let Some(current) = queue.pop_front() {
+            if roots.contains(&current) {
+                let mut chain = vec![current.clone()];
+                let mut next = previous[&current].clone();
+                while !next.is_empty() {
+                    chain.push(next.clone());
+                    next = previous[&next].clone();
+                }
+                return Some(chain);
+            }
+            for dependent in self.rdepends(&current) {
+                if !previous.contains_key(&dependent) {
+                    previous.insert(dependent.clone(), current.clone());
+                    queue.push_back(dependent);
                 }
             }
         }
-        // 
-        return Ok(());
+        return None;
+    }
+    // 标记软件是手动安装还是作为依赖安装，name 为 archive-version，返回是否已安装，不保存数据文件
+    pub fn set_manual(&self, name: &str, manual: bool) -> Result<bool, SoftwareManagerError> {
+        let software = match self.softwares_hashmap.get(name) {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+        match software.inner() {
+            Some(mut guard) => guard.set_manual(manual),
+            None => return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone())),
+        }
+        return Ok(true);
     }
-    // 下载新软件
+    // 作为依赖安装、但手动安装的包和 roots 都不再依赖的包，后安装的排在前面
+    pub fn unreachable_auto(&self, roots: &[String]) -> Result<Vec<String>, SoftwareManagerError> {
+        let mut start: Vec<String> = roots.to_vec();
+        for software in self.softwares.iter() {
+            match software.inner() {
+                Some(guard) => if guard.manual() {
+                    start.push(software.to_string());
+                },
+                None => return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone())),
+            }
+        }
+        return Ok(self.unreachable(&start));
+    }
+    // 下载新软件，依赖排在前面，返回新安装的包
+    #[allow(dead_code)]
     pub fn install_package(
         &mut self,
         dependencies: Vec<Arc<Dependency>>,
-    ) -> Result<(), SoftwareManagerError> {
+    ) -> Result<Vec<Arc<Dependency>>, SoftwareManagerError> {
         // 检查
         let download_list = match self.check(dependencies.clone()) {
             Ok(list) => list,
             Err(err) => return Err(err),
         };
+        let roots : Vec<String> = dependencies.iter().map(|dep| dep.to_string()).collect();
+        return self.install_steps(download_list, &roots);
+    }
+    // 按顺序安装已经解析好的包，每装完一个记录到事务日志
+    // roots 为新版本中应用直接使用的包，新装的包只和同一个 profile 中的包检查文件冲突
+    pub fn install_steps(&mut self, download_list: Vec<InstallStep>, roots: &[String]) -> Result<Vec<Arc<Dependency>>, SoftwareManagerError> {
+        let mut installed : Vec<Arc<Dependency>> = Vec::new();
+        let mut start : Vec<String> = roots.to_vec();
+        for step in download_list.iter() {
+            start.extend(step.dependencies.iter().map(|dep| dep.to_string()));
+        }
+        // 已安装的包中用到的，加上要安装的包
+        let mut profile = self.closure(&start);
+        profile.extend(download_list.iter().map(|step| step.dependency.to_string()));
         let mut package_manager_guard = package_manager().lock().unwrap();
         // 下载依赖包
-        for dependency in download_list.iter() {
+        for step in download_list.iter() {
             // 下载依赖包
-            match package_manager_guard.install_package(dependency.clone()) {
+            match package_manager_guard.install_package(step.dependency.clone(), &profile) {
                 Ok(_) => {},
                 Err(err) => return Err(SoftwareManagerError::from(err)),
             }
+            let deps : Vec<Dependency> = step.dependencies.iter().map(|dep| dep.as_ref().clone()).collect();
+            // 引用计数在事务最后由 collect 重新计算
+            // 先当作依赖安装，应用直接使用的包由调度器标记为手动安装
+            let software = Software::new(step.dependency.archive.clone(), step.dependency.version_wrapper.clone(), deps, 0, SoftwareStatus::Available, false);
+            index_reverse_dependencies(&mut self.reverse_dependencies, &software);
+            self.softwares.push(software.clone());
+            self.softwares_hashmap.insert(software.to_string(), software);
+            installed.push(step.dependency.clone());
+            journal_unit()?.mark_done(JournalAction::Install, &step.dependency.to_string())?;
         }
-        // doto 修改数据文件
-        return Ok(());
+        // 修改数据文件
+        package_manager_guard.save()?;
+        self.save()?;
+        return Ok(installed);
+    }
+    // 模拟安装 new_list，之后的 GC 根为 roots，只解析依赖，不下载也不修改任何状态
+    pub fn simulate(&self, new_list: &PackageList, roots: &[String]) -> Result<Simulation, SoftwareManagerError> {
+        let steps = self.check(new_list.to_dependencies())?;
+        let mut dependencies = self.dependency_map();
+        let mut order : Vec<String> = Vec::new();
+        let mut before : HashMap<String, u32> = HashMap::new();
+        for software in self.softwares.iter() {
+            let count = match software.inner() {
+                Some(guard) => guard.reference_count(),
+                None => return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone())),
+            };
+            before.insert(software.to_string(), count);
+            order.push(software.to_string());
+        }
+        for step in steps.iter() {
+            let name = step.dependency.to_string();
+            dependencies.insert(name.clone(), step.dependencies.iter().map(|dep| dep.to_string()).collect());
+            order.push(name);
+        }
+        // 到达不了的包被删除，剩下的包的引用计数由 GC 根和依赖关系得到
+        let reachable = reachable(roots, &dependencies);
+        let removed : Vec<String> = order.iter().rev().filter(|name| !reachable.contains(*name)).cloned().collect();
+        dependencies.retain(|name, _| reachable.contains(name));
+        let counts = reference_counts(roots, &dependencies);
+        let mut reference_changes = Vec::new();
+        for name in order {
+            let after = counts.get(&name).cloned().unwrap_or(0);
+            let previous = before.get(&name).cloned();
+            if previous != Some(after) {
+                reference_changes.push((name, previous, after));
+            }
+        }
+        return Ok(Simulation { steps, removed, reference_changes });
     }
-    // 检查dependency_list是否存在环
+    // 检查dependency_list是否存在环，返回需要安装的包，无需其它依赖的排前面
     pub fn check(
         &self,
         dependencies: Vec<Arc<Dependency>>,
-    ) -> Result<LinkedList<Arc<Dependency>>, SoftwareManagerError> {
+    ) -> Result<Vec<InstallStep>, SoftwareManagerError> {
         // 用于记录下载队列，按顺序，无需其它依赖的排前面
-        let mut download_list: LinkedList<Arc<Dependency>> = LinkedList::new();
+        let mut download_list: Vec<InstallStep> = Vec::new();
+        // 已经处理完的包
+        let mut finished: HashSet<Arc<Dependency>> = HashSet::new();
         let mut hashset: HashSet<Arc<Dependency>> = HashSet::new();
         let mut linkedlist: LinkedList<Arc<Dependency>> = LinkedList::new();
         for dependency in dependencies {
             // 检测环形
-            match Self::dfs(
+            Self::dfs(
                 self,
                 dependency.clone(),
                 &mut hashset,
+                &mut finished,
                 &mut linkedlist,
                 &mut download_list,
-            ) {
-                Ok(b) => match b {
-                    true => {}
-                    false => return Err(SoftwareManagerError::CircularDependency(download_list)),
-                },
-                Err(e) => return Err(e),
-            }
+            )?;
         }
         return Ok(download_list);
     }
@@ -172,24 +400,29 @@ impl SoftwareManager {
         &self,
         dependency: Arc<Dependency>,
         hashset: &mut HashSet<Arc<Dependency>>,
+        finished: &mut HashSet<Arc<Dependency>>,
         linkedlist: &mut LinkedList<Arc<Dependency>>,
-        download_list: &mut LinkedList<Arc<Dependency>>,
-    ) -> Result<bool, SoftwareManagerError> {
+        download_list: &mut Vec<InstallStep>,
+    ) -> Result<(), SoftwareManagerError> {
         //
-        if self.softwares_hashmap.contains_key(&dependency.to_string()) {
-            return Ok(true);
+        if self.softwares_hashmap.contains_key(&dependency.to_string()) || finished.contains(&dependency) {
+            return Ok(());
         }
         if !hashset.insert(dependency.clone()) {
-            // 出现环形，用download list 装环形
-            download_list.clear();
+            // 出现环形，返回环上的包
+            let mut cycle: LinkedList<Arc<Dependency>> = LinkedList::new();
             for dep_tmp in linkedlist.iter().rev() {
-                //println!("{} {} -> ", dep_tmp.archive, dep_tmp.version);
-                download_list.push_back(dep_tmp.clone());
+                cycle.push_front(dep_tmp.clone());
                 if dep_tmp.eq(&dependency)   {
                     break;
                 }
             }
-            return Ok(false);
+            cycle.push_back(dependency.clone());
+            return Err(SoftwareManagerError::CircularDependency(cycle));
+        }
+        // 要安装锁定的软件的其它版本
+        if let Some(parent) = linkedlist.back() {
+            hold_unit()?.check(&dependency.archive, &dependency.version_wrapper, &format!("{} requires {}", parent.to_string(), dependency.to_string()))?;
         }
         linkedlist.push_back(dependency.clone());
         // 根据dep获得dep的依赖
@@ -197,48 +430,195 @@ impl SoftwareManager {
             Ok(dependency_list) => dependency_list,
             Err(err) => return Err(err),
         };
-        
-        for dependency in dep_dependencies {
-            match self.dfs(dependency, hashset, linkedlist, download_list) {
-                Ok(b) => match b {
-                    true => (),
-                    false => return Ok(false),
-                },
-                Err(err) => return Err(err),
-            }
+
+        for dep in dep_dependencies.iter() {
+            self.dfs(dep.clone(), hashset, finished, linkedlist, download_list)?;
         }
         linkedlist.pop_back();
         hashset.remove(&dependency);
-        download_list.push_front(dependency.clone());
-        return Ok(true);
+        finished.insert(dependency.clone());
+        download_list.push(InstallStep { dependency: dependency.clone(), dependencies: dep_dependencies });
+        return Ok(());
     }
-    // 更新引用计数
-    pub fn update_reference(&mut self, list: PackageList) -> Result<(), SoftwareManagerError>{
-        for dependency in list.dependencies {
-            if self.hashmap().contains_key(&dependency.to_string()){
-                let software = self.hashmap().get(&dependency.to_string()).unwrap();
-                let inner_guard = software.inner();
-                // 加锁
-                if inner_guard.is_some() {
-                    let mut guard = inner_guard.unwrap();
-                    guard.add();
-                }else {
-                    return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone()));
-                }
-            }else {
-                // 如果dependency不存在
-                return Err(SoftwareManagerError::SoftwareNotFound(dependency.clone()));
+    // 图中的一个包，已安装的带上引用计数
+    pub fn graph_node(&self, dependency: &Dependency) -> GraphNode {
+        let reference_count = self.softwares_hashmap.get(&dependency.to_string())
+            .and_then(|software| software.inner().map(|inner| inner.reference_count()));
+        return GraphNode {
+            id: dependency.to_string(),
+            kind: NodeKind::Package,
+            archive: dependency.archive.clone(),
+            version: dependency.version_wrapper.to_string(),
+            reference_count,
+            in_cycle: false,
+        };
+    }
+    // 所有已安装的包和它们之间的依赖
+    pub fn installed_graph(&self) -> DependencyGraph {
+        let mut graph = DependencyGraph::default();
+        for software in self.softwares.iter() {
+            let dependency = Dependency::new(software.archive.clone(), software.version.clone());
+            graph.add_node(self.graph_node(&dependency));
+        }
+        for software in self.softwares.iter() {
+            let from = format!("{}-{}", software.archive, software.version.to_string());
+            for dependency in software.dependencies.iter() {
+                graph.add_node(self.graph_node(dependency));
+                graph.add_edge(&from, &dependency.to_string(), EdgeRelation::Depends);
             }
-            
         }
-        return Ok(());
+        return graph;
+    }
+    // 解析一组依赖得到的依赖图，已安装的包使用记录的依赖，其余的从软件源获取
+    // 有环时不中断，用 check 找到的环做标记
+    pub fn resolve_graph(&self, dependencies: Vec<Arc<Dependency>>) -> Result<DependencyGraph, SoftwareManagerError> {
+        let mut graph = DependencyGraph::default();
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut stack: Vec<Arc<Dependency>> = dependencies.clone();
+        while let Some(dependency) = stack.pop() {
+            if !visited.insert(dependency.to_string()) {
+                continue;
+            }
+            graph.add_node(self.graph_node(&dependency));
+            let children: Vec<Arc<Dependency>> = match self.softwares_hashmap.get(&dependency.to_string()) {
+                Some(software) => software.dependencies.iter().map(|d| Arc::new(d.clone())).collect(),
+                None => Self::get_dep(dependency.clone())?,
+            };
+            for child in children {
+                graph.add_node(self.graph_node(&child));
+                graph.add_edge(&dependency.to_string(), &child.to_string(), EdgeRelation::Depends);
+                stack.push(child);
+            }
+        }
+        match self.check(dependencies) {
+            Ok(_) => {},
+            Err(SoftwareManagerError::CircularDependency(cycle)) => {
+                graph.mark_cycle(cycle.iter().map(|dependency| dependency.to_string()).collect());
+            }
+            Err(err) => return Err(err),
+        }
+        return Ok(graph);
+    }
+    // 根据 GC 根和已安装软件之间的依赖重新计算引用计数，引用计数只是由它们得到的结果
+    pub fn recompute_references(&mut self, roots: &[String]) -> Result<(), SoftwareManagerError> {
+        let counts = reference_counts(roots, &self.dependency_map());
+        for software in self.softwares.iter() {
+            match software.inner() {
+                Some(mut guard) => guard.set_reference_count(counts.get(&software.to_string()).cloned().unwrap_or(0)),
+                None => return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone())),
+            }
+        }
+        return self.save();
     }
     fn get_dep(dependency: Arc<Dependency>) -> Result<Vec<Arc<Dependency>>, SoftwareManagerError> {
         // 获取依赖
-        return download_unit().get_dependency_list(dependency);
+        return download_unit()?.get_dependency_list(dependency);
+    }
+    // 修改本地的数据文件
+    pub fn save(&self) -> Result<(), SoftwareManagerError> {
+        let mut list = SoftwareListTemp { software_temp: Vec::new() };
+        for software in self.softwares.iter() {
+            match software.to_temp() {
+                Some(temp) => list.software_temp.push(temp),
+                None => return Err(SoftwareManagerError::SoftwareLockError(software.archive.clone())),
+            }
+        }
+        let content = match toml::to_string(&list) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::WriteLocalFileError(SOFTWARE_DATA_PATH.to_string(), e).into()),
+        };
+        match profile_handler().write_local_file(SOFTWARE_DATA_PATH, &content) {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
     }
-
-    // todo 修改本地的数据文件
 }
 
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use crate::entity::dependency::Dependency;
+    use crate::entity::software::{Software, SoftwareStatus};
+    use super::{index_reverse_dependencies, reachable, reference_counts, SoftwareManager};
 
+    // 测试用的依赖图：
+    //   a -> b, c    b -> d    c -> d    e -> d    f
+    const GRAPH: [(&str, &[&str]); 6] = [
+        ("a-1.0.0", &["b-1.0.0", "c-1.0.0"]),
+        ("b-1.0.0", &["d-1.0.0"]),
+        ("c-1.0.0", &["d-1.0.0"]),
+        ("d-1.0.0", &[]),
+        ("e-1.0.0", &["d-1.0.0"]),
+        ("f-1.0.0", &[]),
+    ];
+    fn manager() -> SoftwareManager {
+        let mut softwares = Vec::new();
+        for (name, dependencies) in GRAPH.iter() {
+            let dependency = Dependency::from_string(name).unwrap();
+            let dependencies = dependencies.iter().map(|name| Dependency::from_string(name).unwrap()).collect();
+            softwares.push(Software::new(dependency.archive, dependency.version_wrapper, dependencies, 0, SoftwareStatus::Available, false));
+        }
+        let mut reverse_dependencies = HashMap::new();
+        for software in softwares.iter() {
+            index_reverse_dependencies(&mut reverse_dependencies, software);
+        }
+        let softwares_hashmap = softwares.iter().map(|software| (software.to_string(), software.clone())).collect();
+        return SoftwareManager { softwares, softwares_hashmap, reverse_dependencies };
+    }
+    fn names(list: &[&str]) -> Vec<String> {
+        return list.iter().map(|name| name.to_string()).collect();
+    }
+    #[test]
+    fn rdepends_lists_direct_dependents() {
+        let manager = manager();
+        assert_eq!(manager.rdepends("d-1.0.0"), names(&["b-1.0.0", "c-1.0.0", "e-1.0.0"]));
+        assert_eq!(manager.rdepends("b-1.0.0"), names(&["a-1.0.0"]));
+        assert!(manager.rdepends("a-1.0.0").is_empty());
+    }
+    #[test]
+    fn why_finds_the_shortest_chain_from_a_root() {
+        let manager = manager();
+        let roots: HashSet<String> = names(&["a-1.0.0", "f-1.0.0"]).into_iter().collect();
+        assert_eq!(manager.why("d-1.0.0", &roots), Some(names(&["a-1.0.0", "b-1.0.0", "d-1.0.0"])));
+        assert_eq!(manager.why("c-1.0.0", &roots), Some(names(&["a-1.0.0", "c-1.0.0"])));
+        // 根自己就是原因
+        assert_eq!(manager.why("f-1.0.0", &roots), Some(names(&["f-1.0.0"])));
+        // 只有不是根的包依赖它
+        assert_eq!(manager.why("e-1.0.0", &roots), None);
+        let roots: HashSet<String> = names(&["e-1.0.0"]).into_iter().collect();
+        assert_eq!(manager.why("d-1.0.0", &roots), Some(names(&["e-1.0.0", "d-1.0.0"])));
+        assert_eq!(manager.why("b-1.0.0", &roots), None);
+    }
+    #[test]
+    fn reachable_follows_dependencies_from_the_roots() {
+        let dependencies = manager().dependency_map();
+        let found = reachable(&names(&["a-1.0.0"]), &dependencies);
+        let expected: HashSet<String> = names(&["a-1.0.0", "b-1.0.0", "c-1.0.0", "d-1.0.0"]).into_iter().collect();
+        assert_eq!(found, expected);
+        // 没有安装的根也算到达，只是没有依赖
+        let found = reachable(&names(&["f-1.0.0", "g-1.0.0"]), &dependencies);
+        assert_eq!(found, names(&["f-1.0.0", "g-1.0.0"]).into_iter().collect());
+        assert!(reachable(&[], &dependencies).is_empty());
+    }
+    #[test]
+    fn reference_counts_add_roots_and_dependents() {
+        let dependencies = manager().dependency_map();
+        // 同一个根出现在两个版本中算两次
+        let counts = reference_counts(&names(&["a-1.0.0", "f-1.0.0", "a-1.0.0"]), &dependencies);
+        let expected: HashMap<String, u32> = [("a-1.0.0", 2), ("b-1.0.0", 1), ("c-1.0.0", 1), ("d-1.0.0", 3), ("f-1.0.0", 1)]
+            .iter()
+            .map(|(name, count)| (name.to_string(), *count))
+            .collect();
+        assert_eq!(counts, expected);
+        assert_eq!(manager().expected_references(&names(&["a-1.0.0", "f-1.0.0", "a-1.0.0"])), expected);
+    }
+    #[test]
+    fn unreachable_packages_are_collected_dependents_first() {
+        let manager = manager();
+        assert_eq!(manager.unreachable(&names(&["a-1.0.0", "f-1.0.0"])), names(&["e-1.0.0"]));
+        assert_eq!(manager.unreachable(&names(&["a-1.0.0"])), names(&["f-1.0.0", "e-1.0.0"]));
+        // e 依赖 d，保留 e 时 d 也要保留
+        assert_eq!(manager.unreachable(&names(&["e-1.0.0"])), names(&["f-1.0.0", "c-1.0.0", "b-1.0.0", "a-1.0.0"]));
+        assert_eq!(manager.unreachable(&[]).len(), GRAPH.len());
+    }
+}
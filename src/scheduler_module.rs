@@ -1,13 +1,28 @@
-use std::{sync::Arc};
+use std::{collections::{HashMap, HashSet}, fs, io::ErrorKind, path::Path, sync::Arc};
 use lazy_static::lazy_static;
-use crate::entity::dependency::{Configuration, Package, PackageList};
-use crate::entity::software::{Software};
-use crate::entity::version_wrapper::VersionWrapper;
+use serde_derive::Serialize;
+use crate::entity::dependency::{ConfigurationTemp, Dependency, Package, PackageList};
+use crate::entity::hold::HoldTemp;
+use crate::entity::graph::{DependencyGraph, EdgeRelation, GraphNode, NodeKind};
+use crate::entity::environment::{EnvironmentVariable, PROFILE_PATHS};
+use crate::entity::manifest::{ManifestTemp, VerifyReport};
+use crate::entity::output::{DependencyChain, EnvironmentOutput, FileOwner, FsckIssue, FsckIssueKind, InstallReason, ReverseDependencies};
+use crate::entity::journal::{JournalAction, JournalState, JournalStep, JournalTemp};
+use crate::entity::software::SoftwareTemp;
 use crate::error::global_error::GlobalError;
-use crate::manager::package_manager::ConfigurationUpdateMode;
+use crate::manager::configuration_manager::{configuration_manager, ConfigurationManager, KEEP_GENERATIONS};
+use crate::manager::package_manager::{configuration_update_unit, package_manager, store_path, ConfigurationUpdateMode, STORE_PATH, VersionMode};
+use crate::tool::build_unit::{build_unit, BuiltPackage};
+use crate::tool::cache_unit::cache_unit;
+use crate::tool::hold_unit::hold_unit;
+use crate::tool::journal_unit::journal_unit;
+use crate::tool::manifest_unit::manifest_unit;
+use crate::tool::network_module::download_unit;
+use crate::tool::profile_unit::profile_unit;
+use crate::tool::repository_unit::{repository_unit, RepositoryIndex};
 use crate::tool::resolve_file::profile_handler;
-use crate::manager::software_manager::{self,software_manager};
-use crate::error::software_error::SoftwareManagerError;
+use crate::tool::server_module::RepositoryServer;
+use crate::manager::software_manager::{software_manager, InstallStep, SoftwareManager};
 
 // 调度器作为单例
 lazy_static! {
@@ -18,62 +33,716 @@ lazy_static! {
 pub(super) fn scheduler() -> &'static Scheduler {
     &SCHEDULER
 }
+// 引导安装的软件记录在这个应用下
+pub const BOOTSTRAP_APP: &str = "bootstrap";
+// 不属于任何应用、也不会被回收的包，每行一个 archive-version，# 开头的是注释
+pub const GC_ROOTS_PATH: &str = "database/gc_roots";
+// 一次操作中安装和删除的包，以及应用切换到的版本，版本为0表示没有产生新版本
+// 模拟运行时只有 plan，不修改任何状态
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ChangeSummary {
+    pub app: String,
+    pub generation: usize,
+    pub installed: Vec<String>,
+    pub removed: Vec<String>,
+    pub plan: Option<TransactionPlan>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanActionKind {
+    Install,
+    Upgrade,
+    Downgrade,
+    Remove,
+}
+// 计划中的一步，previous 是被替换的版本
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanAction {
+    pub kind: PlanActionKind,
+    pub package: String,
+    pub previous: Option<String>,
+    // 需要下载的大小，已经缓存时为0
+    pub download_size: u64,
+    pub installed_size: u64,
+}
+// 引用计数的变化，before 为空表示新安装
+#[derive(Debug, Clone, Serialize)]
+pub struct ReferenceChange {
+    pub package: String,
+    pub before: Option<u32>,
+    pub after: u32,
+}
+// 模拟运行得到的完整计划，按执行顺序排列
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct TransactionPlan {
+    pub app: String,
+    // 应用将切换到的版本
+    pub generation: usize,
+    pub actions: Vec<PlanAction>,
+    pub reference_changes: Vec<ReferenceChange>,
+    pub download_size: u64,
+    // 安装占用大小的变化，删除为负
+    pub installed_size_change: i64,
+}
+// gc 的结果，模拟时 removed 是会被删除的包
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct GarbageCollection {
+    pub removed: Vec<String>,
+    // 因为 --keep 丢弃的版本数
+    pub dropped_generations: usize,
+}
+// show 命令的结果，installed 为空表示没有安装
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageDetail {
+    pub package: Package,
+    pub installed: Option<SoftwareTemp>,
+}
 // 调度器负责完成任务的调度，操控多个组件一起完成任务
+// 同时使用多个管理器时按 configuration -> software -> package 的顺序加锁
 pub struct  Scheduler{
 }
 impl Scheduler {
     fn new ()-> Scheduler{
         return Scheduler {  };
     }
-    // 解析下载安装
-    // 使用场景： 1、 系统第一次启动时解析配置文件下载安装必带的依赖
-    // pub fn analyse_download_install (&self, path : String) -> Result<(),GlobalError>{
-    //     // 获取要下载的配置文件
-    //     let dependency_list = match profile_handler().analyse_local_file(path.clone()) {
-    //         Ok(list) => list,
-    //         Err(e) => {
-    //             return Err(GlobalError::from(e));
-    //         }
-    //     };
-    //     // 检查
-    //     let mut software_guard = crate::manager::software_manager::software_manager().lock().unwrap();
-    //     let download_list = match software_guard.check(dependency_list.dependencies){
-    //         Ok(list) => list,
-    //         Err(e) => {
-    //             log::error!("bug");
-    //             // 存在环
-    //             return Err(GlobalError::from(e));
-    //         }     
-    //     };
-    //     for dep in download_list.iter(){
-    //         print!(" {} ->",dep.archive);
-    //     }
-    //     //下载
-    //     let download_result = software_guard.install(download_list);
-    //     match download_result {
-    //         Ok(_) => {},
-    //         Err(e) => {
-    //             return Err(GlobalError::from(e));
-    //         }
-    //     }
-    //     // 把新的配置加入管理器
-    //     let mut configuration_guard = configuration_manager().lock().unwrap();
-    //     let config = Configuration::new(dependency_list.clone(), path, 0);
-    //     match configuration_guard.insert(config) {
-    //         Ok(_) => {},
-    //         Err(e) => {
-    //             log::error!("bug");
-    //             return Err(GlobalError::from(e));
-    //         }
-    //     }
-    //     // 对依赖的引用计数做修改
-    //     match software_guard.update_reference(dependency_list) {
-    //         Ok(_) => return Ok(()),
-    //         Err(e) => return Err(GlobalError::from(e)) 
-    //     }
-    // }
-
-    pub fn bootstrap (&self, path : String) -> Result<(),GlobalError>{
+    // 把新的依赖列表作为应用的新版本：先安装，再记录版本，最后删除 GC 根到达不了的包
+    fn apply (&self, configuration_guard : &mut ConfigurationManager, app : &str, new_list : PackageList, dry_run : bool) -> Result<ChangeSummary,GlobalError>{
+        if dry_run {
+            return self.simulate(configuration_guard, app, &new_list, KEEP_GENERATIONS);
+        }
+        let mut software_guard = software_manager().lock().unwrap();
+        // 先解析出要安装和删除的包，写进事务日志再开始修改
+        let roots = self.gc_roots(&configuration_guard.generations_after(app, &new_list, KEEP_GENERATIONS)?)?;
+        let simulation = software_guard.simulate(&new_list, &roots)?;
+        let mut steps : Vec<JournalStep> = simulation.steps.iter()
+            .map(|step| JournalStep { action : JournalAction::Install, package : step.dependency.to_string(), started : false, done : false })
+            .collect();
+        steps.extend(simulation.removed.iter().map(|name| JournalStep { action : JournalAction::Remove, package : name.clone(), started : false, done : false }));
+        journal_unit()?.begin(&format!("switch {} to a new generation", app), JournalState::Prepared, steps)?;
+        match self.transaction(configuration_guard, &mut software_guard, app, new_list, simulation.steps) {
+            Ok(summary) => {
+                journal_unit()?.finish()?;
+                return Ok(summary);
+            }
+            Err(e) => {
+                // 出错时立即按日志恢复，保证数据文件和安装目录一致
+                if let Err(err) = self.recover_locked(configuration_guard, &mut software_guard) {
+                    log::error!("recover failed: {}", err);
+                }
+                return Err(e);
+            }
+        }
+    }
+    // apply 的实际修改，新版本记录之后提交事务
+    fn transaction (&self, configuration_guard : &mut ConfigurationManager, software_guard : &mut SoftwareManager, app : &str, new_list : PackageList, steps : Vec<InstallStep>) -> Result<ChangeSummary,GlobalError>{
+        let mut summary = ChangeSummary::default();
+        let previous = configuration_guard.current(app)?;
+        let roots : Vec<String> = new_list.dependencies.iter().map(|package| package.to_string()).collect();
+        let installed = software_guard.install_steps(steps, &roots)?;
+        summary.installed = installed.iter().map(|dep| dep.to_string()).collect();
+        // 新加入应用的包标记为手动安装，升级或降级时沿用同名旧版本的标记
+        for package in new_list.dependencies.iter() {
+            if previous.dependencies.iter().any(|old| old.to_string() == package.to_string()) {
+                continue;
+            }
+            let manual = match previous.dependencies.iter().find(|old| old.archive == package.archive).and_then(|old| software_guard.get(&old.to_string())) {
+                Some(old) => old.inner().is_none_or(|guard| guard.manual()),
+                None => true,
+            };
+            software_guard.set_manual(&package.to_string(), manual)?;
+        }
+        configuration_guard.update(app, new_list)?;
+        configuration_guard.save()?;
+        journal_unit()?.commit()?;
+        summary.app = app.to_string();
+        summary.generation = configuration_guard.generation(app)?;
+        // 先把应用切换到新的 profile，再删除不再使用的包
+        self.sync_profiles(configuration_guard, software_guard, false)?;
+        summary.removed = software_guard.collect(&self.gc_roots(&configuration_guard.generations()?)?)?;
+        return Ok(summary);
+    }
+    // 检查上次是否有没完成的事务，有则恢复，返回是否进行了恢复
+    pub fn recover (&self) -> Result<bool,GlobalError>{
+        if journal_unit()?.pending().is_none() {
+            return Ok(false);
+        }
+        let mut configuration_guard = configuration_manager().lock().unwrap();
+        let mut software_guard = software_manager().lock().unwrap();
+        self.recover_locked(&mut configuration_guard, &mut software_guard)?;
+        return Ok(true);
+    }
+    // 没有提交的事务回滚到开始前的数据文件，删除装了一半的包；已经提交的事务删除 GC 根到达不了的包并重新计算引用计数
+    fn recover_locked (&self, configuration_guard : &mut ConfigurationManager, software_guard : &mut SoftwareManager) -> Result<(),GlobalError>{
+        let journal = match journal_unit()?.pending() {
+            Some(j) => j,
+            None => return Ok(()),
+        };
+        match journal.state {
+            JournalState::Prepared => {
+                log::warn!("roll back unfinished transaction: {}", journal.operation);
+                self.roll_back(configuration_guard, software_guard, &journal)?;
+            }
+            JournalState::Committed => {
+                log::warn!("roll forward unfinished transaction: {}", journal.operation);
+                self.sync_profiles(configuration_guard, software_guard, true)?;
+                software_guard.collect(&self.gc_roots(&configuration_guard.generations()?)?)?;
+            }
+        }
+        journal_unit()?.finish()?;
+        return Ok(());
+    }
+    fn roll_back (&self, configuration_guard : &mut ConfigurationManager, software_guard : &mut SoftwareManager, journal : &JournalTemp) -> Result<(),GlobalError>{
+        journal_unit()?.restore_snapshot(journal)?;
+        configuration_guard.reload();
+        software_guard.reload();
+        let mut package_manager_guard = package_manager().lock().unwrap();
+        package_manager_guard.reload();
+        // 回滚前已经不在数据文件中的包，删除它的安装目录
+        for step in journal.steps.iter().filter(|step| step.action == JournalAction::Install) {
+            if package_manager_guard.get(&step.package).is_some() {
+                continue;
+            }
+            let dependency = Dependency::from_string(&step.package)?;
+            let path = store_path(&dependency.archive, &dependency.version_wrapper);
+            match fs::remove_dir_all(&path) {
+                Ok(_) => log::info!("removed partially installed {}", path),
+                Err(e) if e.kind() == ErrorKind::NotFound => {},
+                Err(e) => return Err(GlobalError::Io(path, e)),
+            }
+            manifest_unit().remove(&step.package)?;
+        }
+        // 已经开始删除的包文件无法恢复，快照中的记录会指向不存在的目录，向前完成删除
+        let removed : HashSet<String> = journal.steps.iter()
+            .filter(|step| step.action == JournalAction::Remove && (step.started || step.done))
+            .map(|step| step.package.clone())
+            .filter(|name| package_manager_guard.get(name).is_some())
+            .collect();
+        if removed.is_empty() {
+            return Ok(());
+        }
+        for name in removed.iter() {
+            log::warn!("{} was partially removed, finish removing it", name);
+            let dependency = Dependency::from_string(name)?;
+            package_manager_guard.uninstall_package(dependency.archive, dependency.version_wrapper)?;
+        }
+        package_manager_guard.save()?;
+        drop(package_manager_guard);
+        // 从保留的版本中去掉，再重新生成 profile 和引用计数
+        configuration_guard.drop_packages(&removed)?;
+        configuration_guard.save()?;
+        software_guard.forget(&removed)?;
+        software_guard.recompute_references(&self.gc_roots(&configuration_guard.generations()?)?)?;
+        self.sync_profiles(configuration_guard, software_guard, true)?;
+        return Ok(());
+    }
+    // 模拟 apply，只解析依赖和查询软件包信息，生成执行计划
+    fn simulate (&self, configuration_guard : &ConfigurationManager, app : &str, new_list : &PackageList, keep : usize) -> Result<ChangeSummary,GlobalError>{
+        let (generation, _) = configuration_guard.simulate_update(app, keep)?;
+        let current = configuration_guard.current(app)?;
+        let roots = self.gc_roots(&configuration_guard.generations_after(app, new_list, keep)?)?;
+        let software_guard = software_manager().lock().unwrap();
+        let simulation = software_guard.simulate(new_list, &roots)?;
+        let package_manager_guard = package_manager().lock().unwrap();
+        let index = package_manager_guard.index();
+        let mut plan = TransactionPlan { app : app.to_string(), generation, ..TransactionPlan::default() };
+        for step in simulation.steps.iter() {
+            let name = step.dependency.to_string();
+            // 软件包信息依次从新版本、软件包列表和软件源中查找
+            let package = match new_list.dependencies.iter().chain(index.iter()).find(|package| package.to_string() == name) {
+                Some(p) => p.clone(),
+                None => download_unit()?.get_package_information(step.dependency.clone())?,
+            };
+            // 同名的旧版本，先找应用当前的版本，再找会被删除的包
+            let previous = current.dependencies.iter()
+                .map(|package| package.to_dependency())
+                .chain(simulation.removed.iter().filter_map(|removed| Dependency::from_string(removed).ok()))
+                .find(|dep| dep.archive == package.archive && dep.version_wrapper != package.version_wrapper);
+            let kind = match previous.as_ref() {
+                Some(dep) if dep.version_wrapper.version < package.version_wrapper.version => PlanActionKind::Upgrade,
+                Some(_) => PlanActionKind::Downgrade,
+                None => PlanActionKind::Install,
+            };
+            let download_size = if cache_unit().contains(&package) { 0 } else { package.size };
+            plan.download_size += download_size;
+            plan.installed_size_change += package.installed_size as i64;
+            plan.actions.push(PlanAction {
+                kind,
+                package : name,
+                previous : previous.map(|dep| dep.to_string()),
+                download_size,
+                installed_size : package.installed_size,
+            });
+        }
+        // 应用切换到已经安装的其它版本，比如回退，不需要下载
+        for package in new_list.dependencies.iter() {
+            if plan.actions.iter().any(|action| action.package == package.to_string()) {
+                continue;
+            }
+            let previous = current.dependencies.iter()
+                .find(|old| old.archive == package.archive && old.version_wrapper != package.version_wrapper);
+            if let Some(old) = previous {
+                let kind = if old.version_wrapper.version < package.version_wrapper.version { PlanActionKind::Upgrade } else { PlanActionKind::Downgrade };
+                plan.actions.push(PlanAction {
+                    kind,
+                    package : package.to_string(),
+                    previous : Some(old.to_string()),
+                    download_size : 0,
+                    installed_size : 0,
+                });
+            }
+        }
+        for name in simulation.removed.iter() {
+            let installed_size = package_manager_guard.get(name).map_or(0, |package| package.installed_size);
+            plan.installed_size_change -= installed_size as i64;
+            plan.actions.push(PlanAction {
+                kind : PlanActionKind::Remove,
+                package : name.clone(),
+                previous : None,
+                download_size : 0,
+                installed_size,
+            });
+        }
+        plan.reference_changes = simulation.reference_changes.into_iter()
+            .map(|(package, before, after)| ReferenceChange { package, before, after })
+            .collect();
+        return Ok(ChangeSummary { app : app.to_string(), plan : Some(plan), ..ChangeSummary::default() });
+    }
+    // 在当前版本的基础上按更新模式生成新版本，没有变化时不产生新版本
+    fn update_configuration (&self, app : &str, mode : ConfigurationUpdateMode, dry_run : bool) -> Result<ChangeSummary,GlobalError>{
+        let mut configuration_guard = configuration_manager().lock().unwrap();
+        let current = configuration_guard.current(app)?;
+        let index = package_manager().lock().unwrap().index();
+        let new_list = configuration_update_unit().get_new_configuration(current.clone(), mode, &index)?;
+        if configuration_guard.get(app).is_some() && new_list == current {
+            return Ok(ChangeSummary::default());
+        }
+        return self.apply(&mut configuration_guard, app, new_list, dry_run);
+    }
+    // 给应用安装软件，不写版本时安装软件包列表中的最新版本
+    pub fn install (&self, app : &str, packages : Vec<(String, VersionMode)>, dry_run : bool) -> Result<ChangeSummary,GlobalError>{
+        let map : HashMap<String, VersionMode> = packages.into_iter().collect();
+        return self.update_configuration(app, ConfigurationUpdateMode::PartialUpdate(map), dry_run);
+    }
+    // 从应用中移除软件，软件在上一个版本中仍被引用，回退时不用重新下载
+    pub fn remove (&self, app : &str, names : &[String], dry_run : bool) -> Result<ChangeSummary,GlobalError>{
+        return self.remove_packages(app, names, KEEP_GENERATIONS, dry_run);
+    }
+    // 移除软件，模拟时按保留 keep 个版本计算
+    fn remove_packages (&self, app : &str, names : &[String], keep : usize, dry_run : bool) -> Result<ChangeSummary,GlobalError>{
+        let mut configuration_guard = configuration_manager().lock().unwrap();
+        if configuration_guard.get(app).is_none() {
+            return Err(GlobalError::ConfigurationNotFound(app.to_string()));
+        }
+        let current = configuration_guard.current(app)?;
+        let matches = |package : &Arc<Package>, name : &String| package.archive == *name || package.to_string() == *name;
+        for name in names.iter() {
+            if !current.dependencies.iter().any(|package| matches(package, name)) {
+                return Err(GlobalError::PackageNotFound(format!("{} in {}", name, app)));
+            }
+        }
+        let packages : Vec<Arc<Package>> = current.dependencies.iter()
+            .filter(|package| !names.iter().any(|name| matches(package, name)))
+            .cloned()
+            .collect();
+        let new_list = PackageList::with_dependencies(packages);
+        if dry_run {
+            return self.simulate(&configuration_guard, app, &new_list, keep);
+        }
+        return self.apply(&mut configuration_guard, app, new_list, false);
+    }
+    // 移除软件，同时丢弃应用的旧版本并删除软件的缓存，删除后无法回退
+    pub fn purge (&self, app : &str, names : &[String], dry_run : bool) -> Result<ChangeSummary,GlobalError>{
+        if dry_run {
+            return self.remove_packages(app, names, 1, true);
+        }
+        let mut summary = self.remove(app, names, false)?;
+        let mut configuration_guard = configuration_manager().lock().unwrap();
+        let mut software_guard = software_manager().lock().unwrap();
+        // 丢弃旧版本后只需要继续释放引用，中断时向前恢复
+        journal_unit()?.begin(&format!("purge old generations of {}", app), JournalState::Committed, Vec::new())?;
+        configuration_guard.prune(app, 1)?;
+        configuration_guard.save()?;
+        self.sync_profiles(&configuration_guard, &software_guard, false)?;
+        summary.removed.extend(software_guard.collect(&self.gc_roots(&configuration_guard.generations()?)?)?);
+        journal_unit()?.finish()?;
+        for name in names.iter() {
+            let archive = match Dependency::from_string(name) {
+                Ok(dep) => dep.archive,
+                Err(_) => name.clone(),
+            };
+            cache_unit().remove(&archive)?;
+        }
+        return Ok(summary);
+    }
+    // 把应用中的软件升级到软件包列表中的最新版本，names 为空时升级全部
+    pub fn upgrade (&self, app : &str, names : &[String], dry_run : bool) -> Result<ChangeSummary,GlobalError>{
+        if names.is_empty() {
+            if configuration_manager().lock().unwrap().get(app).is_none() {
+                return Err(GlobalError::ConfigurationNotFound(app.to_string()));
+            }
+            return self.update_configuration(app, ConfigurationUpdateMode::AllUpdateLatest, dry_run);
+        }
+        let current = configuration_manager().lock().unwrap().current(app)?;
+        let mut map : HashMap<String, VersionMode> = HashMap::new();
+        for name in names.iter() {
+            if !current.dependencies.iter().any(|package| package.archive == *name) {
+                return Err(GlobalError::PackageNotFound(format!("{} in {}", name, app)));
+            }
+            map.insert(name.clone(), VersionMode::Latest);
+        }
+        return self.update_configuration(app, ConfigurationUpdateMode::PartialUpdate(map), dry_run);
+    }
+    // 从软件源刷新软件包列表
+    pub fn update (&self) -> Result<usize,GlobalError>{
+        let mut package_manager_guard = package_manager().lock().unwrap();
+        return Ok(package_manager_guard.update_index()?);
+    }
+    // 回退到应用的上一个版本
+    pub fn rollback (&self, app : &str, dry_run : bool) -> Result<ChangeSummary,GlobalError>{
+        let mut configuration_guard = configuration_manager().lock().unwrap();
+        let previous = configuration_guard.previous(app)?;
+        for package in previous.dependencies.iter() {
+            hold_unit()?.check(&package.archive, &package.version_wrapper, &format!("rolling back {} would switch to {}", app, package.to_string()))?;
+        }
+        return self.apply(&mut configuration_guard, app, previous, dry_run);
+    }
+    // 锁定软件的版本，name 为 archive 时锁定已安装的最新版本，返回所有锁定的软件
+    pub fn hold (&self, names : &[String]) -> Result<Vec<HoldTemp>,GlobalError>{
+        let software_guard = software_manager().lock().unwrap();
+        let mut holds = Vec::new();
+        for name in names.iter() {
+            if let Ok(dependency) = Dependency::from_string(name) {
+                holds.push((dependency.archive, dependency.version_wrapper));
+                continue;
+            }
+            let installed = software_guard.find(name).into_iter()
+                .max_by(|a, b| a.version.version.cmp(&b.version.version));
+            match installed {
+                Some(software) => holds.push((software.archive.clone(), software.version.clone())),
+                None => return Err(GlobalError::PackageNotFound(format!("{} is not installed, hold archive-version instead", name))),
+            }
+        }
+        for (archive, version) in holds {
+            hold_unit()?.hold(&archive, version)?;
+        }
+        return Ok(hold_unit()?.holds());
+    }
+    // 所有锁定的软件
+    pub fn holds (&self) -> Result<Vec<HoldTemp>,GlobalError>{
+        return Ok(hold_unit()?.holds());
+    }
+    // 解除锁定，返回剩下的锁定的软件
+    pub fn unhold (&self, names : &[String]) -> Result<Vec<HoldTemp>,GlobalError>{
+        for name in names.iter() {
+            let archive = match Dependency::from_string(name) {
+                Ok(dependency) => dependency.archive,
+                Err(_) => name.clone(),
+            };
+            if !hold_unit()?.unhold(&archive)? {
+                return Err(GlobalError::PackageNotFound(format!("{} is not held", archive)));
+            }
+        }
+        return Ok(hold_unit()?.holds());
+    }
+    // 已安装的软件
+    pub fn list_installed (&self) -> Result<Vec<SoftwareTemp>,GlobalError>{
+        let software_guard = software_manager().lock().unwrap();
+        let mut list = Vec::new();
+        for software in software_guard.softwares() {
+            match software.to_temp() {
+                Some(temp) => list.push(temp),
+                None => return Err(GlobalError::SoftwareLockError(software.archive.clone())),
+            }
+        }
+        return Ok(list);
+    }
+    // 应用的配置和版本链，app 为空时返回所有应用
+    pub fn list_configurations (&self, app : Option<&str>) -> Result<Vec<ConfigurationTemp>,GlobalError>{
+        let configuration_guard = configuration_manager().lock().unwrap();
+        let configurations = match app {
+            Some(name) => match configuration_guard.get(name) {
+                Some(c) => vec![c],
+                None => return Err(GlobalError::ConfigurationNotFound(name.to_string())),
+            },
+            None => configuration_guard.configurations(),
+        };
+        let mut list = Vec::new();
+        for configuration in configurations {
+            match configuration.to_temp() {
+                Some(temp) => list.push(temp),
+                None => return Err(GlobalError::ConfigurationLockFailed),
+            }
+        }
+        return Ok(list);
+    }
+    // 使用应用的 profile 要设置的环境变量：profile 中存在的常用目录，加上应用用到的包在元数据中声明的变量
+    // 同名的变量以先出现的为准，应用直接安装的包排在依赖前面
+    pub fn environment (&self, app : &str) -> Result<EnvironmentOutput,GlobalError>{
+        let configuration_guard = configuration_manager().lock().unwrap();
+        if configuration_guard.get(app).is_none() {
+            return Err(GlobalError::ConfigurationNotFound(app.to_string()));
+        }
+        let roots : Vec<String> = configuration_guard.current(app)?.dependencies.iter().map(|package| package.to_string()).collect();
+        let closure = software_manager().lock().unwrap().closure(&roots);
+        let profile = match std::env::current_dir() {
+            Ok(directory) => directory.join(profile_unit().link_path(app)),
+            Err(e) => return Err(GlobalError::Io(String::new(), e)),
+        };
+        let mut variables : Vec<EnvironmentVariable> = Vec::new();
+        for (name, directories, fallback) in PROFILE_PATHS.iter() {
+            let paths : Vec<String> = directories.iter()
+                .map(|directory| profile.join(directory))
+                .filter(|path| path.is_dir())
+                .map(|path| path.display().to_string())
+                .collect();
+            if paths.is_empty() {
+                continue;
+            }
+            variables.push(EnvironmentVariable { name : name.to_string(), value : paths.join(":"), prepend : true, fallback : fallback.map(|value| value.to_string()) });
+        }
+        let package_manager_guard = package_manager().lock().unwrap();
+        for name in closure.iter() {
+            let package = match package_manager_guard.get(name) {
+                Some(p) => p,
+                None => continue,
+            };
+            for entry in package.environment.iter() {
+                let variable = match EnvironmentVariable::parse(entry, &profile.display().to_string()) {
+                    Some(v) => v,
+                    None => {
+                        log::warn!("{}: invalid environment entry {}, skipped", name, entry);
+                        continue;
+                    }
+                };
+                match variables.iter_mut().find(|existing| existing.name == variable.name) {
+                    Some(existing) if existing.prepend && variable.prepend => {
+                        existing.value = format!("{}:{}", existing.value, variable.value);
+                    }
+                    Some(_) => log::warn!("{}: {} is already set by another package, skipped", name, variable.name),
+                    None => variables.push(variable),
+                }
+            }
+        }
+        return Ok(EnvironmentOutput { app : app.to_string(), profile : profile.display().to_string(), variables });
+    }
+    // 查看软件包的详细信息，name 可以是 archive 或 archive-version
+    pub fn show (&self, name : &str) -> Result<PackageDetail,GlobalError>{
+        let installed_software = |package : &Package| -> Option<SoftwareTemp> {
+            let software_guard = software_manager().lock().unwrap();
+            return software_guard.get(&package.to_string()).and_then(|software| software.to_temp());
+        };
+        let package = match Dependency::from_string(name) {
+            Ok(dependency) => {
+                let installed = package_manager().lock().unwrap().get(&dependency.to_string());
+                match installed {
+                    Some(p) => p,
+                    None => download_unit()?.get_package_information(Arc::new(dependency))?,
+                }
+            }
+            Err(_) => {
+                let package_manager_guard = package_manager().lock().unwrap();
+                let installed = package_manager_guard.packages().into_iter()
+                    .filter(|package| package.archive == name)
+                    .max_by(|a, b| a.version_wrapper.version.cmp(&b.version_wrapper.version));
+                match installed.or_else(|| package_manager_guard.latest(name)) {
+                    Some(p) => p,
+                    None => return Err(GlobalError::PackageNotFound(name.to_string())),
+                }
+            }
+        };
+        let installed = installed_software(&package);
+        return Ok(PackageDetail { package: package.as_ref().clone(), installed });
+    }
+    // 按名字查找软件包列表，返回软件包和是否已安装
+    pub fn search (&self, keyword : &str) -> Result<Vec<(Package, bool)>,GlobalError>{
+        let package_manager_guard = package_manager().lock().unwrap();
+        let mut result = Vec::new();
+        for package in package_manager_guard.search(keyword) {
+            let installed = package_manager_guard.get(&package.to_string()).is_some();
+            result.push((package.as_ref().clone(), installed));
+        }
+        return Ok(result);
+    }
+    // 检查已安装的包的文件和安装时记录的清单是否一致，names 为空时检查所有包
+    // fix 时重新安装有问题的包，压缩包优先从缓存中取
+    pub fn verify (&self, names : &[String], fix : bool) -> Result<Vec<VerifyReport>,GlobalError>{
+        let package_manager_guard = package_manager().lock().unwrap();
+        let matches = |package : &Package, name : &String| package.archive == *name || package.to_string() == *name;
+        let mut packages = package_manager_guard.packages();
+        for name in names.iter() {
+            if !packages.iter().any(|package| matches(package, name)) {
+                return Err(GlobalError::PackageNotFound(format!("{} is not installed", name)));
+            }
+        }
+        if !names.is_empty() {
+            packages.retain(|package| names.iter().any(|name| matches(package, name)));
+        }
+        packages.sort_by_key(|package| package.to_string());
+        let mut reports = Vec::new();
+        for package in packages.iter() {
+            let name = package.to_string();
+            let root = store_path(&package.archive, &package.version_wrapper);
+            let mut report = manifest_unit().verify(&name, Path::new(&root))?;
+            if fix && report.broken() {
+                package_manager_guard.reinstall_package(&name)?;
+                report.fixed = !manifest_unit().verify(&name, Path::new(&root))?.broken();
+            }
+            reports.push(report);
+        }
+        return Ok(reports);
+    }
+    // 拥有这个路径的已安装的包，path 可以是相对于安装目录的路径，也可以是 profile 中的文件
+    pub fn owns (&self, path : &str) -> Result<Vec<FileOwner>,GlobalError>{
+        let package_manager_guard = package_manager().lock().unwrap();
+        let mut installed : Vec<String> = package_manager_guard.packages().iter().map(|package| package.to_string()).collect();
+        let mut relative = path.to_string();
+        // profile 中的文件是指向安装目录的符号链接，直接找到安装它的包
+        if let (Ok(real), Ok(store)) = (fs::canonicalize(path), fs::canonicalize(STORE_PATH)) {
+            if let Ok(rest) = real.strip_prefix(&store) {
+                let mut components = rest.components();
+                if let Some(package) = components.next() {
+                    installed.retain(|name| name.as_str() == package.as_os_str());
+                    relative = components.as_path().display().to_string();
+                }
+            }
+        }
+        let owners : Vec<FileOwner> = manifest_unit().owners(&relative, &installed).into_iter()
+            .map(|(package, file)| FileOwner { package, file })
+            .collect();
+        if owners.is_empty() {
+            return Err(GlobalError::PackageNotFound(format!("no installed package owns {}", path)));
+        }
+        return Ok(owners);
+    }
+    // 已安装的包的文件清单，name 可以是 archive 或 archive-version
+    pub fn files (&self, name : &str) -> Result<Vec<ManifestTemp>,GlobalError>{
+        let package_manager_guard = package_manager().lock().unwrap();
+        let mut manifests = Vec::new();
+        for package in package_manager_guard.packages().iter().filter(|package| package.archive == name || package.to_string() == name) {
+            match manifest_unit().get(&package.to_string()) {
+                Some(manifest) => manifests.push(manifest),
+                None => {
+                    log::warn!("{} was installed without a file list", package.to_string());
+                    manifests.push(ManifestTemp { package : package.to_string(), files : Vec::new() });
+                }
+            }
+        }
+        if manifests.is_empty() {
+            return Err(GlobalError::PackageNotFound(format!("{} is not installed", name)));
+        }
+        return Ok(manifests);
+    }
+    // 哪些包和应用直接依赖这个包，name 为 archive 时查询所有已安装的版本
+    pub fn rdepends (&self, name : &str) -> Result<Vec<ReverseDependencies>,GlobalError>{
+        let configuration_guard = configuration_manager().lock().unwrap();
+        let software_guard = software_manager().lock().unwrap();
+        let softwares = software_guard.find(name);
+        if softwares.is_empty() {
+            return Err(GlobalError::PackageNotFound(format!("{} is not installed", name)));
+        }
+        let mut result = Vec::new();
+        for software in softwares {
+            let package = software.to_string();
+            let mut apps = Vec::new();
+            for configuration in configuration_guard.configurations() {
+                if configuration_guard.current(&configuration.archive)?.dependencies.iter().any(|p| p.to_string() == package) {
+                    apps.push(configuration.archive.clone());
+                }
+            }
+            apps.sort();
+            result.push(ReverseDependencies { dependents: software_guard.rdepends(&package), package, apps });
+        }
+        return Ok(result);
+    }
+    // 包为什么被安装：从应用直接安装的包（包括引导安装的）到这个包的依赖链
+    pub fn why (&self, name : &str) -> Result<Vec<InstallReason>,GlobalError>{
+        let configuration_guard = configuration_manager().lock().unwrap();
+        let software_guard = software_manager().lock().unwrap();
+        let softwares = software_guard.find(name);
+        if softwares.is_empty() {
+            return Err(GlobalError::PackageNotFound(format!("{} is not installed", name)));
+        }
+        let mut result = Vec::new();
+        for software in softwares {
+            let package = software.to_string();
+            let mut chains = Vec::new();
+            for configuration in configuration_guard.configurations() {
+                let lists = match configuration.inner() {
+                    Some(guard) => guard.vec(),
+                    None => return Err(GlobalError::ConfigurationLockFailed),
+                };
+                // 先找当前版本，再从新到旧找保留的版本
+                for (index, list) in lists.iter().enumerate().rev() {
+                    let roots : HashSet<String> = list.dependencies.iter().map(|p| p.to_string()).collect();
+                    if let Some(chain) = software_guard.why(&package, &roots) {
+                        chains.push(DependencyChain { app: configuration.archive.clone(), current: index + 1 == lists.len(), chain });
+                        break;
+                    }
+                }
+            }
+            chains.sort_by(|a, b| a.app.cmp(&b.app));
+            result.push(InstallReason { package, chains });
+        }
+        return Ok(result);
+    }
+    // 依赖图，packages 为空时导出整个系统：所有已安装的包和各应用保留的版本
+    pub fn graph (&self, packages : Vec<(String, VersionMode)>) -> Result<DependencyGraph,GlobalError>{
+        if packages.is_empty() {
+            return self.system_graph();
+        }
+        let mut dependencies = Vec::new();
+        {
+            let package_manager_guard = package_manager().lock().unwrap();
+            for (archive, version_mode) in packages {
+                let version = match version_mode {
+                    VersionMode::Specific(version) => version,
+                    VersionMode::Latest => match package_manager_guard.latest(&archive) {
+                        Some(package) => package.version_wrapper.clone(),
+                        None => return Err(GlobalError::PackageNotFound(format!("{} (run update to refresh the package list)", archive))),
+                    },
+                };
+                dependencies.push(Arc::new(Dependency::new(archive, version)));
+            }
+        }
+        let software_guard = software_manager().lock().unwrap();
+        return Ok(software_guard.resolve_graph(dependencies)?);
+    }
+    fn system_graph (&self) -> Result<DependencyGraph,GlobalError>{
+        let configuration_guard = configuration_manager().lock().unwrap();
+        let software_guard = software_manager().lock().unwrap();
+        let mut graph = software_guard.installed_graph();
+        for configuration in configuration_guard.configurations() {
+            let lists = match configuration.inner() {
+                Some(guard) => guard.vec(),
+                None => return Err(GlobalError::ConfigurationLockFailed),
+            };
+            let app = format!("app:{}", configuration.archive);
+            graph.add_node(GraphNode {
+                id: app.clone(),
+                kind: NodeKind::App,
+                archive: configuration.archive.clone(),
+                version: String::new(),
+                reference_count: None,
+                in_cycle: false,
+            });
+            let current = lists.last().cloned().unwrap_or_else(|| PackageList::new(Vec::new()));
+            for list in lists.iter() {
+                for package in list.dependencies.iter() {
+                    // 当前版本也在用的包只画一条 requires
+                    let relation = if current.dependencies.iter().any(|p| p.to_string() == package.to_string()) {
+                        EdgeRelation::Requires
+                    } else {
+                        EdgeRelation::Keeps
+                    };
+                    graph.add_node(software_guard.graph_node(&Dependency::new(package.archive.clone(), package.version_wrapper.clone())));
+                    graph.add_edge(&app, &package.to_string(), relation);
+                }
+            }
+        }
+        return Ok(graph);
+    }
+    // 解析引导文件，安装系统必带的依赖，记录在 bootstrap 应用下
+    pub fn bootstrap (&self, path : String, dry_run : bool) -> Result<ChangeSummary,GlobalError>{
         // 获取要下载的配置文件
         let bootstrap_config = match profile_handler().analyse_bootstrap_file(path.clone()) {
             Ok(list) => list,
@@ -81,76 +750,496 @@ impl Scheduler {
                 return Err(GlobalError::from(e));
             }
         };
-        let mut software_manager_guard = software_manager().lock().unwrap();
-        match software_manager_guard.install_package(bootstrap_config.get_depends()) {
-            Ok(_) => return Ok(()),
-            Err(e) => {
-                return Err(GlobalError::from(e));
+        let packages = bootstrap_config.get_depends().into_iter()
+            .map(|dep| (dep.archive.clone(), VersionMode::Specific(dep.version_wrapper.clone())))
+            .collect();
+        return self.install(BOOTSTRAP_APP, packages, dry_run);
+    }
+    // 标记已安装的包是手动安装还是作为依赖安装，name 可以是 archive 或 archive-version，返回标记的包
+    pub fn mark (&self, names : &[String], manual : bool) -> Result<Vec<String>,GlobalError>{
+        let software_guard = software_manager().lock().unwrap();
+        let mut marked = Vec::new();
+        for name in names.iter() {
+            let softwares = software_guard.find(name);
+            if softwares.is_empty() {
+                return Err(GlobalError::PackageNotFound(format!("{} is not installed", name)));
+            }
+            marked.extend(softwares.iter().map(|software| software.to_string()));
+        }
+        for name in marked.iter() {
+            software_guard.set_manual(name, manual)?;
+        }
+        software_guard.save()?;
+        return Ok(marked);
+    }
+    // 删除作为依赖安装、手动安装的包和引导安装的包都不再依赖的包，返回被删除的包，模拟时返回会被删除的包
+    // 这些包同时从所有应用保留的版本中去掉，回退时不会再装回来
+    pub fn autoremove (&self, dry_run : bool) -> Result<Vec<String>,GlobalError>{
+        let mut configuration_guard = configuration_manager().lock().unwrap();
+        let mut software_guard = software_manager().lock().unwrap();
+        let bootstrap = match configuration_guard.get(BOOTSTRAP_APP) {
+            Some(configuration) => match configuration.inner() {
+                Some(guard) => guard.vec(),
+                None => return Err(GlobalError::ConfigurationLockFailed),
+            },
+            None => Vec::new(),
+        };
+        let unused = software_guard.unreachable_auto(&self.gc_roots(&bootstrap)?)?;
+        if dry_run || unused.is_empty() {
+            return Ok(unused);
+        }
+        let steps = unused.iter()
+            .map(|name| JournalStep { action : JournalAction::Remove, package : name.clone(), started : false, done : false })
+            .collect();
+        // 先从版本中去掉，它们就成了 GC 根到达不了的包，中断时向前恢复
+        journal_unit()?.begin("autoremove", JournalState::Committed, steps)?;
+        configuration_guard.drop_packages(&unused.iter().cloned().collect())?;
+        configuration_guard.save()?;
+        // 保留的版本都变了，重新生成所有的 profile
+        self.sync_profiles(&configuration_guard, &software_guard, true)?;
+        let removed = software_guard.collect(&self.gc_roots(&configuration_guard.generations()?)?)?;
+        journal_unit()?.finish()?;
+        return Ok(removed);
+    }
+    // 让每个应用保留的每个版本都有 profile，把应用切换到当前版本，删除不再保留的版本的 profile
+    // rebuild 时重新生成已有的 profile
+    fn sync_profiles (&self, configuration_guard : &ConfigurationManager, software_guard : &SoftwareManager, rebuild : bool) -> Result<(),GlobalError>{
+        for configuration in configuration_guard.configurations() {
+            let (lists, generations) = match configuration.inner() {
+                Some(guard) => (guard.vec(), (0..guard.vec.len()).map(|index| guard.generation(index)).collect::<Vec<usize>>()),
+                None => return Err(GlobalError::ConfigurationLockFailed),
+            };
+            for (list, generation) in lists.iter().zip(generations.iter()) {
+                if !rebuild && profile_unit().exists(&configuration.archive, *generation) {
+                    continue;
+                }
+                let roots : Vec<String> = list.dependencies.iter().map(|package| package.to_string()).collect();
+                for file in profile_unit().build(&configuration.archive, *generation, &software_guard.closure(&roots))? {
+                    log::warn!("{} generation {}: {} conflicts with another package, skipped", configuration.archive, generation, file);
+                }
+            }
+            if let Some(current) = generations.last() {
+                profile_unit().switch(&configuration.archive, *current)?;
+            }
+            profile_unit().prune(&configuration.archive, &generations.into_iter().collect())?;
+        }
+        return Ok(());
+    }
+    // GC 根：lists 中的包加上 gc_roots 中列出的包，同一个包出现几次就算几个引用
+    fn gc_roots (&self, lists : &[PackageList]) -> Result<Vec<String>,GlobalError>{
+        let mut roots : Vec<String> = lists.iter()
+            .flat_map(|list| list.dependencies.iter().map(|package| package.to_string()))
+            .collect();
+        let content = match fs::read_to_string(GC_ROOTS_PATH) {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(GlobalError::Io(GC_ROOTS_PATH.to_string(), e)),
+        };
+        for line in content.lines().map(|line| line.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            roots.push(Dependency::from_string(line)?.to_string());
+        }
+        return Ok(roots);
+    }
+    // 垃圾回收，删除从 GC 根到达不了的软件包，模拟时只返回会被删除的包
+    // keep 不为空时先让每个应用只保留最近的 keep 个版本，丢弃的版本无法再回退
+    pub fn garbage_collection (&self, keep : Option<usize>, dry_run : bool) -> Result<GarbageCollection,GlobalError>{
+        let mut configuration_guard = configuration_manager().lock().unwrap();
+        let mut software_guard = software_manager().lock().unwrap();
+        let generations = configuration_guard.generations()?;
+        let lists = match keep {
+            Some(count) => configuration_guard.kept_generations(count)?,
+            None => generations.clone(),
+        };
+        let dropped_generations = generations.len() - lists.len();
+        let roots = self.gc_roots(&lists)?;
+        let unused = software_guard.unreachable(&roots);
+        if dry_run {
+            return Ok(GarbageCollection { removed : unused, dropped_generations });
+        }
+        let steps = unused.iter()
+            .map(|name| JournalStep { action : JournalAction::Remove, package : name.clone(), started : false, done : false })
+            .collect();
+        journal_unit()?.begin("garbage collection", JournalState::Committed, steps)?;
+        if let Some(count) = keep {
+            for configuration in configuration_guard.configurations() {
+                configuration_guard.prune(&configuration.archive, count)?;
             }
+            configuration_guard.save()?;
+            self.sync_profiles(&configuration_guard, &software_guard, false)?;
         }
+        let removed = software_guard.collect(&roots)?;
+        journal_unit()?.finish()?;
+        return Ok(GarbageCollection { removed, dropped_generations });
     }
+    // 交叉检查软件数据、软件包数据、安装目录、文件清单和引用计数
+    // repair 时修复能由其他数据推出的问题，依赖缺失的包需要重新安装
+    pub fn fsck (&self, repair : bool) -> Result<Vec<FsckIssue>,GlobalError>{
+        let configuration_guard = configuration_manager().lock().unwrap();
+        let mut software_guard = software_manager().lock().unwrap();
+        let mut package_manager_guard = package_manager().lock().unwrap();
+        let mut issues = Vec::new();
+        let mut report = |kind : FsckIssueKind, subject : &str, detail : String| {
+            issues.push(FsckIssue { kind, subject : subject.to_string(), detail, repaired : false });
+        };
+        // 加载时重复的记录被后面的覆盖，只能从列表中找出来
+        let softwares : Vec<String> = software_guard.softwares().iter().map(|software| software.to_string()).collect();
+        let packages : Vec<String> = package_manager_guard.packages().iter().map(|package| package.to_string()).collect();
+        let mut seen = HashSet::new();
+        for name in softwares.iter().filter(|name| !seen.insert(name.as_str())) {
+            report(FsckIssueKind::DuplicateSoftware, name, "listed more than once in the software data".to_string());
+        }
+        let mut seen = HashSet::new();
+        for name in packages.iter().filter(|name| !seen.insert(name.as_str())) {
+            report(FsckIssueKind::DuplicatePackage, name, "listed more than once in the package data".to_string());
+        }
+        let installed : HashSet<String> = softwares.iter().cloned().collect();
+        let recorded : HashSet<String> = packages.iter().cloned().collect();
+        for software in software_guard.softwares().iter() {
+            for dependency in software.dependencies.iter().filter(|dependency| !installed.contains(&dependency.to_string())) {
+                report(FsckIssueKind::DanglingDependency, &software.to_string(), format!("depends on {}, which is not installed", dependency.to_string()));
+            }
+        }
+        for configuration in configuration_guard.configurations() {
+            let generations = match configuration.inner() {
+                Some(guard) => (0..guard.vec.len()).map(|index| (guard.generation(index), guard.vec[index].clone())).collect::<Vec<(usize, PackageList)>>(),
+                None => return Err(GlobalError::ConfigurationLockFailed),
+            };
+            for (generation, list) in generations.iter() {
+                for package in list.dependencies.iter().filter(|package| !installed.contains(&package.to_string())) {
+                    report(FsckIssueKind::DanglingRoot, &configuration.archive, format!("generation {} uses {}, which is not installed", generation, package.to_string()));
+                }
+            }
+        }
+        for name in installed.iter().filter(|name| !recorded.contains(*name)) {
+            report(FsckIssueKind::MissingPackageRecord, name, "installed, but has no package record".to_string());
+        }
+        for name in recorded.iter().filter(|name| !installed.contains(*name)) {
+            report(FsckIssueKind::OrphanPackageRecord, name, "has a package record, but is not installed".to_string());
+        }
+        // 只缺软件包记录的包的安装目录和清单不算多余的，补上记录后还要用
+        let known : HashSet<&String> = installed.union(&recorded).collect();
+        for name in package_manager_guard.store_entries()?.into_iter().filter(|name| !known.contains(name)) {
+            report(FsckIssueKind::OrphanStorePath, &format!("{}/{}", STORE_PATH, name), "belongs to no package".to_string());
+        }
+        for name in manifest_unit().recorded().into_iter().filter(|name| !known.contains(name)) {
+            report(FsckIssueKind::OrphanManifest, &name, "file list of a package that is not installed".to_string());
+        }
+        let roots = self.gc_roots(&configuration_guard.generations()?)?;
+        let expected = software_guard.expected_references(&roots);
+        for software in software_guard.softwares().iter() {
+            let count = match software.inner() {
+                Some(guard) => guard.reference_count(),
+                None => return Err(GlobalError::SoftwareLockError(software.archive.clone())),
+            };
+            let wanted = expected.get(&software.to_string()).cloned().unwrap_or(0);
+            if count != wanted {
+                report(FsckIssueKind::WrongReferenceCount, &software.to_string(), format!("reference count is {}, should be {}", count, wanted));
+            }
+        }
+        issues.sort_by(|a, b| a.subject.cmp(&b.subject));
+        if !repair || issues.is_empty() {
+            return Ok(issues);
+        }
+        // 修复前写事务日志，中途中断时下次启动回滚到修复前的数据文件
+        journal_unit()?.begin("repair the data directory", JournalState::Prepared, Vec::new())?;
+        let index = package_manager_guard.index();
+        software_guard.remove_duplicates();
+        package_manager_guard.remove_duplicates();
+        // 一项修复失败时记为没有修复，继续修复其它问题
+        for issue in issues.iter_mut() {
+            let repaired = match issue.kind {
+                FsckIssueKind::DuplicateSoftware | FsckIssueKind::DuplicatePackage => Ok(true),
+                // 从软件源的列表中找回软件包的信息
+                FsckIssueKind::MissingPackageRecord => match index.iter().find(|package| package.to_string() == issue.subject) {
+                    Some(package) => {
+                        package_manager_guard.restore(package.clone());
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                },
+                FsckIssueKind::OrphanPackageRecord => match Dependency::from_string(&issue.subject) {
+                    Ok(dependency) => match package_manager_guard.uninstall_package(dependency.archive, dependency.version_wrapper) {
+                        Ok(_) => Ok(true),
+                        Err(e) => Err(GlobalError::from(e)),
+                    },
+                    Err(e) => Err(GlobalError::from(e)),
+                },
+                FsckIssueKind::OrphanStorePath => {
+                    let path = Path::new(&issue.subject);
+                    let removed = if path.is_dir() && !path.is_symlink() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+                    match removed {
+                        Ok(_) => Ok(true),
+                        Err(e) => Err(GlobalError::Io(issue.subject.clone(), e)),
+                    }
+                }
+                FsckIssueKind::OrphanManifest => match manifest_unit().remove(&issue.subject) {
+                    Ok(_) => Ok(true),
+                    Err(e) => Err(GlobalError::from(e)),
+                },
+                FsckIssueKind::WrongReferenceCount => Ok(true),
+                FsckIssueKind::DanglingDependency | FsckIssueKind::DanglingRoot => Ok(false),
+            };
+            issue.repaired = match repaired {
+                Ok(repaired) => repaired,
+                Err(e) => {
+                    log::warn!("could not repair {}: {}", issue.subject, e);
+                    false
+                }
+            };
+        }
+        package_manager_guard.save()?;
+        // 引用计数由 GC 根和依赖推出，重新计算后保存软件数据
+        software_guard.recompute_references(&roots)?;
+        journal_unit()?.finish()?;
+        return Ok(issues);
+    }
+
+    // 把暂存目录和控制目录打成压缩包，不读写数据目录
+    pub fn build (&self, staging : &Path, control : &Path, output : &Path, lenient : bool) -> Result<BuiltPackage,GlobalError>{
+        match build_unit().build(staging, control, output, lenient) {
+            Ok(built) => return Ok(built),
+            Err(e) => return Err(GlobalError::from(e)),
+        }
+    }
+    // 为目录中的压缩包生成软件源索引，不读写数据目录
+    pub fn index (&self, directory : &Path, key : Option<&Path>) -> Result<RepositoryIndex,GlobalError>{
+        match repository_unit().index(directory, key) {
+            Ok(index) => return Ok(index),
+            Err(e) => return Err(GlobalError::from(e)),
+        }
+    }
+    // 监听 listen 地址，用软件源的接口提供目录中的软件包，不读写数据目录
+    pub fn serve (&self, directory : &Path, listen : &str) -> Result<RepositoryServer,GlobalError>{
+        match RepositoryServer::bind(directory, listen) {
+            Ok(server) => return Ok(server),
+            Err(e) => return Err(GlobalError::from(e)),
+        }
+    }
+    // 清空本地缓存的软件包
+    pub fn clean (&self) -> Result<usize,GlobalError>{
+        let package_manager_guard = package_manager().lock().unwrap();
+        match package_manager_guard.clean_cache() {
+            Ok(count) => return Ok(count),
+            Err(e) => return Err(GlobalError::from(e)),
+        }
+    }
+    // 只清除仓库中已经不再提供的缓存
+    pub fn autoclean (&self) -> Result<usize,GlobalError>{
+        let package_manager_guard = package_manager().lock().unwrap();
+        match package_manager_guard.autoclean_cache() {
+            Ok(count) => return Ok(count),
+            Err(e) => return Err(GlobalError::from(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+    use crate::entity::journal::{JournalAction, JournalState, JournalStep};
+    use crate::entity::dependency::Dependency;
+    use crate::manager::configuration_manager::configuration_manager;
+    use crate::error::global_error::GlobalError;
+    use crate::manager::package_manager::{package_manager, store_path, VersionMode};
+    use crate::manager::software_manager::SOFTWARE_DATA_PATH;
+    use crate::entity::source_config::{CredentialList, NetworkConfiguration, Source, SourceConfiguration};
+    use crate::test::sandbox::{reload, sandbox};
+    use crate::tool::network_module::{use_download_unit, DownloadUnit};
+    use crate::tool::repository_unit::repository_unit;
+    use crate::tool::journal_unit::journal_unit;
+    use crate::tool::manifest_unit::manifest_unit;
+    use crate::tool::profile_unit::profile_unit;
+    use super::scheduler;
 
-    // // 
-    // pub fn download_install (&self, dependency_list: PackageList) -> Result<(),GlobalError>{
-    //     // 检查
-    //     let mut software_guard = software_manager().lock().unwrap();
-    //     let download_list ;
-    //     match  software_guard.check(dependency_list.clone()){
-    //         Ok(list) => download_list = list,
-    //         Err(e) => {
-    //             log::error!("bug");
-    //             return Err(GlobalError::from(e));
-    //         }
-            
-    //     }
-    //     // 下载
-    //     let download_result = software_guard.install(download_list);
-    //     match download_result {
-    //         Ok(_) => {},
-    //         Err(e) => {
-    //             return Err(GlobalError::from(e));
-    //         }
-    //     }
-    //     // 对依赖的引用计数做修改
-    //     match software_guard.update_reference(dependency_list) {
-    //         Ok(_) => return Ok(()),
-    //         Err(e) => return Err(GlobalError::from(e)) 
-    //     }
-    // }
-    // // 更新某指定依赖
-    // pub fn update_dependency(&self, archive : String, mode : ConfigurationUpdateMode) -> Result<(),GlobalError> {
-    //     // 应用更新模式，让Manager进行更新，返回新的Configuration，然后对引用计数作修改
-    //     let mut configuration_guard = configuration_manager().lock().unwrap();
-    //     let new_list = match configuration_guard.update(archive, mode) {
-    //         Ok(l) => l,
-    //         Err(e) => {
-    //             return Err(GlobalError::from(e));
-    //         }
-    //     };
-    //     // 进行下载
-    //     self.download_install(new_list);
-    //     // todo 
-    //     return Ok(());
-    // }
-    // // 垃圾回收
-    // pub fn garbage_collection(&self) -> Result<(),GlobalError>{
-    //     // 调用软件管理器
-    //     let mut guard = software_manager().lock().unwrap();
-    //     let result = guard.garbage_collection();
-    //     // todo 向上输出全局类型的错误
-    //     return Ok(());
-    // }
-    // pub fn bootstrap(&self) -> Result<(),GlobalError>{
-    //     let path = "file/bootfile.txt".to_string();
-    //     // 解析引导文件，获取必要安装软件
-    //     let boot_config = match profile_handler().analyse_bootstrap_file(path) {
-    //         Ok(c) => c,
-    //         Err(e) => {
-    //             return Err(GlobalError::from(e));
-    //         }
-    //     }; 
-    //     // todo 调用上层管理器，由上层管理器获取更详细信息，然后调用底层管理器安装
-    //     return Ok(());
-    // }
-    
-}
\ No newline at end of file
+    fn latest(names: &[&str]) -> Vec<(String, VersionMode)> {
+        return names.iter().map(|name| (name.to_string(), VersionMode::Latest)).collect();
+    }
+    fn installed() -> Vec<String> {
+        return scheduler().list_installed().unwrap().iter().map(|software| format!("{}-{}", software.archive, software.version.to_string())).collect();
+    }
+    fn current(app: &str) -> Vec<String> {
+        return configuration_manager().lock().unwrap().current(app).unwrap().dependencies.iter().map(|package| package.to_string()).collect();
+    }
+    fn store(name: &str) -> String {
+        let dependency = Dependency::from_string(name).unwrap();
+        return store_path(&dependency.archive, &dependency.version_wrapper);
+    }
+    fn step(action: JournalAction, package: &str) -> JournalStep {
+        return JournalStep { action, package: package.to_string(), started: false, done: false };
+    }
+    #[test]
+    fn an_interrupted_removal_is_finished_instead_of_rolled_back() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/a"), ("b-1.0.0", "", "usr/bin/b"), ("c-1.0.0", "", "usr/bin/c")]);
+        scheduler().install("app", latest(&["a", "b"]), false).unwrap();
+        // 没有提交的事务装了一半 c，删除 b 时中断，b 的文件已经删掉了一部分
+        let steps = vec![step(JournalAction::Install, "c-1.0.0"), step(JournalAction::Remove, "b-1.0.0")];
+        journal_unit().unwrap().begin("switch app to a new generation", JournalState::Prepared, steps).unwrap();
+        let c = store("c-1.0.0");
+        fs::create_dir_all(&c).unwrap();
+        journal_unit().unwrap().mark_started(JournalAction::Remove, "b-1.0.0").unwrap();
+        let b = store("b-1.0.0");
+        fs::remove_file(Path::new(&b).join("usr/bin/b")).unwrap();
+        reload();
+
+        assert!(scheduler().recover().unwrap());
+        // c 回滚，b 向前完成删除，a 不受影响
+        assert!(!Path::new(&c).exists());
+        assert!(!Path::new(&b).exists());
+        assert_eq!(installed(), vec!["a-1.0.0".to_string()]);
+        assert!(package_manager().lock().unwrap().get("b-1.0.0").is_none());
+        assert_eq!(current("app"), vec!["a-1.0.0".to_string()]);
+        let profile = Path::new(&profile_unit().link_path("app")).join("usr/bin");
+        assert!(profile.join("a").exists());
+        assert!(!profile.join("b").exists());
+        assert!(journal_unit().unwrap().pending().is_none());
+        assert!(scheduler().fsck(false).unwrap().is_empty());
+    }
+    #[test]
+    fn an_unstarted_removal_is_rolled_back() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/a"), ("b-1.0.0", "", "usr/bin/b")]);
+        scheduler().install("app", latest(&["a", "b"]), false).unwrap();
+        let steps = vec![step(JournalAction::Remove, "b-1.0.0")];
+        journal_unit().unwrap().begin("switch app to a new generation", JournalState::Prepared, steps).unwrap();
+        reload();
+        assert!(scheduler().recover().unwrap());
+        assert_eq!(installed(), vec!["a-1.0.0".to_string(), "b-1.0.0".to_string()]);
+        assert_eq!(current("app"), vec!["a-1.0.0".to_string(), "b-1.0.0".to_string()]);
+    }
+    fn held(result: Result<super::ChangeSummary, GlobalError>) -> bool {
+        match result {
+            Err(err) => return err.kind() == "PackageHeld",
+            Ok(_) => return false,
+        }
+    }
+    #[test]
+    fn held_packages_are_not_upgraded() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/a"), ("a-1.1.0", "", "usr/bin/a"), ("b-1.0.0", "", "usr/bin/b"), ("b-1.1.0", "", "usr/bin/b"), ("c-1.0.0", "a (= 1.1.0)", "usr/bin/c")]);
+        let old = Dependency::from_string("a-1.0.0").unwrap().version_wrapper;
+        scheduler().install("app", vec![("a".to_string(), VersionMode::Specific(old)), ("b".to_string(), VersionMode::Specific(Dependency::from_string("b-1.0.0").unwrap().version_wrapper))], false).unwrap();
+        let holds = scheduler().hold(&["a".to_string()]).unwrap();
+        assert_eq!(holds.len(), 1);
+        assert_eq!(format!("{}-{}", holds[0].archive, holds[0].version.to_string()), "a-1.0.0");
+
+        // 指名升级和依赖要求别的版本都被拒绝，全部升级时跳过锁定的软件
+        assert!(held(scheduler().upgrade("app", &["a".to_string()], false)));
+        assert!(held(scheduler().install("app", latest(&["c"]), false)));
+        assert_eq!(current("app"), vec!["a-1.0.0".to_string(), "b-1.0.0".to_string()]);
+        scheduler().upgrade("app", &[], false).unwrap();
+        assert_eq!(current("app"), vec!["a-1.0.0".to_string(), "b-1.1.0".to_string()]);
+
+        assert!(scheduler().unhold(&["a".to_string()]).unwrap().is_empty());
+        assert!(scheduler().unhold(&["a".to_string()]).is_err());
+        scheduler().upgrade("app", &["a".to_string()], false).unwrap();
+        assert_eq!(current("app"), vec!["a-1.1.0".to_string(), "b-1.1.0".to_string()]);
+    }
+    #[test]
+    fn the_environment_points_at_the_current_profile() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/a"), ("b-1.0.0", "", "usr/lib/libb.so"), ("b-1.1.0", "", "usr/lib/libb.so")]);
+        assert!(matches!(scheduler().environment("app"), Err(GlobalError::ConfigurationNotFound(_))));
+        scheduler().install("app", vec![("a".to_string(), VersionMode::Latest), ("b".to_string(), VersionMode::Specific(Dependency::from_string("b-1.0.0").unwrap().version_wrapper))], false).unwrap();
+        let environment = scheduler().environment("app").unwrap();
+        let profile = std::env::current_dir().unwrap().join(profile_unit().link_path("app"));
+        assert_eq!(environment.profile, profile.display().to_string());
+        let value = |name: &str| environment.variables.iter().find(|variable| variable.name == name).map(|variable| variable.value.clone());
+        assert_eq!(value("PATH"), Some(profile.join("usr/bin").display().to_string()));
+        assert_eq!(value("LD_LIBRARY_PATH"), Some(profile.join("usr/lib").display().to_string()));
+        // profile 中没有的目录不设置
+        assert_eq!(value("MANPATH"), None);
+        // 路径指向应用的链接，升级后不用重新设置
+        scheduler().upgrade("app", &[], false).unwrap();
+        assert_eq!(fs::read_to_string(profile.join("usr/lib/libb.so")).unwrap(), "b-1.1.0\n");
+        assert_eq!(scheduler().environment("app").unwrap().variables, environment.variables);
+    }
+    #[test]
+    fn only_packages_in_the_same_profile_conflict() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/tool"), ("b-1.0.0", "c (= 1.0.0)", "usr/bin/tool"), ("c-1.0.0", "", "usr/lib/libc.so"), ("d-1.0.0", "", "usr/bin/tool")]);
+        scheduler().install("app", latest(&["a"]), false).unwrap();
+        // 另一个应用有自己的 profile，可以提供同一个文件
+        scheduler().install("other", latest(&["d"]), false).unwrap();
+        assert_eq!(fs::read_to_string(Path::new(&profile_unit().link_path("other")).join("usr/bin/tool")).unwrap(), "d-1.0.0\n");
+
+        // 同一个应用中冲突，先装好的依赖 c 也被回滚
+        match scheduler().install("app", latest(&["b"]), false) {
+            Err(GlobalError::FileConflict(package, path, owner)) => {
+                assert_eq!(package, "b-1.0.0");
+                assert_eq!(path, "usr/bin/tool");
+                assert_eq!(owner, "a-1.0.0");
+            }
+            other => panic!("unexpected result {:?}", other.map(|summary| summary.installed)),
+        }
+        assert_eq!(installed(), vec!["a-1.0.0".to_string(), "d-1.0.0".to_string()]);
+        assert!(!Path::new(&store("c-1.0.0")).exists());
+        assert!(manifest_unit().get("c-1.0.0").is_none());
+        assert!(scheduler().fsck(false).unwrap().is_empty());
+    }
+    #[test]
+    fn verify_finds_and_fixes_changed_files() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/a"), ("b-1.0.0", "", "usr/bin/b")]);
+        scheduler().install("app", latest(&["a", "b"]), false).unwrap();
+        assert!(matches!(scheduler().verify(&["c".to_string()], false), Err(GlobalError::PackageNotFound(_))));
+        let file = Path::new(&store("a-1.0.0")).join("usr/bin/a");
+        fs::write(&file, "changed\n").unwrap();
+        let reports = scheduler().verify(&[], false).unwrap();
+        assert_eq!(reports.iter().map(|report| report.broken()).collect::<Vec<bool>>(), vec![true, false]);
+        assert_eq!(reports[0].modified, vec!["usr/bin/a".to_string()]);
+        // 只检查指定的包，按 archive 或 archive-version 指定
+        assert_eq!(scheduler().verify(&["b-1.0.0".to_string()], false).unwrap().len(), 1);
+
+        let reports = scheduler().verify(&["a".to_string()], true).unwrap();
+        assert!(reports[0].fixed);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "a-1.0.0\n");
+        assert!(scheduler().verify(&[], false).unwrap().iter().all(|report| !report.broken()));
+    }
+    #[test]
+    fn a_failed_repair_does_not_stop_the_others() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/a"), ("b-1.0.0", "a (= 1.0.0)", "usr/bin/b")]);
+        scheduler().install("app", latest(&["b"]), false).unwrap();
+        // 引用计数错误，多出一个安装目录，还有一个删不掉的清单
+        let data = fs::read_to_string(SOFTWARE_DATA_PATH).unwrap();
+        fs::write(SOFTWARE_DATA_PATH, data.replace("reference_count = 1", "reference_count = 5")).unwrap();
+        fs::create_dir_all(store("zzz-1.0.0")).unwrap();
+        fs::create_dir_all("database/manifests/aaa-1.0.0.toml").unwrap();
+        reload();
+
+        let issues = scheduler().fsck(true).unwrap();
+        let repaired : Vec<(String, bool)> = issues.iter().map(|issue| (issue.subject.clone(), issue.repaired)).collect();
+        assert_eq!(repaired, vec![
+            ("a-1.0.0".to_string(), true),
+            ("aaa-1.0.0".to_string(), false),
+            ("b-1.0.0".to_string(), true),
+            (store("zzz-1.0.0"), true),
+        ]);
+        assert!(journal_unit().unwrap().pending().is_none());
+        reload();
+        let left : Vec<String> = scheduler().fsck(false).unwrap().iter().map(|issue| issue.subject.clone()).collect();
+        assert_eq!(left, vec!["aaa-1.0.0".to_string()]);
+    }
+    #[test]
+    fn a_package_changed_after_signing_is_not_installed() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/a"), ("b-1.0.0", "", "usr/bin/b")]);
+        // 用密钥重新生成索引，换成要求签名的源
+        let repository = std::env::current_dir().unwrap().join("packages/repository");
+        fs::write("packages/index.key", format!("{}\n", "07".repeat(32))).unwrap();
+        let public_key = repository_unit().index(&repository, Some(Path::new("packages/index.key"))).unwrap().public_key.unwrap();
+        let configuration = SourceConfiguration {
+            sources: vec![Source { name: "signed".to_string(), url: repository.display().to_string(), public_key }],
+            network: NetworkConfiguration::default(),
+            architecture: String::new(),
+        };
+        use_download_unit(DownloadUnit::from_configuration(&configuration, &CredentialList::default()));
+        scheduler().update().unwrap();
+        let information = repository.join("a-1.0.0.toml");
+        let content = fs::read_to_string(&information).unwrap().replace("a-1.0.0 for tests", "a-1.0.0 for attackers");
+        fs::write(&information, content).unwrap();
+        match scheduler().install("app", latest(&["a"]), false) {
+            Err(err) => assert_eq!(err.kind(), "IntegrityError"),
+            Ok(_) => panic!("a changed package was installed"),
+        }
+        assert!(installed().is_empty());
+        scheduler().install("app", latest(&["b"]), false).unwrap();
+        assert_eq!(installed(), vec!["b-1.0.0".to_string()]);
+    }
+}
@@ -1 +1,19 @@
-pub mod test_backend;
\ No newline at end of file
+pub mod test_backend;
+#[cfg(test)]
+mod test_repository;
+#[cfg(test)]
+pub mod sandbox;
+
+// 测试用的临时目录，每个测试用不同的 name，目录在开始时清空
+#[cfg(test)]
+pub fn temp_directory(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("package-manager-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    return path;
+}
+// 仓库中的文件，用绝对路径，不受当前目录影响
+#[cfg(test)]
+pub fn fixture(name: &str) -> std::path::PathBuf {
+    return std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(name);
+}
@@ -0,0 +1,77 @@
+use std::{fs, path::{Path, PathBuf}, sync::{Mutex, MutexGuard}};
+use crate::entity::source_config::{CredentialList, NetworkConfiguration, Source, SourceConfiguration};
+use crate::manager::configuration_manager::configuration_manager;
+use crate::manager::package_manager::{package_manager, PACKAGE_DATA_PATH};
+use crate::manager::software_manager::{software_manager, SOFTWARE_DATA_PATH};
+use crate::scheduler_module::scheduler;
+use crate::tool::build_unit::build_unit;
+use crate::tool::hold_unit::hold_unit;
+use crate::tool::journal_unit::journal_unit;
+use crate::tool::network_module::{use_download_unit, DownloadUnit};
+use crate::tool::repository_unit::repository_unit;
+use super::temp_directory;
+
+// 用到全局管理器的测试：管理器按相对路径读写 database/，这些测试共用一个临时目录作为当前目录，一次只运行一个
+// 第一次使用时切换当前目录，所以其它测试不能使用相对路径，仓库中的文件用 fixture 找
+static SANDBOX: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub struct Sandbox {
+    _guard: MutexGuard<'static, Option<PathBuf>>,
+}
+// 在 directory 中打出一个架构无关的包，name 为 archive-version，包中只有文件 file，内容为 name
+fn build_package(directory: &Path, name: &str, depends: &str, file: &str) {
+    let (archive, version) = name.split_once('-').unwrap();
+    let staging = directory.join(format!("{}-staging", name));
+    let control = directory.join(format!("{}-control", name));
+    let path = staging.join(file);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::create_dir_all(&control).unwrap();
+    fs::write(&path, format!("{}\n", name)).unwrap();
+    let content = format!("Package: {}\nVersion: {}\nArchitecture: all\nDepends: {}\nDescription: {} for tests\n", archive, version, depends, name);
+    fs::write(control.join("control.txt"), content).unwrap();
+    build_unit().build(&staging, &control, &directory.join("repository"), false).unwrap();
+}
+// 清空数据目录，用 packages 建一个本地软件源并刷新软件包列表
+// packages 中每一项为 (archive-version, Depends, 包中的文件)
+pub fn sandbox(packages: &[(&str, &str, &str)]) -> Sandbox {
+    let mut guard = SANDBOX.lock().unwrap_or_else(|e| e.into_inner());
+    if guard.is_none() {
+        let directory = temp_directory("sandbox");
+        std::env::set_current_dir(&directory).unwrap();
+        *guard = Some(directory);
+    }
+    let directory = guard.clone().unwrap();
+    for path in ["database", "packages"] {
+        let _ = fs::remove_dir_all(directory.join(path));
+    }
+    fs::create_dir_all(directory.join("packages")).unwrap();
+    fs::create_dir_all(directory.join("database")).unwrap();
+    fs::write(PACKAGE_DATA_PATH, "packages = []\n").unwrap();
+    fs::write(SOFTWARE_DATA_PATH, "software_temp = []\n").unwrap();
+    for (name, depends, file) in packages.iter() {
+        build_package(&directory.join("packages"), name, depends, file);
+    }
+    // build 输出到 packages/repository
+    let repository = directory.join("packages").join("repository");
+    if packages.is_empty() {
+        fs::create_dir_all(&repository).unwrap();
+    }
+    repository_unit().index(&repository, None).unwrap();
+    let configuration = SourceConfiguration {
+        sources: vec![Source { name: "sandbox".to_string(), url: repository.display().to_string(), public_key: String::new() }],
+        network: NetworkConfiguration::default(),
+        architecture: String::new(),
+    };
+    use_download_unit(DownloadUnit::from_configuration(&configuration, &CredentialList::default()));
+    reload();
+    scheduler().update().unwrap();
+    return Sandbox { _guard: guard };
+}
+// 重新读取数据文件，模拟进程重新启动
+pub fn reload() {
+    configuration_manager().lock().unwrap().reload();
+    software_manager().lock().unwrap().reload();
+    package_manager().lock().unwrap().reload();
+    journal_unit().unwrap().reload().unwrap();
+    hold_unit().unwrap().reload().unwrap();
+}
@@ -0,0 +1,144 @@
+use std::{fs, path::{Path, PathBuf}, sync::Arc, thread};
+use crate::entity::dependency::Dependency;
+use crate::entity::source_config::{CredentialList, NetworkConfiguration, Source, SourceConfiguration};
+use crate::manager::package_manager::PackageManagerError;
+use crate::tool::build_unit::build_unit;
+use crate::tool::cache_unit::sha256_hex;
+use crate::tool::network_module::{decompress_unit, DownloadUnit};
+use crate::tool::repository_unit::repository_unit;
+use crate::tool::server_module::RepositoryServer;
+use crate::tool::transport::{HttpTransport, Transport};
+use super::temp_directory;
+
+// 打包、生成索引，再像客户端一样从软件源读取
+
+// 在 directory 下准备一个包的暂存目录和控制目录，返回 (暂存目录, 控制目录)
+pub fn prepare_package(directory: &Path, archive: &str, architecture: &str, depends: &str) -> (PathBuf, PathBuf) {
+    let staging = directory.join(format!("{}-{}-staging", archive, architecture));
+    let control = directory.join(format!("{}-{}-control", archive, architecture));
+    fs::create_dir_all(staging.join("usr/bin")).unwrap();
+    fs::create_dir_all(&control).unwrap();
+    fs::write(staging.join("usr/bin").join(archive), format!("#!/bin/sh\necho {}\n", archive)).unwrap();
+    let content = format!("Package: {}\nVersion: 1.0.0\nArchitecture: {}\nDepends: {}\nDescription: {} for tests\n", archive, architecture, depends, archive);
+    fs::write(control.join("control.txt"), content).unwrap();
+    return (staging, control);
+}
+// 在 repository 中打出 hello 和它依赖的 libgreet，用 key 签名索引，返回公钥
+pub fn prepare_repository(work: &Path, repository: &Path) -> String {
+    fs::create_dir_all(repository).unwrap();
+    for (archive, depends) in [("hello", "libgreet (= 1.0.0), adduser"), ("libgreet", "")] {
+        let (staging, control) = prepare_package(work, archive, "x86_64", depends);
+        build_unit().build(&staging, &control, repository, true).unwrap();
+    }
+    let key = work.join("index.key");
+    fs::write(&key, format!("{}\n", hex::encode([7u8; 32]))).unwrap();
+    let index = repository_unit().index(repository, Some(&key)).unwrap();
+    assert_eq!(index.packages, vec!["hello-1.0.0".to_string(), "libgreet-1.0.0".to_string()]);
+    return index.public_key.unwrap();
+}
+fn client(url: &str, public_key: &str) -> DownloadUnit {
+    let configuration = SourceConfiguration {
+        sources: vec![Source { name: "test".to_string(), url: url.to_string(), public_key: public_key.to_string() }],
+        network: NetworkConfiguration::default(),
+        architecture: String::new(),
+    };
+    return DownloadUnit::from_configuration(&configuration, &CredentialList::default());
+}
+// 客户端读取索引、依赖、详细信息并下载解压 hello
+pub fn consume(url: &str, public_key: &str, target: &Path) {
+    let client = client(url, public_key);
+    let index = client.get_index().unwrap();
+    let names: Vec<String> = index.iter().map(|package| package.to_string()).collect();
+    assert_eq!(names, vec!["hello-1.0.0".to_string(), "libgreet-1.0.0".to_string()]);
+    assert!(index.iter().all(|package| package.source == url && package.size > 0 && package.sha256.len() == 64));
+
+    let hello = Arc::new(Dependency::from_string("hello-1.0.0").unwrap());
+    let dependencies: Vec<String> = client.get_dependency_list(hello.clone()).unwrap().iter().map(|dependency| dependency.to_string()).collect();
+    assert_eq!(dependencies, vec!["libgreet-1.0.0".to_string()]);
+    let package = client.get_package_information(hello).unwrap();
+    assert_eq!(package.sha256, index[0].sha256);
+    assert_eq!(package.architecture, "x86_64");
+
+    let data = client.download_software(package.clone()).unwrap();
+    assert_eq!(data.len() as u64, package.size);
+    assert_eq!(sha256_hex(&data), package.sha256);
+    let target = target.display().to_string();
+    decompress_unit().install(decompress_unit().decode(data).unwrap(), &target).unwrap();
+    assert_eq!(fs::read_to_string(Path::new(&target).join("usr/bin/hello")).unwrap(), "#!/bin/sh\necho hello\n");
+    // 元数据不解压到安装目录
+    assert!(!Path::new(&target).join(".package").exists());
+}
+
+#[test]
+fn built_packages_are_served_from_a_signed_index() {
+    let work = temp_directory("repository-round-trip");
+    let repository = work.join("repository");
+    let public_key = prepare_repository(&work, &repository);
+    consume(&repository.display().to_string(), &public_key, &work.join("target"));
+}
+#[test]
+fn a_changed_index_is_refused() {
+    let work = temp_directory("repository-tampered");
+    let repository = work.join("repository");
+    let public_key = prepare_repository(&work, &repository);
+    let index = repository.join("index.toml");
+    let content = fs::read_to_string(&index).unwrap().replace("for tests", "for attackers");
+    fs::write(&index, content).unwrap();
+    assert!(matches!(client(&repository.display().to_string(), &public_key).get_index(), Err(PackageManagerError::IntegrityError(_, _))));
+    // 不要求签名的源照常使用
+    assert_eq!(client(&repository.display().to_string(), "").get_index().unwrap().len(), 2);
+}
+#[test]
+fn changed_package_files_are_refused() {
+    let work = temp_directory("repository-package-tampered");
+    let repository = work.join("repository");
+    let public_key = prepare_repository(&work, &repository);
+    // 单个包的文件没有签名，改掉压缩包的 sha256 和依赖列表
+    let information = repository.join("hello-1.0.0.toml");
+    let content = fs::read_to_string(&information).unwrap();
+    let sha256 = content.lines().find(|line| line.starts_with("sha256")).unwrap().to_string();
+    fs::write(&information, content.replace(&sha256, &format!("sha256 = \"{}\"", sha256_hex(b"another archive")))).unwrap();
+    fs::write(repository.join("hello-1.0.0.txt"), "dependencies = []\n").unwrap();
+    let hello = Arc::new(Dependency::from_string("hello-1.0.0").unwrap());
+    let signed = client(&repository.display().to_string(), &public_key);
+    assert!(matches!(signed.get_package_information(hello.clone()), Err(PackageManagerError::IntegrityError(location, _)) if location == information.display().to_string()));
+    assert!(signed.get_dependency_list(hello.clone()).is_err());
+    // 没改过的包照常使用
+    let libgreet = Arc::new(Dependency::from_string("libgreet-1.0.0").unwrap());
+    assert!(signed.get_package_information(libgreet.clone()).is_ok());
+    assert!(signed.get_dependency_list(libgreet).unwrap().is_empty());
+    // 不要求签名的源不检查
+    assert!(client(&repository.display().to_string(), "").get_package_information(hello).is_ok());
+}
+#[test]
+fn one_version_for_two_architectures_is_refused() {
+    let work = temp_directory("repository-architectures");
+    let repository = work.join("repository");
+    fs::create_dir_all(&repository).unwrap();
+    let (staging, control) = prepare_package(&work, "hello", "x86_64", "");
+    build_unit().build(&staging, &control, &repository, false).unwrap();
+    // 打出的文件名相同，另一个架构的包放在别处再改名放进来
+    let other = work.join("other");
+    let (staging, control) = prepare_package(&work, "hello", "aarch64", "");
+    let built = build_unit().build(&staging, &control, &other, false).unwrap();
+    fs::copy(&built.path, repository.join("hello-1.0.0-aarch64.tar.gz")).unwrap();
+    match repository_unit().index(&repository, None) {
+        Err(PackageManagerError::DuplicatePackage(_, package)) => assert_eq!(package, "hello-1.0.0"),
+        other => panic!("unexpected result {:?}", other.map(|index| index.packages)),
+    }
+}
+#[test]
+fn built_packages_are_served_over_http() {
+    let work = temp_directory("repository-server");
+    let repository = work.join("repository");
+    let public_key = prepare_repository(&work, &repository);
+    let server = RepositoryServer::bind(&repository, "127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.address());
+    thread::spawn(move || server.run());
+    consume(&url, &public_key, &work.join("target"));
+    // 源中没有的软件包返回 404
+    let transport = HttpTransport::new(&url, NetworkConfiguration::default(), None);
+    let missing = Dependency::from_string("missing-1.0.0").unwrap();
+    assert!(matches!(transport.fetch_package_information(&missing), Err(e) if e.is_not_found()));
+    assert!(matches!(transport.fetch_dependency_list(&missing), Err(e) if e.is_not_found()));
+}
@@ -0,0 +1,124 @@
+use std::{env, fs, path::Path, sync::RwLock};
+use lazy_static::lazy_static;
+use crate::error::global_error::GlobalError;
+use crate::manager::package_manager::PACKAGE_DATA_PATH;
+use crate::manager::software_manager::SOFTWARE_DATA_PATH;
+use super::network_module::download_unit;
+use super::resolve_file::profile_handler;
+
+// 架构组件，决定安装哪个架构的软件包
+// 优先级：命令行 --arch，环境变量 PACKAGE_MANAGER_ARCH，配置文件的 architecture，本机架构
+lazy_static! {
+    static ref ARCHITECTURE: RwLock<Option<String>> = RwLock::new(None);
+}
+// 所有架构都能使用的软件包
+const ARCHITECTURE_INDEPENDENT: [&str; 2] = ["all", "any"];
+// sysroot 中记录安装时使用的架构的文件
+const SYSROOT_ARCHITECTURE_PATH: &str = "database/architecture";
+
+// 本机架构，使用软件包中的命名方式
+pub fn host_architecture() -> &'static str {
+    match env::consts::ARCH {
+        "x86_64" => "amd64",
+        "x86" => "i386",
+        "aarch64" => "arm64",
+        "arm" => "armhf",
+        "loongarch64" => "loong64",
+        "powerpc64" => "ppc64el",
+        other => other,
+    }
+}
+// 当前安装的架构
+pub fn architecture() -> String {
+    if let Some(arch) = ARCHITECTURE.read().unwrap().as_ref() {
+        return arch.clone();
+    }
+    if let Ok(arch) = env::var("PACKAGE_MANAGER_ARCH") {
+        if !arch.is_empty() {
+            return arch;
+        }
+    }
+    // 配置文件有错时先按本机架构，错误在访问软件源时报告
+    if let Ok(unit) = download_unit() {
+        if !unit.architecture().is_empty() {
+            return unit.architecture().to_string();
+        }
+    }
+    return host_architecture().to_string();
+}
+// 用命令行参数覆盖架构
+pub fn set_architecture(arch: &str) {
+    *ARCHITECTURE.write().unwrap() = Some(arch.to_string());
+}
+// 软件包能否安装到当前架构，没有标注架构的包视为与架构无关
+pub fn is_compatible(package_architecture: &str) -> bool {
+    if package_architecture.is_empty() || ARCHITECTURE_INDEPENDENT.contains(&package_architecture) {
+        return true;
+    }
+    return package_architecture == architecture();
+}
+// 切换到 sysroot，之后的数据目录和安装目录都在 sysroot 下
+// 软件源配置仍然使用本机的，arch 为空时使用 sysroot 上次记录的架构
+pub fn enter_sysroot(sysroot: &str, arch: Option<&str>) -> Result<(), GlobalError> {
+    // 先读取本机的软件源配置
+    if let Err(e) = download_unit() {
+        return Err(GlobalError::from(e));
+    }
+    if let Err(e) = fs::create_dir_all(Path::new(sysroot).join("database")) {
+        return Err(GlobalError::Io(sysroot.to_string(), e));
+    }
+    if let Err(e) = env::set_current_dir(sysroot) {
+        return Err(GlobalError::Io(sysroot.to_string(), e));
+    }
+    // 新的 sysroot 还没有数据文件
+    for (path, content) in [(PACKAGE_DATA_PATH, "packages = []\n"), (SOFTWARE_DATA_PATH, "software_temp = []\n")] {
+        if Path::new(path).exists() {
+            continue;
+        }
+        if let Err(e) = profile_handler().write_local_file(path, content) {
+            return Err(GlobalError::from(e));
+        }
+    }
+    match arch {
+        Some(arch) => {
+            if let Err(e) = profile_handler().write_local_file(SYSROOT_ARCHITECTURE_PATH, &format!("{}\n", arch)) {
+                return Err(GlobalError::from(e));
+            }
+            set_architecture(arch);
+        }
+        None => {
+            if let Ok(content) = fs::read_to_string(SYSROOT_ARCHITECTURE_PATH) {
+                let arch = content.trim();
+                if !arch.is_empty() {
+                    set_architecture(arch);
+                }
+            }
+        }
+    }
+    log::info!("using sysroot {} for {}", sysroot, architecture());
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{host_architecture, is_compatible};
+
+    #[test]
+    fn the_host_uses_package_architecture_names() {
+        let expected = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            other => other,
+        };
+        assert_eq!(host_architecture(), expected);
+    }
+    #[test]
+    fn independent_packages_fit_every_architecture() {
+        assert!(is_compatible("all"));
+        assert!(is_compatible("any"));
+        // 没有标注架构的旧软件包
+        assert!(is_compatible(""));
+        assert!(is_compatible(host_architecture()));
+        assert!(!is_compatible("not-this-machine"));
+    }
+}
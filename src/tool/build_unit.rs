@@ -0,0 +1,446 @@
+use std::{fs, io::Write, os::unix::fs::PermissionsExt, path::{Path, PathBuf}, str::FromStr, sync::Arc};
+use flate2::{write::GzEncoder, Compression};
+use lazy_static::lazy_static;
+use semver::Version;
+use serde_derive::Serialize;
+use tar::{EntryType, Header};
+use crate::entity::dependency::{DependencyListTemp, Package};
+use crate::entity::environment::EnvironmentVariable;
+use crate::entity::manifest::{FileEntry, FileKind, ManifestTemp};
+use crate::entity::version_wrapper::VersionWrapper;
+use crate::manager::package_manager::PackageManagerError;
+use super::cache_unit::sha256_hex;
+
+// 打包组件，把暂存目录和控制目录打成软件源使用的压缩包
+// 控制目录的格式见 package_example：control.txt 和 preinst、postinst、prerm、postrm 四个脚本，脚本可以没有
+// 压缩包是 gzip 压缩的 tar，开头是 .package/软件名/ 下的元数据，后面是暂存目录中的文件：
+//   package.toml       软件包的详细信息，格式同软件源中的 archive-version.toml
+//   dependencies.toml  依赖列表，格式同软件源中的 archive-version.txt
+//   manifest.toml      暂存目录中的文件清单
+//   checksum           manifest.toml 的 sha256
+lazy_static! {
+    static ref BUILD_UNIT: Arc<BuildUnit> = Arc::new(BuildUnit::new());
+}
+#[inline(always)]
+#[allow(dead_code)]
+pub fn build_unit() -> &'static Arc<BuildUnit> {
+    &BUILD_UNIT
+}
+// 压缩包中存放元数据的目录
+pub const METADATA_DIRECTORY: &str = ".package";
+// 压缩包中的路径是否属于元数据目录
+pub fn is_metadata(path: &str) -> bool {
+    let path = path.trim_start_matches("./");
+    return path == METADATA_DIRECTORY || path.starts_with(&format!("{}/", METADATA_DIRECTORY));
+}
+// 维护脚本，控制目录中的文件名为 脚本名.txt
+const SCRIPTS: [&str; 4] = ["preinst", "postinst", "prerm", "postrm"];
+// 这个包管理器不处理的 control 字段，有值时打包失败，宽松模式下略去
+const UNSUPPORTED_FIELDS: [&str; 7] = ["Pre-Depends", "Recommends", "Suggests", "Breaks", "Conflicts", "Replaces", "Provides"];
+
+pub struct BuildUnit {}
+// 打好的包
+#[derive(Debug, Clone, Serialize)]
+pub struct BuiltPackage {
+    // archive-version
+    pub package: String,
+    pub path: String,
+    // 压缩包的大小和 sha256
+    pub size: u64,
+    pub sha256: String,
+    pub installed_size: u64,
+    pub files: usize,
+    // 宽松模式下略去的依赖和字段
+    pub dropped: Vec<String>,
+}
+// 要放进压缩包的一个文件
+struct StagedFile {
+    entry: FileEntry,
+    data: Vec<u8>,
+}
+fn invalid(path: &Path, reason: String) -> PackageManagerError {
+    return PackageManagerError::InvalidControl(path.display().to_string(), reason);
+}
+fn io_error(path: &Path, e: std::io::Error) -> PackageManagerError {
+    return PackageManagerError::Io(path.display().to_string(), e);
+}
+// 软件名只能由小写字母、数字和 + . _ 组成，不能有 -，否则 archive-version 无法拆开
+fn valid_archive(name: &str) -> bool {
+    return name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '+' | '.' | '_'));
+}
+// 解析 control 文件：Key: Value，# 开头的是注释，空白开头的行接在上一个字段后面
+fn parse_fields(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            match fields.last_mut() {
+                Some((_, value)) => {
+                    value.push('\n');
+                    value.push_str(line.trim());
+                }
+                None => return Err(format!("line {}: continuation without a field", number + 1)),
+            }
+            continue;
+        }
+        match line.split_once(':') {
+            Some((key, value)) => {
+                let key = key.trim();
+                if fields.iter().any(|(existing, _)| existing.eq_ignore_ascii_case(key)) {
+                    return Err(format!("line {}: {} is given twice", number + 1, key));
+                }
+                fields.push((key.to_string(), value.trim().to_string()));
+            }
+            None => return Err(format!("line {}: expected Key: Value", number + 1)),
+        }
+    }
+    return Ok(fields);
+}
+// Depends 中的一项，只支持确定的版本：name (= version) 或 name-version
+// 其他 dpkg 写法(可选的依赖 a | b、>= 等版本范围、不带版本的包名)无法对应到一个包，返回 None，由调用方报错或在宽松模式下略去
+fn parse_dependency(item: &str) -> Result<Option<String>, String> {
+    if item.contains('|') {
+        return Ok(None);
+    }
+    let (name, version) = match item.split_once('(') {
+        Some((name, relation)) => {
+            let relation = match relation.trim().strip_suffix(')') {
+                Some(r) => r.trim(),
+                None => return Err(format!("dependency {} has an unclosed version relation", item)),
+            };
+            // = 之外的关系是版本范围
+            match relation.strip_prefix('=') {
+                Some(version) if !version.starts_with('=') => (name.trim(), version.trim()),
+                _ => return Ok(None),
+            }
+        }
+        None => match item.split_once('-') {
+            // 包名可以有 -，只有后半部分是版本号时才当作 name-version
+            Some((name, version)) if Version::from_str(version).is_ok() => (name, version),
+            _ => return Ok(None),
+        },
+    };
+    if !valid_archive(name) {
+        return Err(format!("dependency {} has an invalid package name", item));
+    }
+    if let Err(e) = Version::from_str(version) {
+        return Err(format!("dependency {}: {}", item, e));
+    }
+    return Ok(Some(format!("{}-{}", name, version)));
+}
+// 读出暂存目录 root 下 directory 中的文件，按路径排列
+fn stage(root: &Path, directory: &Path, files: &mut Vec<StagedFile>) -> Result<(), PackageManagerError> {
+    let mut entries = Vec::new();
+    match fs::read_dir(directory) {
+        Ok(list) => for entry in list {
+            match entry {
+                Ok(e) => entries.push(e.path()),
+                Err(e) => return Err(io_error(directory, e)),
+            }
+        },
+        Err(e) => return Err(io_error(directory, e)),
+    }
+    entries.sort();
+    for path in entries {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(m) => m,
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        let relative = match path.strip_prefix(root) {
+            Ok(r) => r.display().to_string(),
+            Err(_) => continue,
+        };
+        if relative == METADATA_DIRECTORY {
+            return Err(invalid(&path, format!("{} is reserved for package metadata", METADATA_DIRECTORY)));
+        }
+        let mode = metadata.permissions().mode() & 0o7777;
+        let file_type = metadata.file_type();
+        let mut entry = FileEntry { path: relative, kind: FileKind::Other, mode, size: 0, hash: String::new(), target: None };
+        let mut data = Vec::new();
+        if file_type.is_file() {
+            data = match fs::read(&path) {
+                Ok(d) => d,
+                Err(e) => return Err(io_error(&path, e)),
+            };
+            entry.kind = FileKind::File;
+            entry.size = data.len() as u64;
+            entry.hash = sha256_hex(&data);
+        } else if file_type.is_symlink() {
+            entry.kind = FileKind::Symlink;
+            entry.target = match fs::read_link(&path) {
+                Ok(target) => Some(target.display().to_string()),
+                Err(e) => return Err(io_error(&path, e)),
+            };
+        } else if file_type.is_dir() {
+            entry.kind = FileKind::Directory;
+        } else {
+            return Err(invalid(&path, "only regular files, directories and symbolic links can be packaged".to_string()));
+        }
+        let directory = entry.kind == FileKind::Directory;
+        files.push(StagedFile { entry, data });
+        if directory {
+            stage(root, &path, files)?;
+        }
+    }
+    return Ok(());
+}
+// 把文件写进压缩包，时间和属主固定，同样的输入得到同样的压缩包
+fn append<W: Write>(builder: &mut tar::Builder<W>, file: &StagedFile) -> Result<(), std::io::Error> {
+    let mut header = Header::new_gnu();
+    header.set_mode(file.entry.mode);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    match file.entry.kind {
+        FileKind::Directory => {
+            header.set_entry_type(EntryType::Directory);
+            header.set_size(0);
+            return builder.append_data(&mut header, format!("{}/", file.entry.path), std::io::empty());
+        }
+        FileKind::Symlink => {
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            let target = file.entry.target.clone().unwrap_or_default();
+            return builder.append_link(&mut header, &file.entry.path, target);
+        }
+        _ => {
+            header.set_entry_type(EntryType::Regular);
+            header.set_size(file.data.len() as u64);
+            return builder.append_data(&mut header, &file.entry.path, file.data.as_slice());
+        }
+    }
+}
+// 元数据目录中的一个文件
+fn metadata_file(archive: &str, name: &str, mode: u32, data: Vec<u8>) -> StagedFile {
+    let entry = FileEntry {
+        path: format!("{}/{}/{}", METADATA_DIRECTORY, archive, name),
+        kind: FileKind::File,
+        mode,
+        size: data.len() as u64,
+        hash: sha256_hex(&data),
+        target: None,
+    };
+    return StagedFile { entry, data };
+}
+fn metadata_directory(path: String) -> StagedFile {
+    let entry = FileEntry { path, kind: FileKind::Directory, mode: 0o755, size: 0, hash: String::new(), target: None };
+    return StagedFile { entry, data: Vec::new() };
+}
+// 压缩包中软件 archive 的元数据文件 name 的内容
+fn to_toml<T: serde::Serialize>(archive: &str, name: &str, value: &T) -> Result<Vec<u8>, PackageManagerError> {
+    match toml::to_string(value) {
+        Ok(content) => return Ok(content.into_bytes()),
+        Err(e) => return Err(PackageManagerError::WriteLocalFileError(format!("{}/{}/{}", METADATA_DIRECTORY, archive, name), e)),
+    }
+}
+impl BuildUnit {
+    pub fn new() -> BuildUnit {
+        return BuildUnit {};
+    }
+    // 读取并检查控制目录中的 control.txt，返回软件包信息、依赖列表和略去的依赖和字段
+    // 有无法保留的依赖或字段时报错，lenient 时略去它们
+    pub fn read_control(&self, control: &Path, lenient: bool) -> Result<(Package, Vec<String>, Vec<String>), PackageManagerError> {
+        let path = control.join("control.txt");
+        let content = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        let fields = match parse_fields(&content) {
+            Ok(f) => f,
+            Err(reason) => return Err(invalid(&path, reason)),
+        };
+        let field = |key: &str| -> Option<String> {
+            return fields.iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.clone())
+                .filter(|value| !value.is_empty());
+        };
+        let required = |key: &str| -> Result<String, PackageManagerError> {
+            return field(key).ok_or_else(|| invalid(&path, format!("{} is required", key)));
+        };
+        let archive = required("Package")?;
+        if !valid_archive(&archive) {
+            return Err(invalid(&path, format!("invalid package name {}, use lowercase letters, digits, '+', '.' and '_'", archive)));
+        }
+        let version = match Version::from_str(&required("Version")?) {
+            Ok(v) => v,
+            Err(e) => return Err(invalid(&path, format!("Version is not a semantic version: {}", e))),
+        };
+        let architecture = required("Architecture")?;
+        if !architecture.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(invalid(&path, format!("invalid architecture {}", architecture)));
+        }
+        let mut dependencies = Vec::new();
+        let mut dropped = Vec::new();
+        for item in field("Depends").unwrap_or_default().split(',').map(|item| item.trim()).filter(|item| !item.is_empty()) {
+            match parse_dependency(item) {
+                Ok(Some(d)) => dependencies.push(d),
+                Ok(None) => dropped.push(format!("Depends: {}", item)),
+                Err(reason) => return Err(invalid(&path, reason)),
+            }
+        }
+        let environment: Vec<String> = field("Environment").unwrap_or_default().lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        for entry in environment.iter() {
+            if EnvironmentVariable::parse(entry, "").is_none() {
+                return Err(invalid(&path, format!("invalid environment entry {}, use NAME=VALUE or NAME+=VALUE", entry)));
+            }
+        }
+        for key in UNSUPPORTED_FIELDS.iter() {
+            if let Some(value) = field(key) {
+                dropped.push(format!("{}: {}", key, value));
+            }
+        }
+        if !dropped.is_empty() && !lenient {
+            return Err(invalid(&path, format!("cannot keep {}; depend on exact versions as name (= version), or build with --lenient to leave these out", dropped.join("; "))));
+        }
+        let origin = field("Origin").unwrap_or_else(|| "local".to_string());
+        let mut package = Package::new(
+            archive.clone(),
+            VersionWrapper::new(version.clone()),
+            field("Section").unwrap_or_else(|| "main".to_string()),
+            origin.clone(),
+            field("Label").unwrap_or(origin),
+            architecture,
+            format!("{}-{}.tar.gz", archive, version),
+            field("Description").unwrap_or_default(),
+        );
+        package.environment = environment;
+        return Ok((package, dependencies, dropped));
+    }
+    // 打包，staging 是安装后的目录结构，control 是控制目录，压缩包写到 output 目录
+    // lenient 时略去无法保留的依赖和字段，在结果中列出
+    pub fn build(&self, staging: &Path, control: &Path, output: &Path, lenient: bool) -> Result<BuiltPackage, PackageManagerError> {
+        let (mut package, dependencies, dropped) = self.read_control(control, lenient)?;
+        if !staging.is_dir() {
+            return Err(invalid(staging, "the staging directory does not exist".to_string()));
+        }
+        let mut payload = Vec::new();
+        stage(staging, staging, &mut payload)?;
+        package.installed_size = payload.iter().map(|file| file.entry.size).sum();
+        let archive = package.archive.clone();
+        let manifest = to_toml(&package.archive, "manifest.toml", &ManifestTemp { package: package.to_string(), files: payload.iter().map(|file| file.entry.clone()).collect() })?;
+        let checksum = format!("{}\n", sha256_hex(&manifest)).into_bytes();
+        let mut metadata = vec![
+            metadata_directory(METADATA_DIRECTORY.to_string()),
+            metadata_directory(format!("{}/{}", METADATA_DIRECTORY, archive)),
+            metadata_file(&archive, "package.toml", 0o644, to_toml(&archive, "package.toml", &package)?),
+            metadata_file(&archive, "dependencies.toml", 0o644, to_toml(&archive, "dependencies.toml", &DependencyListTemp { dependencies })?),
+            metadata_file(&archive, "manifest.toml", 0o644, manifest),
+            metadata_file(&archive, "checksum", 0o644, checksum),
+        ];
+        for script in SCRIPTS.iter() {
+            let path = control.join(format!("{}.txt", script));
+            match fs::read(&path) {
+                Ok(data) => metadata.push(metadata_file(&archive, script, 0o755, data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+                Err(e) => return Err(io_error(&path, e)),
+            }
+        }
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for file in metadata.iter().chain(payload.iter()) {
+            if let Err(e) = append(&mut builder, file) {
+                return Err(io_error(Path::new(&file.entry.path), e));
+            }
+        }
+        let data = match builder.into_inner().and_then(|encoder| encoder.finish()) {
+            Ok(d) => d,
+            Err(e) => return Err(io_error(output, e)),
+        };
+        if let Err(e) = fs::create_dir_all(output) {
+            return Err(io_error(output, e));
+        }
+        let path: PathBuf = output.join(&package.download);
+        if let Err(e) = fs::write(&path, &data) {
+            return Err(io_error(&path, e));
+        }
+        return Ok(BuiltPackage {
+            package: package.to_string(),
+            path: path.display().to_string(),
+            size: data.len() as u64,
+            sha256: sha256_hex(&data),
+            installed_size: package.installed_size,
+            files: payload.len(),
+            dropped,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test::fixture;
+    use crate::manager::package_manager::PackageManagerError;
+    use super::{build_unit, parse_dependency, parse_fields};
+
+    fn fields(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        return list.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+    }
+    #[test]
+    fn fields_are_read_in_order() {
+        let content = "# comment\nPackage: hello\nVersion: 1.0.0\n\nDescription: greets\n the world\n\tand more\nConflicts:\n";
+        assert_eq!(parse_fields(content).unwrap(), fields(&[
+            ("Package", "hello"), ("Version", "1.0.0"), ("Description", "greets\nthe world\nand more"), ("Conflicts", ""),
+        ]));
+        // 值中的冒号保留
+        assert_eq!(parse_fields("Homepage: http://example.com").unwrap(), fields(&[("Homepage", "http://example.com")]));
+    }
+    #[test]
+    fn malformed_fields_are_rejected() {
+        assert_eq!(parse_fields(" dangling").unwrap_err(), "line 1: continuation without a field");
+        assert_eq!(parse_fields("Package: a\nno colon").unwrap_err(), "line 2: expected Key: Value");
+        assert_eq!(parse_fields("Package: a\npackage: b").unwrap_err(), "line 2: package is given twice");
+    }
+    #[test]
+    fn exact_dependencies_are_kept() {
+        assert_eq!(parse_dependency("libc (= 2.34.0)"), Ok(Some("libc-2.34.0".to_string())));
+        assert_eq!(parse_dependency("libc(=2.34.0)"), Ok(Some("libc-2.34.0".to_string())));
+        assert_eq!(parse_dependency("libc-2.34.0"), Ok(Some("libc-2.34.0".to_string())));
+    }
+    #[test]
+    fn other_dependencies_are_left_out() {
+        for item in ["adduser", "ubuntu-keyring", "gpgv | gpgv2 | gpgv1", "libc6 (>= 2.34)", "dpkg (<< 1.17.2)", "libc (== 2.34.0)"] {
+            assert_eq!(parse_dependency(item), Ok(None), "{}", item);
+        }
+    }
+    #[test]
+    fn malformed_dependencies_are_rejected() {
+        assert!(parse_dependency("libc (= 2.34.0").is_err());
+        assert!(parse_dependency("libc (= 2.34)").is_err());
+        assert!(parse_dependency("Libc (= 2.34.0)").is_err());
+    }
+    #[test]
+    fn inexact_dependencies_fail_the_build() {
+        // 示例中的依赖都没有给出确定的版本
+        match build_unit().read_control(&fixture("package_example"), false) {
+            Err(PackageManagerError::InvalidControl(_, reason)) => {
+                assert!(reason.contains("Depends: libc6 (>= 2.34)"), "{}", reason);
+                assert!(reason.contains("--lenient"), "{}", reason);
+            }
+            other => panic!("unexpected result {:?}", other.map(|(package, _, _)| package.to_string())),
+        }
+    }
+    #[test]
+    fn the_example_control_file_is_accepted_leniently() {
+        let (package, dependencies, dropped) = build_unit().read_control(&fixture("package_example"), true).unwrap();
+        assert_eq!(package.to_string(), "apt-2.4.5");
+        assert_eq!(package.architecture, "amd64");
+        assert!(dependencies.is_empty());
+        assert_eq!(dropped, vec![
+            "Depends: adduser",
+            "Depends: gpgv | gpgv2 | gpgv1",
+            "Depends: libapt-pkg6.0 (>= 2.4.5)",
+            "Depends: ubuntu-keyring",
+            "Depends: libc6 (>= 2.34)",
+            "Recommends: ca-certificates",
+            "Suggests: apt-doc, aptitude | synaptic | wajig, dpkg-dev (>= 1.17.2), gnupg | gnupg2 | gnupg1, powermgmt-base",
+            "Breaks: apt-transport-https (<< 1.5~alpha4~), apt-utils (<< 1.3~exp2~), aptitude (<< 0.8.10)",
+            "Replaces: apt-transport-https (<< 1.5~alpha4~), apt-utils (<< 1.3~exp2~)",
+            "Provides: apt-transport-https (= 2.4.5)",
+        ]);
+    }
+}
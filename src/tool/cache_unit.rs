@@ -0,0 +1,271 @@
+use std::{fs, io::Write, path::Path, str::FromStr, sync::Arc};
+use lazy_static::lazy_static;
+use semver::Version;
+use sha2::{Digest, Sha256};
+use crate::entity::dependency::{Dependency, Package};
+use crate::entity::version_wrapper::VersionWrapper;
+use crate::manager::package_manager::PackageManagerError;
+use super::network_module::download_unit;
+
+// 本地缓存组件，保存下载过的软件包压缩包，重装和回退时不用再次下载
+// 文件名为 archive-version-hash.tar，hash为压缩包内容的sha256
+// 写入中的文件带 .partial 后缀，写入中断时会留下来，由 clean 和 autoclean 删除
+lazy_static! {
+    static ref CACHE_UNIT: Arc<CacheUnit> = Arc::new(CacheUnit::new("database/cache/archives"));
+}
+#[inline(always)]
+#[allow(dead_code)]
+pub fn cache_unit() -> &'static Arc<CacheUnit> {
+    &CACHE_UNIT
+}
+// 计算内容的sha256
+pub fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    return hex::encode(hasher.finalize());
+}
+// 写入中的临时文件的后缀
+const PARTIAL_SUFFIX: &str = ".partial";
+pub struct CacheUnit {
+    // 缓存目录
+    path: String,
+}
+// 缓存中的一个压缩包
+struct CacheEntry {
+    archive: String,
+    version: VersionWrapper,
+    hash: String,
+    file_name: String,
+}
+impl CacheEntry {
+    // 从文件名 archive-version-hash.tar 解析
+    fn from_file_name(file_name: &str) -> Option<CacheEntry> {
+        let stem = file_name.strip_suffix(".tar")?;
+        let (rest, hash) = stem.rsplit_once('-')?;
+        let (archive, version) = rest.split_once('-')?;
+        let version = Version::from_str(version).ok()?;
+        return Some(CacheEntry {
+            archive: archive.to_string(),
+            version: VersionWrapper::new(version),
+            hash: hash.to_string(),
+            file_name: file_name.to_string(),
+        });
+    }
+    // 是否是这个软件包的缓存，软件源给出了sha256时内容也要一致
+    fn matches(&self, package: &Package) -> bool {
+        if self.archive != package.archive || self.version != package.version_wrapper {
+            return false;
+        }
+        return package.sha256.is_empty() || self.hash == package.sha256;
+    }
+}
+impl CacheUnit {
+    pub fn new(path: &str) -> CacheUnit {
+        return CacheUnit { path: path.to_string() };
+    }
+    // 列出缓存目录中的所有文件名
+    fn file_names(&self) -> Result<Vec<String>, PackageManagerError> {
+        let dir = match fs::read_dir(&self.path) {
+            Ok(d) => d,
+            // 缓存目录还不存在
+            Err(_) => return Ok(Vec::new()),
+        };
+        let mut file_names = Vec::new();
+        for entry in dir {
+            match entry {
+                Ok(e) => file_names.push(e.file_name().to_string_lossy().to_string()),
+                Err(err) => return Err(PackageManagerError::CacheError(self.path.clone(), err)),
+            }
+        }
+        return Ok(file_names);
+    }
+    // 列出缓存中的所有压缩包
+    fn entries(&self) -> Result<Vec<CacheEntry>, PackageManagerError> {
+        let mut entries = Vec::new();
+        for file_name in self.file_names()? {
+            if file_name.ends_with(PARTIAL_SUFFIX) {
+                continue;
+            }
+            match CacheEntry::from_file_name(&file_name) {
+                Some(e) => entries.push(e),
+                None => log::warn!("ignore unknown file in cache: {}", file_name),
+            }
+        }
+        return Ok(entries);
+    }
+    // 删除写入中断后留下的临时文件，返回删除的数量
+    // 调用方持有数据库的排他锁，不会有其他进程正在写入
+    fn remove_partials(&self) -> Result<usize, PackageManagerError> {
+        let mut count = 0;
+        for file_name in self.file_names()? {
+            if !file_name.ends_with(PARTIAL_SUFFIX) {
+                continue;
+            }
+            let file_path = Path::new(&self.path).join(&file_name);
+            match fs::remove_file(&file_path) {
+                Ok(_) => count += 1,
+                Err(err) => return Err(PackageManagerError::CacheError(file_path.display().to_string(), err)),
+            }
+        }
+        return Ok(count);
+    }
+    // 缓存中是否有这个软件包，不读取内容
+    pub fn contains(&self, package: &Package) -> bool {
+        match self.entries() {
+            Ok(entries) => return entries.iter().any(|entry| entry.matches(package)),
+            Err(_) => return false,
+        }
+    }
+    // 查找软件包的缓存，和软件源给出的sha256不一致的缓存不会使用，校验失败的缓存会被删除
+    pub fn lookup(&self, package: &Package) -> Option<Vec<u8>> {
+        let entries = self.entries().ok()?;
+        for entry in entries {
+            if !entry.matches(package) {
+                continue;
+            }
+            let file_path = Path::new(&self.path).join(&entry.file_name);
+            let data = match fs::read(&file_path) {
+                Ok(d) => d,
+                Err(_) => continue,
+            };
+            if sha256_hex(&data) == entry.hash {
+                log::info!("use cached archive {}", entry.file_name);
+                return Some(data);
+            }
+            log::warn!("cached archive {} is corrupted, remove it", entry.file_name);
+            let _ = fs::remove_file(&file_path);
+        }
+        return None;
+    }
+    // 把下载的压缩包放进缓存，先写临时文件再改名，避免留下写了一半的文件
+    pub fn store(&self, package: &Package, data: &[u8]) -> Result<(), PackageManagerError> {
+        if let Err(err) = fs::create_dir_all(&self.path) {
+            return Err(PackageManagerError::CacheError(self.path.clone(), err));
+        }
+        let file_name = format!("{}-{}.tar", package.to_string(), sha256_hex(data));
+        let tmp_path = Path::new(&self.path).join(format!("{}.partial", file_name));
+        let mut file = match fs::File::create(&tmp_path) {
+            Ok(f) => f,
+            Err(err) => return Err(PackageManagerError::CacheError(tmp_path.display().to_string(), err)),
+        };
+        if let Err(err) = file.write_all(data) {
+            return Err(PackageManagerError::CacheError(tmp_path.display().to_string(), err));
+        }
+        let file_path = Path::new(&self.path).join(&file_name);
+        match fs::rename(&tmp_path, &file_path) {
+            Ok(_) => return Ok(()),
+            Err(err) => return Err(PackageManagerError::CacheError(file_path.display().to_string(), err)),
+        }
+    }
+    // 删除所有缓存和临时文件，返回删除的数量
+    pub fn clean(&self) -> Result<usize, PackageManagerError> {
+        let mut count = self.remove_partials()?;
+        for entry in self.entries()? {
+            let file_path = Path::new(&self.path).join(&entry.file_name);
+            match fs::remove_file(&file_path) {
+                Ok(_) => count += 1,
+                Err(err) => return Err(PackageManagerError::CacheError(file_path.display().to_string(), err)),
+            }
+        }
+        return Ok(count);
+    }
+    // 删除某个软件所有版本的缓存，返回删除的数量
+    pub fn remove(&self, archive: &str) -> Result<usize, PackageManagerError> {
+        let mut count = 0;
+        for entry in self.entries()? {
+            if entry.archive != archive {
+                continue;
+            }
+            let file_path = Path::new(&self.path).join(&entry.file_name);
+            match fs::remove_file(&file_path) {
+                Ok(_) => count += 1,
+                Err(err) => return Err(PackageManagerError::CacheError(file_path.display().to_string(), err)),
+            }
+        }
+        return Ok(count);
+    }
+    // 只删除仓库中已经不再提供的软件包和临时文件，返回删除的数量
+    pub fn autoclean(&self) -> Result<usize, PackageManagerError> {
+        let mut count = self.remove_partials()?;
+        for entry in self.entries()? {
+            let dependency = Arc::new(Dependency::new(entry.archive.clone(), entry.version.clone()));
+            let offered = match download_unit()?.get_package_information(dependency) {
+                Ok(_) => true,
+                Err(err) if err.is_not_found() => false,
+                // 其它错误无法判断仓库是否还提供，直接返回
+                Err(err) => return Err(err),
+            };
+            if offered {
+                continue;
+            }
+            let file_path = Path::new(&self.path).join(&entry.file_name);
+            match fs::remove_file(&file_path) {
+                Ok(_) => count += 1,
+                Err(err) => return Err(PackageManagerError::CacheError(file_path.display().to_string(), err)),
+            }
+        }
+        return Ok(count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::entity::dependency::{Dependency, Package};
+    use crate::test::temp_directory;
+    use super::{sha256_hex, CacheUnit};
+
+    fn package(name: &str, sha256: &str) -> Package {
+        let dependency = Dependency::from_string(name).unwrap();
+        let mut package = Package::new(dependency.archive, dependency.version_wrapper, String::new(), String::new(), String::new(), String::new(), String::new(), String::new());
+        package.sha256 = sha256.to_string();
+        return package;
+    }
+    #[test]
+    fn a_stored_archive_is_found_again() {
+        let directory = temp_directory("cache-store");
+        let cache = CacheUnit::new(&directory.display().to_string());
+        let data = b"archive".to_vec();
+        cache.store(&package("hello-1.0.0", ""), &data).unwrap();
+        assert!(directory.join(format!("hello-1.0.0-{}.tar", sha256_hex(&data))).is_file());
+        assert_eq!(cache.lookup(&package("hello-1.0.0", "")), Some(data.clone()));
+        assert_eq!(cache.lookup(&package("hello-1.0.0", &sha256_hex(&data))), Some(data));
+        assert!(cache.contains(&package("hello-1.0.0", "")));
+        // 其它版本没有缓存
+        assert_eq!(cache.lookup(&package("hello-2.0.0", "")), None);
+    }
+    #[test]
+    fn an_archive_with_another_hash_is_not_used() {
+        let directory = temp_directory("cache-hash");
+        let cache = CacheUnit::new(&directory.display().to_string());
+        cache.store(&package("hello-1.0.0", ""), b"old build").unwrap();
+        let rebuilt = package("hello-1.0.0", &sha256_hex(b"new build"));
+        assert!(!cache.contains(&rebuilt));
+        assert_eq!(cache.lookup(&rebuilt), None);
+    }
+    #[test]
+    fn a_corrupted_archive_is_removed() {
+        let directory = temp_directory("cache-corrupted");
+        let cache = CacheUnit::new(&directory.display().to_string());
+        let data = b"archive".to_vec();
+        cache.store(&package("hello-1.0.0", ""), &data).unwrap();
+        let path = directory.join(format!("hello-1.0.0-{}.tar", sha256_hex(&data)));
+        fs::write(&path, b"changed on disk").unwrap();
+        assert_eq!(cache.lookup(&package("hello-1.0.0", "")), None);
+        assert!(!path.exists());
+    }
+    #[test]
+    fn cleaning_removes_archives_and_partial_files() {
+        let directory = temp_directory("cache-clean");
+        let cache = CacheUnit::new(&directory.display().to_string());
+        cache.store(&package("hello-1.0.0", ""), b"hello").unwrap();
+        cache.store(&package("hello-2.0.0", ""), b"hello 2").unwrap();
+        cache.store(&package("world-1.0.0", ""), b"world").unwrap();
+        fs::write(directory.join("world-2.0.0-0000.tar.partial"), b"interrupted").unwrap();
+        assert_eq!(cache.remove("hello").unwrap(), 2);
+        assert!(!cache.contains(&package("hello-1.0.0", "")));
+        assert!(cache.contains(&package("world-1.0.0", "")));
+        assert_eq!(cache.clean().unwrap(), 2);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,141 @@
+use std::{fs, io::ErrorKind, sync::{Arc, Mutex}};
+use lazy_static::lazy_static;
+use crate::entity::hold::{HoldListTemp, HoldTemp};
+use crate::entity::version_wrapper::VersionWrapper;
+use crate::manager::package_manager::PackageManagerError;
+use super::resolve_file::profile_handler;
+
+// 锁定组件，记录锁定版本的软件，升级和依赖解析时不能改变它们的版本
+// 第一次使用时读取数据文件，读取失败时返回错误，下次使用时重新读取
+lazy_static! {
+    static ref HOLD_UNIT: Mutex<Option<Arc<HoldUnit>>> = Mutex::new(None);
+}
+#[inline(always)]
+#[allow(dead_code)]
+pub fn hold_unit() -> Result<Arc<HoldUnit>, PackageManagerError> {
+    let mut guard = HOLD_UNIT.lock().unwrap();
+    if let Some(unit) = guard.as_ref() {
+        return Ok(unit.clone());
+    }
+    let unit = Arc::new(HoldUnit::new(HOLD_PATH)?);
+    *guard = Some(unit.clone());
+    return Ok(unit);
+}
+// 锁定数据的路径
+const HOLD_PATH: &str = "database/hold_data.toml";
+// 还没有锁定过软件时没有数据文件
+fn read_holds(path: &str) -> Result<Vec<HoldTemp>, PackageManagerError> {
+    match fs::read_to_string(path) {
+        Ok(content) => match toml::from_str::<HoldListTemp>(&content) {
+            Ok(list) => return Ok(list.holds),
+            Err(err) => return Err(PackageManagerError::ParseLocalFileError(path.to_string(), err)),
+        },
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(PackageManagerError::Io(path.to_string(), err)),
+    }
+}
+pub struct HoldUnit {
+    path: String,
+    holds: Mutex<Vec<HoldTemp>>,
+}
+impl HoldUnit {
+    pub fn new(path: &str) -> Result<HoldUnit, PackageManagerError> {
+        let holds = read_holds(path)?;
+        return Ok(HoldUnit { path: path.to_string(), holds: Mutex::new(holds) });
+    }
+    // 重新读取磁盘上的锁定数据，测试中模拟进程重新启动
+    #[cfg(test)]
+    pub fn reload(&self) -> Result<(), PackageManagerError> {
+        let holds = read_holds(&self.path)?;
+        *self.holds.lock().unwrap() = holds;
+        return Ok(());
+    }
+    // 所有锁定的软件，按名字排列
+    pub fn holds(&self) -> Vec<HoldTemp> {
+        let mut holds = self.holds.lock().unwrap().clone();
+        holds.sort_by(|a, b| a.archive.cmp(&b.archive));
+        return holds;
+    }
+    // 软件锁定的版本，没有锁定时为空
+    pub fn held(&self, archive: &str) -> Option<VersionWrapper> {
+        return self.holds.lock().unwrap().iter()
+            .find(|hold| hold.archive == archive)
+            .map(|hold| hold.version.clone());
+    }
+    // 检查软件能否换成这个版本，reason 说明为什么需要这个版本
+    pub fn check(&self, archive: &str, version: &VersionWrapper, reason: &str) -> Result<(), PackageManagerError> {
+        match self.held(archive) {
+            Some(held) if held != *version => {
+                return Err(PackageManagerError::PackageHeld(format!("{}-{}", archive, held.to_string()), reason.to_string()));
+            }
+            _ => return Ok(()),
+        }
+    }
+    // 锁定软件的版本，已经锁定时改为新的版本
+    pub fn hold(&self, archive: &str, version: VersionWrapper) -> Result<(), PackageManagerError> {
+        let mut guard = self.holds.lock().unwrap();
+        guard.retain(|hold| hold.archive != archive);
+        guard.push(HoldTemp { archive: archive.to_string(), version });
+        return self.write(&guard);
+    }
+    // 解除锁定，返回软件原来是否被锁定
+    pub fn unhold(&self, archive: &str) -> Result<bool, PackageManagerError> {
+        let mut guard = self.holds.lock().unwrap();
+        let count = guard.len();
+        guard.retain(|hold| hold.archive != archive);
+        if guard.len() == count {
+            return Ok(false);
+        }
+        self.write(&guard)?;
+        return Ok(true);
+    }
+    fn write(&self, holds: &[HoldTemp]) -> Result<(), PackageManagerError> {
+        let content = match toml::to_string(&HoldListTemp { holds: holds.to_vec() }) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::WriteLocalFileError(self.path.clone(), e)),
+        };
+        match profile_handler().write_local_file(&self.path, &content) {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::entity::dependency::Dependency;
+    use crate::manager::package_manager::PackageManagerError;
+    use crate::test::temp_directory;
+    use super::HoldUnit;
+
+    #[test]
+    fn holds_are_kept_until_released() {
+        let path = temp_directory("hold-unit").join("hold_data.toml").display().to_string();
+        let unit = HoldUnit::new(&path).unwrap();
+        let held = Dependency::from_string("a-1.0.0").unwrap();
+        unit.hold("a", held.version_wrapper.clone()).unwrap();
+        // 重新锁定时换成新的版本
+        let newer = Dependency::from_string("a-1.1.0").unwrap();
+        unit.hold("a", newer.version_wrapper.clone()).unwrap();
+        let reopened = HoldUnit::new(&path).unwrap();
+        assert_eq!(reopened.holds().len(), 1);
+        assert!(reopened.held("a") == Some(newer.version_wrapper.clone()));
+        assert!(reopened.check("a", &newer.version_wrapper, "requested").is_ok());
+        assert!(matches!(reopened.check("a", &held.version_wrapper, "requested"), Err(PackageManagerError::PackageHeld(..))));
+        assert!(reopened.check("b", &held.version_wrapper, "requested").is_ok());
+        assert!(reopened.unhold("a").unwrap());
+        assert!(!reopened.unhold("a").unwrap());
+        assert!(HoldUnit::new(&path).unwrap().holds().is_empty());
+    }
+    #[test]
+    fn a_damaged_hold_file_is_an_error() {
+        let path = temp_directory("hold-damaged").join("hold_data.toml");
+        fs::write(&path, "holds = [[").unwrap();
+        let path = path.display().to_string();
+        assert!(matches!(HoldUnit::new(&path), Err(PackageManagerError::ParseLocalFileError(p, _)) if p == path));
+        // 文件修好后重新读取
+        fs::write(&path, "holds = []\n").unwrap();
+        assert!(HoldUnit::new(&path).unwrap().holds().is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+use std::{fs, io::ErrorKind, sync::{Arc, Mutex}};
+use lazy_static::lazy_static;
+use crate::entity::journal::{JournalAction, JournalState, JournalStep, JournalTemp, SnapshotFile};
+use crate::manager::configuration_manager::CONFIGURATION_DATA_PATH;
+use crate::manager::package_manager::{PackageManagerError, PACKAGE_DATA_PATH};
+use crate::manager::software_manager::SOFTWARE_DATA_PATH;
+use super::resolve_file::profile_handler;
+
+// 事务日志组件，多个包的操作开始前先写日志，再修改数据文件和安装目录
+// 进程中断后日志留在磁盘上，下次启动时根据状态回滚或继续完成
+// 第一次使用时读取日志，读取失败时返回错误，下次使用时重新读取
+lazy_static! {
+    static ref JOURNAL_UNIT: Mutex<Option<Arc<JournalUnit>>> = Mutex::new(None);
+}
+#[inline(always)]
+#[allow(dead_code)]
+pub fn journal_unit() -> Result<Arc<JournalUnit>, PackageManagerError> {
+    let mut guard = JOURNAL_UNIT.lock().unwrap();
+    if let Some(unit) = guard.as_ref() {
+        return Ok(unit.clone());
+    }
+    let unit = Arc::new(JournalUnit::new(JOURNAL_PATH)?);
+    *guard = Some(unit.clone());
+    return Ok(unit);
+}
+// 事务日志的路径
+const JOURNAL_PATH: &str = "database/journal.toml";
+// 事务开始前保存的数据文件
+const SNAPSHOT_PATHS: [&str; 3] = [CONFIGURATION_DATA_PATH, SOFTWARE_DATA_PATH, PACKAGE_DATA_PATH];
+// 读取上次没有完成的事务，只有文件不存在才表示没有；日志损坏时无法恢复，直接报错
+fn read_journal(path: &str) -> Result<Option<JournalTemp>, PackageManagerError> {
+    match fs::read_to_string(path) {
+        Ok(content) => match toml::from_str::<JournalTemp>(&content) {
+            Ok(journal) => return Ok(Some(journal)),
+            Err(err) => return Err(PackageManagerError::ParseLocalFileError(path.to_string(), err)),
+        },
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(PackageManagerError::Io(path.to_string(), err)),
+    }
+}
+pub struct JournalUnit {
+    path: String,
+    // 进行中的事务
+    current: Mutex<Option<JournalTemp>>,
+}
+impl JournalUnit {
+    pub fn new(path: &str) -> Result<JournalUnit, PackageManagerError> {
+        let current = read_journal(path)?;
+        return Ok(JournalUnit { path: path.to_string(), current: Mutex::new(current) });
+    }
+    // 重新读取磁盘上的日志，重新加锁后别的进程可能已经完成了恢复
+    pub fn reload(&self) -> Result<(), PackageManagerError> {
+        let current = read_journal(&self.path)?;
+        *self.current.lock().unwrap() = current;
+        return Ok(());
+    }
+    // 上次没有完成的事务
+    pub fn pending(&self) -> Option<JournalTemp> {
+        return self.current.lock().unwrap().clone();
+    }
+    fn write(&self, journal: &JournalTemp) -> Result<(), PackageManagerError> {
+        let content = match toml::to_string(journal) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::WriteLocalFileError(self.path.clone(), e)),
+        };
+        match profile_handler().write_local_file(&self.path, &content) {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+    // 开始事务，保存数据文件的快照和计划的步骤
+    pub fn begin(&self, operation: &str, state: JournalState, steps: Vec<JournalStep>) -> Result<(), PackageManagerError> {
+        let mut guard = self.current.lock().unwrap();
+        if let Some(journal) = guard.as_ref() {
+            return Err(PackageManagerError::TransactionPending(journal.operation.clone()));
+        }
+        let mut snapshot = Vec::new();
+        for path in SNAPSHOT_PATHS.iter() {
+            match fs::read_to_string(path) {
+                Ok(content) => snapshot.push(SnapshotFile { path: path.to_string(), exists: true, content }),
+                Err(e) if e.kind() == ErrorKind::NotFound => snapshot.push(SnapshotFile { path: path.to_string(), exists: false, content: String::new() }),
+                Err(e) => return Err(PackageManagerError::Io(path.to_string(), e)),
+            }
+        }
+        let journal = JournalTemp { operation: operation.to_string(), state, steps, snapshot };
+        self.write(&journal)?;
+        *guard = Some(journal);
+        return Ok(());
+    }
+    // 标记一步已经完成，没有进行中的事务时什么都不做
+    pub fn mark_done(&self, action: JournalAction, package: &str) -> Result<(), PackageManagerError> {
+        let mut guard = self.current.lock().unwrap();
+        let journal = match guard.as_mut() {
+            Some(j) => j,
+            None => return Ok(()),
+        };
+        match journal.steps.iter_mut().find(|step| step.action == action && step.package == package) {
+            Some(step) => {
+                step.started = true;
+                step.done = true;
+            }
+            None => journal.steps.push(JournalStep { action, package: package.to_string(), started: true, done: true }),
+        }
+        return self.write(journal);
+    }
+    // 标记一步开始修改安装目录，没有进行中的事务时什么都不做
+    pub fn mark_started(&self, action: JournalAction, package: &str) -> Result<(), PackageManagerError> {
+        let mut guard = self.current.lock().unwrap();
+        let journal = match guard.as_mut() {
+            Some(j) => j,
+            None => return Ok(()),
+        };
+        match journal.steps.iter_mut().find(|step| step.action == action && step.package == package) {
+            Some(step) => step.started = true,
+            None => journal.steps.push(JournalStep { action, package: package.to_string(), started: true, done: false }),
+        }
+        return self.write(journal);
+    }
+    // 新版本已经记录，之后中断时继续完成而不是回滚
+    pub fn commit(&self) -> Result<(), PackageManagerError> {
+        let mut guard = self.current.lock().unwrap();
+        let journal = match guard.as_mut() {
+            Some(j) => j,
+            None => return Ok(()),
+        };
+        journal.state = JournalState::Committed;
+        return self.write(journal);
+    }
+    // 事务完成，删除日志
+    pub fn finish(&self) -> Result<(), PackageManagerError> {
+        let mut guard = self.current.lock().unwrap();
+        match fs::remove_file(&self.path) {
+            Ok(_) => {},
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => return Err(PackageManagerError::Io(self.path.clone(), e)),
+        }
+        *guard = None;
+        return Ok(());
+    }
+    // 把快照写回数据文件
+    pub fn restore_snapshot(&self, journal: &JournalTemp) -> Result<(), PackageManagerError> {
+        for file in journal.snapshot.iter() {
+            if file.exists {
+                profile_handler().write_local_file(&file.path, &file.content)?;
+                continue;
+            }
+            match fs::remove_file(&file.path) {
+                Ok(_) => {},
+                Err(e) if e.kind() == ErrorKind::NotFound => {},
+                Err(e) => return Err(PackageManagerError::Io(file.path.clone(), e)),
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::entity::journal::{JournalAction, JournalState, JournalStep, JournalTemp, SnapshotFile};
+    use crate::manager::package_manager::PackageManagerError;
+    use crate::test::temp_directory;
+    use super::JournalUnit;
+
+    fn step(action: JournalAction, package: &str) -> JournalStep {
+        return JournalStep { action, package: package.to_string(), started: false, done: false };
+    }
+    #[test]
+    fn an_interrupted_transaction_is_found_after_a_restart() {
+        let path = temp_directory("journal-restart").join("journal.toml").display().to_string();
+        let unit = JournalUnit::new(&path).unwrap();
+        assert!(unit.pending().is_none());
+        unit.begin("install app", JournalState::Prepared, vec![step(JournalAction::Install, "a-1.0.0")]).unwrap();
+        unit.mark_done(JournalAction::Install, "a-1.0.0").unwrap();
+        // 计划之外的步骤也要记下来
+        unit.mark_done(JournalAction::Remove, "b-1.0.0").unwrap();
+        drop(unit);
+
+        let unit = JournalUnit::new(&path).unwrap();
+        let journal = unit.pending().unwrap();
+        assert_eq!(journal.operation, "install app");
+        assert_eq!(journal.state, JournalState::Prepared);
+        let steps: Vec<(JournalAction, &str, bool)> = journal.steps.iter().map(|step| (step.action, step.package.as_str(), step.done)).collect();
+        assert_eq!(steps, vec![(JournalAction::Install, "a-1.0.0", true), (JournalAction::Remove, "b-1.0.0", true)]);
+
+        unit.commit().unwrap();
+        assert_eq!(JournalUnit::new(&path).unwrap().pending().unwrap().state, JournalState::Committed);
+        unit.finish().unwrap();
+        assert!(unit.pending().is_none());
+        assert!(!std::path::Path::new(&path).exists());
+        assert!(JournalUnit::new(&path).unwrap().pending().is_none());
+    }
+    #[test]
+    fn only_one_transaction_at_a_time() {
+        let path = temp_directory("journal-pending").join("journal.toml").display().to_string();
+        let unit = JournalUnit::new(&path).unwrap();
+        unit.begin("install app", JournalState::Prepared, Vec::new()).unwrap();
+        match unit.begin("remove app", JournalState::Prepared, Vec::new()) {
+            Err(PackageManagerError::TransactionPending(operation)) => assert_eq!(operation, "install app"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        // 没有事务时提交和标记什么都不做
+        unit.finish().unwrap();
+        unit.commit().unwrap();
+        unit.mark_done(JournalAction::Install, "a-1.0.0").unwrap();
+        assert!(unit.pending().is_none());
+    }
+    #[test]
+    fn rolling_back_restores_the_data_files() {
+        let directory = temp_directory("journal-rollback");
+        let kept = directory.join("kept.toml");
+        let created = directory.join("created.toml");
+        fs::write(&kept, "old").unwrap();
+        let journal = JournalTemp {
+            operation: "install app".to_string(),
+            state: JournalState::Prepared,
+            steps: Vec::new(),
+            snapshot: vec![
+                SnapshotFile { path: kept.display().to_string(), exists: true, content: "old".to_string() },
+                SnapshotFile { path: created.display().to_string(), exists: false, content: String::new() },
+            ],
+        };
+        // 事务中途修改和新建的文件
+        fs::write(&kept, "new").unwrap();
+        fs::write(&created, "new").unwrap();
+        let unit = JournalUnit::new(&directory.join("journal.toml").display().to_string()).unwrap();
+        unit.restore_snapshot(&journal).unwrap();
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "old");
+        assert!(!created.exists());
+        // 再次回滚结果不变
+        unit.restore_snapshot(&journal).unwrap();
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "old");
+    }
+    #[test]
+    fn reloading_sees_what_another_process_did() {
+        let path = temp_directory("journal-reload").join("journal.toml").display().to_string();
+        let ours = JournalUnit::new(&path).unwrap();
+        let theirs = JournalUnit::new(&path).unwrap();
+        theirs.begin("install app", JournalState::Prepared, Vec::new()).unwrap();
+        assert!(ours.pending().is_none());
+        ours.reload().unwrap();
+        assert_eq!(ours.pending().unwrap().operation, "install app");
+        // 换锁期间别的进程完成了恢复
+        theirs.finish().unwrap();
+        ours.reload().unwrap();
+        assert!(ours.pending().is_none());
+    }
+    #[test]
+    fn a_damaged_journal_is_an_error() {
+        let directory = temp_directory("journal-damaged");
+        let path = directory.join("journal.toml");
+        fs::write(&path, "operation = [").unwrap();
+        assert!(matches!(JournalUnit::new(&path.display().to_string()), Err(PackageManagerError::ParseLocalFileError(_, _))));
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        assert!(matches!(JournalUnit::new(&path.display().to_string()), Err(PackageManagerError::Io(_, _))));
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fs::{self, File, OpenOptions}, io::{ErrorKind, Read, Seek, SeekFrom, Write}, os::unix::io::AsRawFd, thread, time::{Duration, Instant}};
+use crate::error::global_error::GlobalError;
+
+// 进程间的全局锁，保护数据目录下的数据文件和安装目录
+// 进程内的 Mutex 只能保护同一个进程，两个进程同时运行时要靠这个文件锁
+const LOCK_PATH: &str = "database/lock";
+// 等待锁时重试的间隔
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    // 只读的查询，可以多个进程同时持有
+    Shared,
+    // 修改数据的操作，只能有一个进程持有
+    Exclusive,
+}
+// 持有期间锁有效，drop 时关闭文件，锁随之释放
+pub struct DatabaseLock {
+    path: String,
+    file: File,
+    mode: LockMode,
+}
+impl DatabaseLock {
+    // 加锁，wait 为 None 时锁被占用立即返回错误，否则最多等待 wait
+    pub fn acquire(mode: LockMode, wait: Option<Duration>) -> Result<DatabaseLock, GlobalError> {
+        if let Err(e) = fs::create_dir_all("database") {
+            return Err(GlobalError::DatabaseLockError(format!("database: {}", e)));
+        }
+        return DatabaseLock::acquire_file(LOCK_PATH, mode, wait);
+    }
+    // 用 path 作为锁文件加锁
+    fn acquire_file(path: &str, mode: LockMode, wait: Option<Duration>) -> Result<DatabaseLock, GlobalError> {
+        let file = match OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path) {
+            Ok(f) => f,
+            Err(e) => return Err(GlobalError::DatabaseLockError(format!("{}: {}", path, e))),
+        };
+        let mut lock = DatabaseLock { path: path.to_string(), file, mode };
+        lock.wait_for(mode, wait)?;
+        return Ok(lock);
+    }
+    // 共享锁换成独占锁，用于只读命令发现需要恢复事务的情况
+    // flock 的转换不是原子的，这里先释放共享锁再重新加独占锁，中间别的进程可能已经修改了数据，返回后调用者要重新检查
+    pub fn upgrade(&mut self, wait: Option<Duration>) -> Result<(), GlobalError> {
+        if self.mode == LockMode::Exclusive {
+            return Ok(());
+        }
+        if unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+            return Err(GlobalError::DatabaseLockError(format!("{}: {}", self.path, std::io::Error::last_os_error())));
+        }
+        self.wait_for(LockMode::Exclusive, wait)?;
+        self.mode = LockMode::Exclusive;
+        return Ok(());
+    }
+    fn wait_for(&mut self, mode: LockMode, wait: Option<Duration>) -> Result<(), GlobalError> {
+        let operation = match mode {
+            LockMode::Shared => libc::LOCK_SH,
+            LockMode::Exclusive => libc::LOCK_EX,
+        };
+        let start = Instant::now();
+        loop {
+            if unsafe { libc::flock(self.file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+                break;
+            }
+            let error = std::io::Error::last_os_error();
+            if error.kind() != ErrorKind::WouldBlock {
+                return Err(GlobalError::DatabaseLockError(format!("{}: {}", self.path, error)));
+            }
+            match wait {
+                Some(timeout) if start.elapsed() < timeout => thread::sleep(RETRY_INTERVAL),
+                _ => return Err(GlobalError::DatabaseLocked(self.holder())),
+            }
+        }
+        // 记录独占锁持有者的进程号，别的进程加锁失败时显示；共享锁有多个持有者，不记录
+        if mode == LockMode::Exclusive {
+            self.write_pid();
+        }
+        return Ok(());
+    }
+    // 锁文件中记录的进程号
+    fn holder(&mut self) -> Option<u32> {
+        let mut content = String::new();
+        if self.file.seek(SeekFrom::Start(0)).is_err() || self.file.read_to_string(&mut content).is_err() {
+            return None;
+        }
+        return content.trim().parse::<u32>().ok();
+    }
+    fn write_pid(&mut self) {
+        let result = self.file.set_len(0)
+            .and_then(|_| self.file.seek(SeekFrom::Start(0)))
+            .and_then(|_| self.file.write_all(format!("{}\n", std::process::id()).as_bytes()));
+        if let Err(e) = result {
+            log::warn!("{}: failed to record pid: {}", self.path, e);
+        }
+    }
+}
+impl Drop for DatabaseLock {
+    // 释放独占锁前清掉记录的进程号，之后只持有共享锁的进程不会被误报为持有者
+    fn drop(&mut self) {
+        if self.mode == LockMode::Exclusive {
+            let _ = self.file.set_len(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::{Duration, Instant}};
+    use crate::error::global_error::GlobalError;
+    use crate::test::temp_directory;
+    use super::{DatabaseLock, LockMode};
+
+    fn lock_path(name: &str) -> String {
+        return temp_directory(name).join("lock").display().to_string();
+    }
+    #[test]
+    fn readers_share_the_lock() {
+        let path = lock_path("lock-shared");
+        let first = DatabaseLock::acquire_file(&path, LockMode::Shared, None).unwrap();
+        let second = DatabaseLock::acquire_file(&path, LockMode::Shared, None).unwrap();
+        // 共享锁不记录进程号
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(matches!(DatabaseLock::acquire_file(&path, LockMode::Exclusive, None), Err(GlobalError::DatabaseLocked(None))));
+        drop(first);
+        drop(second);
+        assert!(DatabaseLock::acquire_file(&path, LockMode::Exclusive, None).is_ok());
+    }
+    #[test]
+    fn a_writer_excludes_everyone_else() {
+        let path = lock_path("lock-exclusive");
+        let writer = DatabaseLock::acquire_file(&path, LockMode::Exclusive, None).unwrap();
+        let pid = std::process::id();
+        assert_eq!(fs::read_to_string(&path).unwrap().trim(), pid.to_string());
+        match DatabaseLock::acquire_file(&path, LockMode::Shared, None) {
+            Err(GlobalError::DatabaseLocked(holder)) => assert_eq!(holder, Some(pid)),
+            other => panic!("unexpected result {:?}", other.err()),
+        }
+        // 等到超时仍然失败
+        let start = Instant::now();
+        assert!(matches!(DatabaseLock::acquire_file(&path, LockMode::Exclusive, Some(Duration::from_millis(250))), Err(GlobalError::DatabaseLocked(Some(_)))));
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        // 释放时清掉进程号
+        drop(writer);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(DatabaseLock::acquire_file(&path, LockMode::Shared, None).is_ok());
+    }
+    #[test]
+    fn waiting_gets_the_lock_once_it_is_released() {
+        let path = lock_path("lock-wait");
+        let writer = DatabaseLock::acquire_file(&path, LockMode::Exclusive, None).unwrap();
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            drop(writer);
+        });
+        assert!(DatabaseLock::acquire_file(&path, LockMode::Exclusive, Some(Duration::from_secs(10))).is_ok());
+        release.join().unwrap();
+    }
+    #[test]
+    fn upgrading_waits_for_other_readers() {
+        let path = lock_path("lock-upgrade");
+        let mut reader = DatabaseLock::acquire_file(&path, LockMode::Shared, None).unwrap();
+        let other = DatabaseLock::acquire_file(&path, LockMode::Shared, None).unwrap();
+        assert!(matches!(reader.upgrade(None), Err(GlobalError::DatabaseLocked(_))));
+        drop(other);
+        drop(reader);
+        let mut reader = DatabaseLock::acquire_file(&path, LockMode::Shared, None).unwrap();
+        reader.upgrade(None).unwrap();
+        assert_eq!(reader.mode, LockMode::Exclusive);
+        assert_eq!(fs::read_to_string(&path).unwrap().trim(), std::process::id().to_string());
+        assert!(matches!(DatabaseLock::acquire_file(&path, LockMode::Shared, None), Err(GlobalError::DatabaseLocked(_))));
+        // 已经是独占锁时什么都不做
+        reader.upgrade(None).unwrap();
+    }
+}
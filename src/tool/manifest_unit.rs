@@ -0,0 +1,389 @@
+use std::{collections::HashMap, fs, io::{ErrorKind, Read}, os::unix::fs::PermissionsExt, path::Path, sync::Arc};
+use lazy_static::lazy_static;
+use tar::{Archive, EntryType};
+use crate::entity::manifest::{FileEntry, FileKind, ManifestTemp, VerifyReport};
+use crate::manager::package_manager::PackageManagerError;
+use super::build_unit::{is_metadata, METADATA_DIRECTORY};
+use super::cache_unit::sha256_hex;
+use super::resolve_file::profile_handler;
+
+// 文件清单组件，记录每个已安装的包装了哪些文件，用于查询文件属于哪个包和检查包之间的文件冲突
+lazy_static! {
+    static ref MANIFEST_UNIT: Arc<ManifestUnit> = Arc::new(ManifestUnit::new("database/manifests"));
+}
+#[inline(always)]
+#[allow(dead_code)]
+pub fn manifest_unit() -> &'static Arc<ManifestUnit> {
+    &MANIFEST_UNIT
+}
+pub struct ManifestUnit {
+    path: String,
+}
+// 去掉压缩包中路径开头的 ./ 和结尾的 /，用户输入的路径去掉开头的 /
+pub fn normalize_path(path: &str) -> String {
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    return path.trim_end_matches('/').to_string();
+}
+// 两个包提供同一个路径，只有都是目录时不算冲突
+fn clash(a: &FileEntry, b: &FileEntry) -> bool {
+    return a.path == b.path && !(a.kind == FileKind::Directory && b.kind == FileKind::Directory);
+}
+// 实际文件和清单中记录的是否不同，符号链接只比较目标
+fn differs(recorded: &FileEntry, actual: &FileEntry) -> bool {
+    if recorded.kind != actual.kind {
+        return true;
+    }
+    match recorded.kind {
+        FileKind::File => return recorded.hash != actual.hash || recorded.mode != actual.mode,
+        FileKind::Symlink => return recorded.target != actual.target,
+        _ => return recorded.mode != actual.mode,
+    }
+}
+// 读出 root 下 directory 中的所有文件，路径相对于 root
+fn scan(root: &Path, directory: &Path, files: &mut HashMap<String, FileEntry>) -> Result<(), PackageManagerError> {
+    let entries = match fs::read_dir(directory) {
+        Ok(e) => e,
+        Err(e) => return Err(PackageManagerError::Io(directory.display().to_string(), e)),
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => return Err(PackageManagerError::Io(directory.display().to_string(), e)),
+        };
+        let path = entry.path();
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(m) => m,
+            Err(e) => return Err(PackageManagerError::Io(path.display().to_string(), e)),
+        };
+        let relative = match path.strip_prefix(root) {
+            Ok(r) => r.display().to_string(),
+            Err(_) => continue,
+        };
+        let file_type = metadata.file_type();
+        let mut file = FileEntry { path: relative.clone(), kind: FileKind::Other, mode: metadata.permissions().mode() & 0o7777, size: metadata.len(), hash: String::new(), target: None };
+        if file_type.is_file() {
+            file.kind = FileKind::File;
+            match fs::read(&path) {
+                Ok(content) => file.hash = sha256_hex(&content),
+                Err(e) => return Err(PackageManagerError::Io(path.display().to_string(), e)),
+            }
+        } else if file_type.is_symlink() {
+            file.kind = FileKind::Symlink;
+            file.target = fs::read_link(&path).ok().map(|target| target.display().to_string());
+        } else if file_type.is_dir() {
+            file.kind = FileKind::Directory;
+            scan(root, &path, files)?;
+        }
+        files.insert(relative, file);
+    }
+    return Ok(());
+}
+impl ManifestUnit {
+    pub fn new(path: &str) -> ManifestUnit {
+        return ManifestUnit { path: path.to_string() };
+    }
+    fn manifest_path(&self, name: &str) -> String {
+        return format!("{}/{}.toml", self.path, name);
+    }
+    // 解压前从 tar 压缩包中读出文件清单
+    pub fn read_archive(&self, data: &[u8]) -> Result<Vec<FileEntry>, std::io::Error> {
+        let mut archive = Archive::new(data);
+        let mut files = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = normalize_path(&entry.path()?.to_string_lossy());
+            if path.is_empty() || is_metadata(&path) {
+                continue;
+            }
+            let header = entry.header();
+            let kind = match header.entry_type() {
+                EntryType::Regular | EntryType::Continuous => FileKind::File,
+                EntryType::Directory => FileKind::Directory,
+                EntryType::Symlink => FileKind::Symlink,
+                _ => FileKind::Other,
+            };
+            let mode = header.mode()? & 0o7777;
+            let target = match kind {
+                FileKind::Symlink => entry.link_name()?.map(|target| target.to_string_lossy().to_string()),
+                _ => None,
+            };
+            let mut hash = String::new();
+            if kind == FileKind::File {
+                let mut content = Vec::new();
+                entry.read_to_end(&mut content)?;
+                hash = sha256_hex(&content);
+            }
+            files.push(FileEntry { path, kind, mode, size: entry.size(), hash, target });
+        }
+        return Ok(files);
+    }
+    // 检查 build 打出的包中的 checksum 和 manifest.toml：checksum 是 manifest.toml 的 sha256，
+    // 清单中的文件要和压缩包中的文件 files 一致；没有元数据的压缩包不检查
+    pub fn check_archive(&self, name: &str, data: &[u8], files: &[FileEntry]) -> Result<(), PackageManagerError> {
+        let mut manifest = None;
+        let mut checksum = None;
+        let mut archive = Archive::new(data);
+        let entries = match archive.entries() {
+            Ok(e) => e,
+            Err(e) => return Err(PackageManagerError::DecompressError(name.to_string(), e)),
+        };
+        for entry in entries {
+            let mut entry = match entry {
+                Ok(e) => e,
+                Err(e) => return Err(PackageManagerError::DecompressError(name.to_string(), e)),
+            };
+            let path = match entry.path() {
+                Ok(p) => normalize_path(&p.to_string_lossy()),
+                Err(e) => return Err(PackageManagerError::DecompressError(name.to_string(), e)),
+            };
+            let file = match path.strip_prefix(&format!("{}/", METADATA_DIRECTORY)).and_then(|rest| rest.split_once('/')) {
+                Some((_, file)) if file == "manifest.toml" || file == "checksum" => file.to_string(),
+                _ => continue,
+            };
+            let mut content = Vec::new();
+            if let Err(e) = entry.read_to_end(&mut content) {
+                return Err(PackageManagerError::DecompressError(name.to_string(), e));
+            }
+            if file == "checksum" {
+                checksum = Some(content);
+            } else {
+                manifest = Some(content);
+            }
+        }
+        let integrity = |reason: &str| PackageManagerError::IntegrityError(name.to_string(), reason.to_string());
+        let (manifest, checksum) = match (manifest, checksum) {
+            (None, None) => return Ok(()),
+            (Some(manifest), Some(checksum)) => (manifest, checksum),
+            _ => return Err(integrity("the package has only one of manifest.toml and checksum")),
+        };
+        if String::from_utf8_lossy(&checksum).trim() != sha256_hex(&manifest) {
+            return Err(integrity("checksum does not match manifest.toml"));
+        }
+        let recorded = match std::str::from_utf8(&manifest).ok().and_then(|content| toml::from_str::<ManifestTemp>(content).ok()) {
+            Some(m) => m,
+            None => return Err(integrity("manifest.toml cannot be read")),
+        };
+        let mut actual: HashMap<&str, &FileEntry> = files.iter().map(|file| (file.path.as_str(), file)).collect();
+        for file in recorded.files.iter() {
+            match actual.remove(file.path.as_str()) {
+                None => return Err(integrity(&format!("{} is in manifest.toml but not in the package", file.path))),
+                Some(found) if differs(file, found) => return Err(integrity(&format!("{} differs from manifest.toml", file.path))),
+                Some(_) => {},
+            }
+        }
+        if let Some(path) = actual.keys().min() {
+            return Err(integrity(&format!("{} is in the package but not in manifest.toml", path)));
+        }
+        return Ok(());
+    }
+    // 已安装的包的文件清单，name 为 archive-version，没有记录时为空
+    pub fn get(&self, name: &str) -> Option<ManifestTemp> {
+        let content = fs::read_to_string(self.manifest_path(name)).ok()?;
+        match toml::from_str::<ManifestTemp>(&content) {
+            Ok(manifest) => return Some(manifest),
+            Err(e) => {
+                log::warn!("{} is corrupted: {}", self.manifest_path(name), e);
+                return None;
+            }
+        }
+    }
+    // 记录了文件清单的所有包
+    pub fn recorded(&self) -> Vec<String> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(e) => e,
+            Err(_) => return Vec::new(),
+        };
+        let mut names: Vec<String> = entries.flatten()
+            .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.strip_suffix(".toml")).map(|name| name.to_string()))
+            .collect();
+        names.sort();
+        return names;
+    }
+    pub fn record(&self, name: &str, files: Vec<FileEntry>) -> Result<(), PackageManagerError> {
+        let content = match toml::to_string(&ManifestTemp { package: name.to_string(), files }) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::WriteLocalFileError(self.manifest_path(name), e)),
+        };
+        match profile_handler().write_local_file(&self.manifest_path(name), &content) {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+    pub fn remove(&self, name: &str) -> Result<(), PackageManagerError> {
+        let path = self.manifest_path(name);
+        match fs::remove_file(&path) {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(PackageManagerError::Io(path, e)),
+        }
+    }
+    // 比较包的安装目录 root 和安装时记录的文件清单
+    pub fn verify(&self, name: &str, root: &Path) -> Result<VerifyReport, PackageManagerError> {
+        let mut report = VerifyReport { package: name.to_string(), ..Default::default() };
+        let manifest = match self.get(name) {
+            Some(m) => m,
+            None => return Ok(report),
+        };
+        report.recorded = true;
+        let mut actual = HashMap::new();
+        // 安装目录整个不见了时所有文件都算缺失
+        if root.is_dir() {
+            scan(root, root, &mut actual)?;
+        }
+        for file in manifest.files.iter() {
+            match actual.remove(&file.path) {
+                None => report.missing.push(file.path.clone()),
+                Some(found) if differs(file, &found) => report.modified.push(file.path.clone()),
+                Some(_) => {},
+            }
+        }
+        report.extra = actual.into_keys().collect();
+        report.extra.sort();
+        return Ok(report);
+    }
+    // 检查 files 是否和 installed 中的包的文件冲突，返回第一个冲突的路径和它所属的包
+    pub fn conflict(&self, files: &[FileEntry], installed: &[String]) -> Option<(String, String)> {
+        for name in installed.iter() {
+            let manifest = match self.get(name) {
+                Some(m) => m,
+                None => continue,
+            };
+            for file in files.iter() {
+                if manifest.files.iter().any(|owned| clash(owned, file)) {
+                    return Some((file.path.clone(), name.clone()));
+                }
+            }
+        }
+        return None;
+    }
+    // installed 中拥有 path 的包和对应的文件
+    pub fn owners(&self, path: &str, installed: &[String]) -> Vec<(String, FileEntry)> {
+        let path = normalize_path(path);
+        let mut owners = Vec::new();
+        for name in installed.iter() {
+            let manifest = match self.get(name) {
+                Some(m) => m,
+                None => continue,
+            };
+            if let Some(file) = manifest.files.into_iter().find(|file| file.path == path) {
+                owners.push((name.clone(), file));
+            }
+        }
+        return owners;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Read, os::unix::fs::{symlink, PermissionsExt}, path::Path};
+    use crate::manager::package_manager::PackageManagerError;
+    use crate::test::temp_directory;
+    use crate::tool::build_unit::build_unit;
+    use crate::tool::network_module::decompress_unit;
+    use super::ManifestUnit;
+
+    // 打包 root 下的文件，和安装时一样先从压缩包读出清单
+    fn archive(root: &Path) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.follow_symlinks(false);
+        builder.append_dir_all(".", root).unwrap();
+        return builder.into_inner().unwrap();
+    }
+    fn installed(name: &str) -> (ManifestUnit, std::path::PathBuf) {
+        let directory = temp_directory(name);
+        let root = directory.join("store");
+        fs::create_dir_all(root.join("usr/bin")).unwrap();
+        fs::write(root.join("usr/bin/tool"), "tool\n").unwrap();
+        fs::set_permissions(root.join("usr/bin/tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(root.join("usr/bin/data"), "data\n").unwrap();
+        symlink("tool", root.join("usr/bin/alias")).unwrap();
+        let unit = ManifestUnit::new(&directory.join("manifests").display().to_string());
+        let files = unit.read_archive(&archive(&root)).unwrap();
+        unit.record("tool-1.0.0", files).unwrap();
+        return (unit, root);
+    }
+    #[test]
+    fn an_untouched_package_passes() {
+        let (unit, root) = installed("verify-clean");
+        let report = unit.verify("tool-1.0.0", &root).unwrap();
+        assert!(report.recorded);
+        assert!(!report.broken(), "{:?} {:?} {:?}", report.missing, report.modified, report.extra);
+    }
+    #[test]
+    fn changed_files_are_reported() {
+        let (unit, root) = installed("verify-changed");
+        let bin = root.join("usr/bin");
+        fs::write(bin.join("data"), "changed\n").unwrap();
+        fs::set_permissions(bin.join("tool"), fs::Permissions::from_mode(0o700)).unwrap();
+        fs::remove_file(bin.join("alias")).unwrap();
+        symlink("data", bin.join("alias")).unwrap();
+        fs::write(bin.join("new"), "new\n").unwrap();
+        let report = unit.verify("tool-1.0.0", &root).unwrap();
+        let mut modified = report.modified.clone();
+        modified.sort();
+        assert_eq!(modified, vec!["usr/bin/alias", "usr/bin/data", "usr/bin/tool"]);
+        assert_eq!(report.extra, vec!["usr/bin/new"]);
+        assert!(report.missing.is_empty());
+    }
+    #[test]
+    fn a_missing_store_or_manifest_is_broken() {
+        let (unit, root) = installed("verify-missing");
+        fs::remove_dir_all(&root).unwrap();
+        let report = unit.verify("tool-1.0.0", &root).unwrap();
+        assert_eq!(report.missing.len(), 5);
+        let report = unit.verify("other-1.0.0", &root).unwrap();
+        assert!(!report.recorded);
+        assert!(report.broken());
+    }
+    // 打一个包，返回解开 gzip 后的 tar
+    fn built(name: &str) -> Vec<u8> {
+        let directory = temp_directory(name);
+        let (staging, control) = (directory.join("staging"), directory.join("control"));
+        fs::create_dir_all(staging.join("usr/bin")).unwrap();
+        fs::create_dir_all(&control).unwrap();
+        fs::write(staging.join("usr/bin/tool"), "tool\n").unwrap();
+        fs::write(control.join("control.txt"), "Package: tool\nVersion: 1.0.0\nArchitecture: all\n").unwrap();
+        let built = build_unit().build(&staging, &control, &directory, false).unwrap();
+        return decompress_unit().decode(fs::read(&built.path).unwrap()).unwrap();
+    }
+    // 复制压缩包，改掉 path 的内容
+    fn tampered(data: &[u8], path: &str, content: &[u8]) -> Vec<u8> {
+        let mut archive = tar::Archive::new(data);
+        let mut builder = tar::Builder::new(Vec::new());
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut header = entry.header().clone();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            if entry.path().unwrap().to_string_lossy() == path {
+                data = content.to_vec();
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+            }
+            builder.append(&header, data.as_slice()).unwrap();
+        }
+        return builder.into_inner().unwrap();
+    }
+    fn check(data: &[u8]) -> Result<(), PackageManagerError> {
+        let unit = ManifestUnit::new("unused");
+        let files = unit.read_archive(data).unwrap();
+        return unit.check_archive("tool-1.0.0", data, &files);
+    }
+    #[test]
+    fn the_embedded_checksum_is_checked() {
+        let data = built("check-archive");
+        assert!(check(&data).is_ok());
+        let changed = tampered(&data, "usr/bin/tool", b"changed\n");
+        assert!(matches!(check(&changed), Err(PackageManagerError::IntegrityError(_, reason)) if reason == "usr/bin/tool differs from manifest.toml"));
+        let changed = tampered(&data, ".package/tool/checksum", b"0000\n");
+        assert!(matches!(check(&changed), Err(PackageManagerError::IntegrityError(_, reason)) if reason == "checksum does not match manifest.toml"));
+        // 没有元数据的压缩包不检查
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "usr/bin/plain", &b"plain"[..]).unwrap();
+        assert!(check(&builder.into_inner().unwrap()).is_ok());
+    }
+}
@@ -1,2 +1,13 @@
+pub mod arch_unit;
+pub mod build_unit;
+pub mod cache_unit;
+pub mod hold_unit;
+pub mod journal_unit;
+pub mod lock_unit;
+pub mod manifest_unit;
 pub mod network_module;
-pub mod resolve_file;
\ No newline at end of file
+pub mod profile_unit;
+pub mod repository_unit;
+pub mod resolve_file;
+pub mod server_module;
+pub mod transport;
@@ -1,134 +1,232 @@
-
-use base64::decode;
 use lazy_static::lazy_static;
-use serde::de;
-use reqwest::Error;
-use simple_logger::SimpleLogger;
-use tokio::runtime::Runtime;
-use std::{
-    collections::{HashMap, HashSet, LinkedList}, fs::File, io::Write, ptr::null, result, sync::{Arc, Mutex, MutexGuard}
-};
-use serde_json::{Value, json};
-use crate::{entity::dependency::{self, Dependency, Package, PackageList}, manager::{package_manager::PackageManagerError}};
+use std::{collections::{HashMap, HashSet}, fs, io::Read, path::Path, sync::{Arc, Mutex}};
+use flate2::read::GzDecoder;
+use tar::Archive;
+use crate::{entity::dependency::{Dependency, Package, PackageListTemp}, manager::{package_manager::PackageManagerError}};
+use crate::entity::repository::RepositoryIndexTemp;
+use crate::entity::source_config::{CredentialList, SourceConfiguration};
 use crate::error::software_error::SoftwareManagerError;
-use crate::entity::software::{Software};
-use crate::entity::version_wrapper::VersionWrapper;
 
+use super::build_unit::is_metadata;
+use super::repository_unit::verify_signature;
 use super::resolve_file::profile_handler;
-//下载组件
+use super::transport::{body_excerpt, transport_for, Fetched, Transport};
+//下载组件，第一次使用时读取软件源配置，配置文件有错时返回错误
 lazy_static! {
-    static ref DOWNLOAD_UNIT: Arc<DownloadUnit> = Arc::new(DownloadUnit::new());
+    static ref DOWNLOAD_UNIT: Mutex<Option<Arc<DownloadUnit>>> = Mutex::new(None);
 }
 //
 #[inline(always)]
 #[allow(dead_code)]
-pub fn download_unit() -> &'static Arc<DownloadUnit> {
-    &DOWNLOAD_UNIT
+pub fn download_unit() -> Result<Arc<DownloadUnit>, PackageManagerError> {
+    let mut guard = DOWNLOAD_UNIT.lock().unwrap();
+    if let Some(unit) = guard.as_ref() {
+        return Ok(unit.clone());
+    }
+    let unit = Arc::new(DownloadUnit::new()?);
+    *guard = Some(unit.clone());
+    return Ok(unit);
+}
+// 测试中使用给出的下载组件，不读取配置文件
+#[cfg(test)]
+pub fn use_download_unit(unit: DownloadUnit) {
+    *DOWNLOAD_UNIT.lock().unwrap() = Some(Arc::new(unit));
+}
+// 软件源配置文件
+pub const SOURCE_CONFIGURATION_PATH: &str = "file/config.toml";
+// 私有软件源的认证信息
+pub const CREDENTIAL_PATH: &str = "file/credentials.toml";
+pub struct DownloadUnit {
+    // 按顺序查找的软件源
+    transports: Vec<Box<dyn Transport>>,
+    // 每个源的签名公钥，和 transports 一一对应，为空表示不检查签名
+    public_keys: Vec<String>,
+    // 带公钥的源检查过签名的索引，按源的位置记录，单个包的信息和依赖列表没有签名，要和索引一致
+    signed_indexes: Mutex<HashMap<usize, Arc<RepositoryIndexTemp>>>,
+    // 配置文件中指定的架构
+    architecture: String,
+}
+// 检查索引的签名，public_key 为空时不检查
+fn check_signature(fetched: &Fetched, public_key: &str) -> Result<(), PackageManagerError> {
+    if public_key.is_empty() {
+        return Ok(());
+    }
+    let checked = match fetched.signature.as_ref() {
+        Some(signature) => verify_signature(&fetched.content, signature, public_key),
+        None => Err("the index is not signed".to_string()),
+    };
+    match checked {
+        Ok(_) => return Ok(()),
+        Err(reason) => return Err(PackageManagerError::IntegrityError(fetched.location.clone(), reason)),
+    }
 }
-pub struct DownloadUnit {}
 impl DownloadUnit {
-    // todo 设置目标地址，下载器下载文件且解压
-    pub fn download_software(&self, package: Arc<Package>, target_path: &str) -> Result<(), SoftwareManagerError> {
-        // 创建一个新的 tokio 运行时环境
-        let rt = Runtime::new().unwrap();    
-        // 在异步上下文中执行异步函数并等待结果返回
-        let result = rt.block_on(async {
-            self.download_software_async(package, target_path).await
-        });
-        return result;
-    }
-    // 
-    async fn download_software_async(&self, package: Arc<Package>, target_path: &str) -> Result<(), SoftwareManagerError> {
-        // 找到下载地址
-        let downloadsite = package.download();
-        //
-        let client = reqwest::Client::new();
-        let response = match client.get(downloadsite).send().await{
-            Ok(r) => r,
-            Err(err) => return Err(SoftwareManagerError::DownloadError(err.to_string())),
-        };
-        let file : Value = match response.text().await {
-            Ok(f) => serde_json::from_str(&f).unwrap(),
-            Err(err) => return Err(SoftwareManagerError::DownloadError(err.to_string())),
+    // 第 position 个源检查过签名的索引，源没有公钥时为空，第一次使用时下载
+    fn signed_index(&self, position: usize) -> Result<Option<Arc<RepositoryIndexTemp>>, PackageManagerError> {
+        if self.public_keys[position].is_empty() {
+            return Ok(None);
+        }
+        if let Some(index) = self.signed_indexes.lock().unwrap().get(&position) {
+            return Ok(Some(index.clone()));
+        }
+        let fetched = self.transports[position].fetch_index()?;
+        check_signature(&fetched, &self.public_keys[position])?;
+        let index : RepositoryIndexTemp = match toml::from_str(&fetched.content) {
+            Ok(i) => i,
+            Err(_) => return Err(PackageManagerError::MalformedResponse(fetched.location, body_excerpt(&fetched.content))),
         };
-        // 根据返回结果操作
-        if file.get("status_code").is_some() {
-            return Err(SoftwareManagerError::DownloadError(file.get("message").unwrap().to_string()));
+        let index = Arc::new(index);
+        self.signed_indexes.lock().unwrap().insert(position, index.clone());
+        return Ok(Some(index));
+    }
+    // 下载软件包，返回压缩包内容
+    pub fn download_software(&self, package: Arc<Package>) -> Result<Vec<u8>, PackageManagerError> {
+        // 从返回这个包的源下载，相对的下载地址也只对这个源有意义
+        if let Some(transport) = self.transports.iter().find(|t| !package.source.is_empty() && t.location() == package.source) {
+            return transport.fetch_archive(&package);
         }
-        if file.get("data").is_none() {
-            return Err(SoftwareManagerError::DownloadError("data is none".to_string()));
+        // 不知道来源（旧的数据文件或者源已经从配置中删除）时依次尝试
+        if !package.source.is_empty() {
+            log::warn!("source {} of {} is no longer configured", package.source, package.to_string());
         }
-        // 获得返回结果，如果没有问题就安装到本地
-        let data  = file.get("data").unwrap().as_str().unwrap();
-        let decoded_data: Vec<u8> = match decode(data) {
-            Ok(decoded) => decoded,
-            Err(_) => {
-                return Err(SoftwareManagerError::DownloadError("Failed to decode Base64 string.".to_string()));
+        let mut last_error = PackageManagerError::PackageNotFound(package.download());
+        for transport in self.transports.iter() {
+            match transport.fetch_archive(&package) {
+                Err(err) if err.is_not_found() => last_error = err,
+                result => return result,
             }
-        };
-        match decompress_unit().install(decoded_data, target_path) {
-            Ok(o) => return Ok(()),
-            Err(err) => return Err(SoftwareManagerError::from(err)),
-        } 
+        }
+        return Err(last_error);
     }
     // 获取配置文件
     pub fn get_dependency_list(&self, dependency: Arc<Dependency>) -> Result<Vec<Arc<Dependency>>, SoftwareManagerError> {
-        // 创建一个新的 tokio 运行时环境
-        let rt = Runtime::new().unwrap();    
-        // 在异步上下文中执行异步函数并等待结果返回
-        let result = rt.block_on(async {
-            self.get_dependency_list_async(dependency).await
-        });
-        return result;
-    }
-    async fn get_dependency_list_async(&self, dependency: Arc<Dependency>) -> Result<Vec<Arc<Dependency>>, SoftwareManagerError> {
-        let url = format!("http://127.0.0.1:8080/api/v1/dependency/get?archive={}&version={}", dependency.archive, dependency.version_wrapper.version.to_string());
-        let client = reqwest::Client::new();
-        let response = match client.get(url).send().await{
-            Ok(r) => r,
-            Err(err) => return Err(SoftwareManagerError::DownloadError(err.to_string())),
-        };
-        let file : Value  = match response.text().await {
-            Ok(f) => serde_json::from_str(&f).unwrap(),
-            Err(err) => return Err(SoftwareManagerError::DownloadError(err.to_string())),
-        };
-        let deps = match file.get("data") {
-            Some(d) => d.as_str().unwrap(),
-            None => {
-                return Err(SoftwareManagerError::DownloadError("Failed to get data".to_string()));
+        // 按顺序查找软件源，源中没有这个包时找下一个
+        let mut last_error = SoftwareManagerError::DownloadError("no source configured".to_string());
+        for (position, transport) in self.transports.iter().enumerate() {
+            let fetched = match transport.fetch_dependency_list(&dependency) {
+                Ok(f) => f,
+                Err(err) if err.is_not_found() => {
+                    last_error = err;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let list = match profile_handler().from_string_to_dependencies(fetched.content.clone()) {
+                Ok(l) => l,
+                Err(_) => return Err(PackageManagerError::MalformedResponse(fetched.location, body_excerpt(&fetched.content)).into()),
+            };
+            // 签名的源中依赖列表要和索引中的一致
+            if let Some(index) = self.signed_index(position)? {
+                let names : Vec<String> = list.iter().map(|dependency| dependency.to_string()).collect();
+                match index.packages.iter().find(|entry| entry.package.to_string() == dependency.to_string()) {
+                    Some(entry) if entry.dependencies == names => {},
+                    Some(_) => return Err(PackageManagerError::IntegrityError(fetched.location, "the dependency list does not match the signed index".to_string()).into()),
+                    None => return Err(PackageManagerError::IntegrityError(fetched.location, "the package is not in the signed index".to_string()).into()),
+                }
             }
-        };
-        return profile_handler().from_string_to_dependencies(deps.to_string());
+            return Ok(list);
+        }
+        return Err(last_error);
     }
     // 获取软件包详细信息
     pub fn get_package_information(&self, dependency: Arc<Dependency>) -> Result<Arc<Package>, PackageManagerError> {
-        // 创建一个新的 tokio 运行时环境
-        let rt = Runtime::new().unwrap();    
-        // 在异步上下文中执行异步函数并等待结果返回
-        let result = rt.block_on(async {
-            self.get_package_information_async(dependency).await
-        });
-        return result;
-    }
-    async fn get_package_information_async(&self, dependency: Arc<Dependency>) -> Result<Arc<Package>, PackageManagerError> {
-        let url = format!("http://127.0.0.1:8080/api/v1/software/information?archive={}&version={}", dependency.archive, dependency.version_wrapper.version.to_string());
-        let client = reqwest::Client::new();
-        let response = match client.get(url).send().await{
-            Ok(r) => r,
-            Err(err) => return Err(PackageManagerError::PackageInstallFailed),
-        };
-        let file  = match response.text().await {
-            Ok(f) => f,
-            Err(err) => return Err(PackageManagerError::PackageInstallFailed),
+        // 按顺序查找软件源，源中没有这个包时找下一个
+        let mut last_error = PackageManagerError::DownloadError("no source configured".to_string());
+        for (position, transport) in self.transports.iter().enumerate() {
+            let fetched = match transport.fetch_package_information(&dependency) {
+                Ok(f) => f,
+                Err(err) if err.is_not_found() => {
+                    last_error = err;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let mut package = match toml::from_str::<Package>(&fetched.content) {
+                Ok(p) => p,
+                Err(_) => return Err(PackageManagerError::MalformedResponse(fetched.location, body_excerpt(&fetched.content))),
+            };
+            // 签名的源中详细信息（包括压缩包的 sha256）要和索引中的一致
+            if let Some(index) = self.signed_index(position)? {
+                match index.packages.iter().find(|entry| entry.package.to_string() == dependency.to_string()) {
+                    Some(entry) if entry.package == package => {},
+                    Some(_) => return Err(PackageManagerError::IntegrityError(fetched.location, "the package information does not match the signed index".to_string())),
+                    None => return Err(PackageManagerError::IntegrityError(fetched.location, "the package is not in the signed index".to_string())),
+                }
+            }
+            package.source = transport.location().to_string();
+            return Ok(Arc::new(package));
+        }
+        return Err(last_error);
+    }
+    // 配置文件中指定的架构，为空表示没有指定
+    pub fn architecture(&self) -> &str {
+        return &self.architecture;
+    }
+    // 获取所有软件源的软件包列表，同名同版本同架构的包以排在前面的源为准
+    pub fn get_index(&self) -> Result<Vec<Package>, PackageManagerError> {
+        let mut packages : Vec<Package> = Vec::new();
+        let mut seen : HashSet<String> = HashSet::new();
+        for (transport, public_key) in self.transports.iter().zip(self.public_keys.iter()) {
+            let fetched = match transport.fetch_index() {
+                Ok(f) => f,
+                Err(err) if err.is_not_found() => {
+                    log::warn!("{}", err);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            check_signature(&fetched, public_key)?;
+            let list : PackageListTemp = match toml::from_str(&fetched.content) {
+                Ok(l) => l,
+                Err(_) => return Err(PackageManagerError::MalformedResponse(fetched.location, body_excerpt(&fetched.content))),
+            };
+            for mut package in list.packages {
+                package.source = transport.location().to_string();
+                if seen.insert(format!("{}:{}", package.to_string(), package.architecture)) {
+                    packages.push(package);
+                }
+            }
+        }
+        return Ok(packages);
+    }
+    pub fn new() -> Result<DownloadUnit, PackageManagerError> {
+        return DownloadUnit::from_files(SOURCE_CONFIGURATION_PATH, CREDENTIAL_PATH);
+    }
+    // 读取软件源配置和认证信息创建
+    // 只有配置文件不存在时才使用默认的本地源，配置文件有错时返回错误，不能悄悄换成其它源
+    pub fn from_files(path: &str, credential_path: &str) -> Result<DownloadUnit, PackageManagerError> {
+        let configuration = if Path::new(path).exists() {
+            profile_handler().analyse_source_file(path)?
+        } else {
+            let mut c = SourceConfiguration::default_configuration();
+            c.network.apply_env();
+            c
         };
-        let package : Package = match toml::from_str(&file) {
-            Ok(p) => p,
-            Err(e) => return Err(PackageManagerError::PackageInstallFailed),
+        // 读取私有软件源的认证信息，没有则不认证
+        let path = credential_path;
+        let credentials = if Path::new(path).exists() {
+            match profile_handler().analyse_credential_file(path.to_string()) {
+                Ok(c) => c,
+                Err(err) => {
+                    log::warn!("{}, ignore credentials", err);
+                    CredentialList::default()
+                }
+            }
+        } else {
+            CredentialList::default()
         };
-        return Ok(Arc::new(package));
+        return Ok(DownloadUnit::from_configuration(&configuration, &credentials));
     }
-    pub fn new() -> DownloadUnit {
-        return DownloadUnit {};
+    // 按给出的软件源配置和认证信息创建，不读取配置文件
+    pub fn from_configuration(configuration: &SourceConfiguration, credentials: &CredentialList) -> DownloadUnit {
+        let transports = configuration.sources.iter()
+            .map(|source| {
+                let credential = credentials.credentials.iter().find(|c| c.source == source.name).cloned();
+                transport_for(source, &configuration.network, credential)
+            })
+            .collect();
+        let public_keys = configuration.sources.iter().map(|source| source.public_key.clone()).collect();
+        return DownloadUnit { transports, public_keys, signed_indexes: Mutex::new(HashMap::new()), architecture: configuration.architecture.clone() };
     }
 }
 // 解压组件，仅仅解压，不进行安装
@@ -148,16 +246,100 @@ impl DecompressUnit {
     pub fn new() -> DecompressUnit {
         return DecompressUnit {};
     }
+    // gzip 压缩的 tar 先解开压缩，得到 tar 格式的内容，没有压缩的原样返回
+    pub fn decode(&self, data : Vec<u8>) -> Result<Vec<u8>, std::io::Error> {
+        if !data.starts_with(&[0x1f, 0x8b]) {
+            return Ok(data);
+        }
+        let mut decoded = Vec::new();
+        GzDecoder::new(data.as_slice()).read_to_end(&mut decoded)?;
+        return Ok(decoded);
+    }
+    // 把tar格式的压缩包解压到目标目录
     pub fn install(&self, decoded_data : Vec<u8>, target_path: &str) -> Result<(), PackageManagerError>{
-        let compressed_data: Vec<u8> = decoded_data;
-        // 将数据写入文件
-        let mut file = match File::create(target_path.to_string()+"output.tar") {
-            Ok(f) => f,
-            Err(err) => return Err(PackageManagerError::PackageInstallFailed),
+        if let Err(err) = fs::create_dir_all(target_path) {
+            return Err(PackageManagerError::Io(target_path.to_string(), err));
+        }
+        // build 打出的包开头是 .package 目录下的元数据，不解压到安装目录
+        let mut archive = Archive::new(decoded_data.as_slice());
+        let entries = match archive.entries() {
+            Ok(e) => e,
+            Err(err) => return Err(PackageManagerError::DecompressError(target_path.to_string(), err)),
         };
-        match file.write_all(&compressed_data) {
-            Ok(r) => return Ok(()),
-            Err(err) => return Err(PackageManagerError::PackageInstallFailed),
+        for entry in entries {
+            let mut entry = match entry {
+                Ok(e) => e,
+                Err(err) => return Err(PackageManagerError::DecompressError(target_path.to_string(), err)),
+            };
+            let metadata = match entry.path() {
+                Ok(path) => is_metadata(&path.to_string_lossy()),
+                Err(err) => return Err(PackageManagerError::DecompressError(target_path.to_string(), err)),
+            };
+            if metadata {
+                continue;
+            }
+            if let Err(err) = entry.unpack_in(target_path) {
+                return Err(PackageManagerError::DecompressError(target_path.to_string(), err));
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+    use crate::entity::dependency::Dependency;
+    use crate::entity::source_config::{CredentialList, NetworkConfiguration, Source, SourceConfiguration};
+    use crate::manager::package_manager::PackageManagerError;
+    use crate::test::temp_directory;
+    use super::DownloadUnit;
+
+    #[test]
+    fn without_a_configuration_file_the_local_source_is_used() {
+        let directory = temp_directory("source-configuration-missing");
+        let path = directory.join("config.toml").display().to_string();
+        let credentials = directory.join("credentials.toml").display().to_string();
+        let unit = DownloadUnit::from_files(&path, &credentials).unwrap();
+        let locations: Vec<&str> = unit.transports.iter().map(|transport| transport.location()).collect();
+        assert_eq!(locations, vec!["http://127.0.0.1:8080"]);
+    }
+    #[test]
+    fn a_broken_configuration_file_is_an_error() {
+        let directory = temp_directory("source-configuration-broken");
+        let path = directory.join("config.toml");
+        let credentials = directory.join("credentials.toml").display().to_string();
+        fs::write(&path, "[[sources]]\nname = \"main\"\nurl = \"https://repo.example.com\"\n[network]\nca_certificate = [\"/etc/ca.pem\"\n").unwrap();
+        match DownloadUnit::from_files(&path.display().to_string(), &credentials) {
+            Err(PackageManagerError::ParseLocalFileError(location, _)) => assert_eq!(location, path.display().to_string()),
+            Err(other) => panic!("unexpected error {}", other),
+            Ok(_) => panic!("a broken configuration was accepted"),
+        }
+        // 能解析的配置照常使用
+        fs::write(&path, "[[sources]]\nname = \"main\"\nurl = \"https://repo.example.com\"\n").unwrap();
+        let unit = DownloadUnit::from_files(&path.display().to_string(), &credentials).unwrap();
+        assert_eq!(unit.transports[0].location(), "https://repo.example.com");
+    }
+    #[test]
+    fn archives_come_from_the_source_that_described_them() {
+        let directory = temp_directory("source-of-archive");
+        let (first, second) = (directory.join("first"), directory.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        // 第一个源只有一个同名的压缩包，没有这个包的详细信息
+        fs::write(first.join("hello-1.0.0.tar"), b"from the first source").unwrap();
+        fs::write(second.join("hello-1.0.0.tar"), b"from the second source").unwrap();
+        let description = "archive = \"hello\"\nversion_wrapper = \"1.0.0\"\ncomponent = \"main\"\norigin = \"test\"\nlabel = \"test\"\narchitecture = \"all\"\ndownload = \"\"\nothers = \"\"\n";
+        fs::write(second.join("hello-1.0.0.toml"), description).unwrap();
+        let source = |name: &str, url: &std::path::Path| Source { name: name.to_string(), url: url.display().to_string(), public_key: String::new() };
+        let configuration = SourceConfiguration {
+            sources: vec![source("first", &first), source("second", &second)],
+            network: NetworkConfiguration::default(),
+            architecture: String::new(),
         };
+        let unit = DownloadUnit::from_configuration(&configuration, &CredentialList::default());
+        let package = unit.get_package_information(Arc::new(Dependency::from_string("hello-1.0.0").unwrap())).unwrap();
+        assert_eq!(package.source, second.display().to_string());
+        assert_eq!(unit.download_software(package).unwrap(), b"from the second source".to_vec());
     }
 }
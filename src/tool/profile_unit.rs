@@ -0,0 +1,235 @@
+use std::{collections::HashSet, fs, io::ErrorKind, os::unix::fs::symlink, path::{Component, Path, PathBuf}, sync::Arc};
+use lazy_static::lazy_static;
+use crate::entity::dependency::Dependency;
+use crate::manager::package_manager::{store_path, PackageManagerError};
+
+// 应用的 profile：由指向安装目录的符号链接组成的目录树，合并了应用用到的所有包
+// 每个版本一个目录 profiles/应用.generations/版本号，profiles/应用 是指向当前版本的符号链接
+// 不同应用可以使用同一个库的不同版本，程序只需要知道自己的 profile 的地址
+lazy_static! {
+    static ref PROFILE_UNIT: Arc<ProfileUnit> = Arc::new(ProfileUnit::new("database/profiles"));
+}
+#[inline(always)]
+#[allow(dead_code)]
+pub fn profile_unit() -> &'static Arc<ProfileUnit> {
+    &PROFILE_UNIT
+}
+pub struct ProfileUnit {
+    path: String,
+}
+// 从目录 from 到 to 的相对路径，两个路径都相对于当前目录
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in to[common..].iter() {
+        path.push(component.as_os_str());
+    }
+    return path;
+}
+fn io_error(path: &Path, e: std::io::Error) -> PackageManagerError {
+    return PackageManagerError::Io(path.display().to_string(), e);
+}
+fn remove_directory(path: &Path) -> Result<(), PackageManagerError> {
+    match fs::remove_dir_all(path) {
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(io_error(path, e)),
+    }
+}
+impl ProfileUnit {
+    pub fn new(path: &str) -> ProfileUnit {
+        return ProfileUnit { path: path.to_string() };
+    }
+    // 应用当前版本的 profile，程序通过它找到依赖
+    pub fn link_path(&self, app: &str) -> String {
+        return format!("{}/{}", self.path, app);
+    }
+    fn generations_path(&self, app: &str) -> String {
+        return format!("{}/{}.generations", self.path, app);
+    }
+    pub fn generation_path(&self, app: &str, generation: usize) -> String {
+        return format!("{}/{}", self.generations_path(app), generation);
+    }
+    pub fn exists(&self, app: &str, generation: usize) -> bool {
+        return Path::new(&self.generation_path(app, generation)).is_dir();
+    }
+    // 生成应用某个版本的 profile，packages 为 archive-version，排在前面的包优先
+    // 两个包提供同一个文件时保留前一个，返回冲突的文件
+    // 先在临时目录中生成，完成后再换上去，生成失败时原来的 profile 不变
+    pub fn build(&self, app: &str, generation: usize, packages: &[String]) -> Result<Vec<String>, PackageManagerError> {
+        let target = PathBuf::from(self.generation_path(app, generation));
+        let building = PathBuf::from(format!("{}.tmp", target.display()));
+        let replaced = PathBuf::from(format!("{}.old", target.display()));
+        // 上次在两次改名之间中断，原来的 profile 还在一边
+        if !target.exists() && replaced.is_dir() {
+            if let Err(e) = fs::rename(&replaced, &target) {
+                return Err(io_error(&target, e));
+            }
+        }
+        for path in [&building, &replaced] {
+            remove_directory(path)?;
+        }
+        if let Err(e) = fs::create_dir_all(&building) {
+            return Err(io_error(&building, e));
+        }
+        let collisions = match self.link_packages(packages, &building, &target) {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&building);
+                return Err(e);
+            }
+        };
+        // 目录不能直接改名覆盖，先把原来的移开，换上新的再删除
+        let exists = target.exists();
+        if exists {
+            if let Err(e) = fs::rename(&target, &replaced) {
+                return Err(io_error(&target, e));
+            }
+        }
+        if let Err(e) = fs::rename(&building, &target) {
+            if exists {
+                let _ = fs::rename(&replaced, &target);
+            }
+            return Err(io_error(&target, e));
+        }
+        remove_directory(&replaced)?;
+        return Ok(collisions);
+    }
+    fn link_packages(&self, packages: &[String], building: &Path, target: &Path) -> Result<Vec<String>, PackageManagerError> {
+        let mut collisions = Vec::new();
+        for name in packages.iter() {
+            let dependency = match Dependency::from_string(name) {
+                Ok(d) => d,
+                Err(_) => return Err(PackageManagerError::ParseDependencyError(name.clone())),
+            };
+            let store = PathBuf::from(store_path(&dependency.archive, &dependency.version_wrapper));
+            // 没有文件的包没有安装目录
+            if !store.is_dir() {
+                continue;
+            }
+            self.link_tree(&store, building, target, &mut collisions)?;
+        }
+        return Ok(collisions);
+    }
+    // 把 source 下的文件链接到 destination，目录使用真实的目录，这样多个包可以合并到同一个目录
+    // 链接按最终位置 target 计算相对路径
+    fn link_tree(&self, source: &Path, destination: &Path, target: &Path, collisions: &mut Vec<String>) -> Result<(), PackageManagerError> {
+        let entries = match fs::read_dir(source) {
+            Ok(e) => e,
+            Err(e) => return Err(io_error(source, e)),
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(e) => return Err(io_error(source, e)),
+            };
+            let file_type = match entry.file_type() {
+                Ok(t) => t,
+                Err(e) => return Err(io_error(&entry.path(), e)),
+            };
+            let link = destination.join(entry.file_name());
+            let existing = fs::symlink_metadata(&link).ok();
+            if file_type.is_dir() {
+                match existing {
+                    Some(metadata) if metadata.is_dir() => {},
+                    Some(_) => {
+                        collisions.push(entry.path().display().to_string());
+                        continue;
+                    }
+                    None => if let Err(e) = fs::create_dir(&link) {
+                        return Err(io_error(&link, e));
+                    },
+                }
+                self.link_tree(&entry.path(), &link, &target.join(entry.file_name()), collisions)?;
+                continue;
+            }
+            if existing.is_some() {
+                collisions.push(entry.path().display().to_string());
+                continue;
+            }
+            if let Err(e) = symlink(relative_path(target, &entry.path()), &link) {
+                return Err(io_error(&link, e));
+            }
+        }
+        return Ok(());
+    }
+    // 把应用切换到某个版本的 profile，先建好新链接再改名覆盖，程序不会看到没有 profile 的时刻
+    pub fn switch(&self, app: &str, generation: usize) -> Result<(), PackageManagerError> {
+        let link = PathBuf::from(self.link_path(app));
+        let building = PathBuf::from(format!("{}.tmp", link.display()));
+        match fs::remove_file(&building) {
+            Ok(_) => {},
+            Err(e) if e.kind() == ErrorKind::NotFound => {},
+            Err(e) => return Err(io_error(&building, e)),
+        }
+        if let Err(e) = symlink(format!("{}.generations/{}", app, generation), &building) {
+            return Err(io_error(&building, e));
+        }
+        if let Err(e) = fs::rename(&building, &link) {
+            return Err(io_error(&link, e));
+        }
+        return Ok(());
+    }
+    // 删除应用不再保留的版本的 profile，keep 为保留的版本号
+    pub fn prune(&self, app: &str, keep: &HashSet<usize>) -> Result<(), PackageManagerError> {
+        let path = PathBuf::from(self.generations_path(app));
+        let entries = match fs::read_dir(&path) {
+            Ok(e) => e,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        for entry in entries.flatten() {
+            let kept = entry.file_name().to_str()
+                .and_then(|name| name.parse::<usize>().ok())
+                .is_some_and(|generation| keep.contains(&generation));
+            if kept {
+                continue;
+            }
+            if let Err(e) = fs::remove_dir_all(entry.path()) {
+                return Err(io_error(&entry.path(), e));
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+    use crate::manager::configuration_manager::configuration_manager;
+    use crate::manager::package_manager::VersionMode;
+    use crate::scheduler_module::scheduler;
+    use crate::test::sandbox::sandbox;
+    use super::profile_unit;
+
+    #[test]
+    fn a_failed_rebuild_keeps_the_old_profile() {
+        let _sandbox = sandbox(&[("a-1.0.0", "", "usr/bin/a"), ("b-1.0.0", "", "usr/bin/b")]);
+        scheduler().install("app", vec![("a".to_string(), VersionMode::Latest)], false).unwrap();
+        scheduler().install("other", vec![("b".to_string(), VersionMode::Latest)], false).unwrap();
+        let generation = configuration_manager().lock().unwrap().generation("app").unwrap();
+        let path = profile_unit().generation_path("app", generation);
+        let profile = Path::new(&path).join("usr/bin");
+        // b 已经链接进临时目录后才失败
+        let packages = ["b-1.0.0".to_string(), "not a package".to_string()];
+        assert!(profile_unit().build("app", generation, &packages).is_err());
+        assert!(profile.join("a").exists());
+        assert!(!profile.join("b").exists());
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        // 上次在两次改名之间中断，下次生成前先放回原来的 profile
+        fs::rename(&path, format!("{}.old", path)).unwrap();
+        assert!(profile_unit().build("app", generation, &packages).is_err());
+        assert!(profile.join("a").exists());
+
+        assert!(profile_unit().build("app", generation, &["b-1.0.0".to_string()]).unwrap().is_empty());
+        assert!(!profile.join("a").exists());
+        assert!(profile.join("b").exists());
+        assert!(!Path::new(&format!("{}.old", path)).exists());
+    }
+}
@@ -0,0 +1,271 @@
+use std::{collections::HashSet, fs, io::{ErrorKind, Read}, path::Path, sync::Arc};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use lazy_static::lazy_static;
+use serde_derive::Serialize;
+use tar::Archive;
+use crate::entity::dependency::{DependencyListTemp, Package};
+use crate::entity::repository::{IndexEntryTemp, RepositoryIndexTemp};
+use crate::manager::package_manager::PackageManagerError;
+use super::build_unit::METADATA_DIRECTORY;
+use super::cache_unit::sha256_hex;
+use super::network_module::decompress_unit;
+use super::transport::read_legacy_dependency_list;
+
+// 仓库组件，扫描一个目录中的压缩包，生成软件源需要的文件：
+//   archive-version.toml  软件包的详细信息，带上压缩包的大小和 sha256
+//   archive-version.txt   依赖列表
+//   index.toml            所有软件包的详细信息和依赖列表
+//   index.toml.sig        给出密钥时 index.toml 的 ed25519 签名，hex 编码
+// build 打出的包从 .package 目录中读取信息，其他压缩包使用旁边已有的 archive-version.toml 和 archive-version.txt
+lazy_static! {
+    static ref REPOSITORY_UNIT: Arc<RepositoryUnit> = Arc::new(RepositoryUnit::new());
+}
+#[inline(always)]
+#[allow(dead_code)]
+pub fn repository_unit() -> &'static Arc<RepositoryUnit> {
+    &REPOSITORY_UNIT
+}
+pub const INDEX_FILE: &str = "index.toml";
+pub const SIGNATURE_FILE: &str = "index.toml.sig";
+
+pub struct RepositoryUnit {}
+// 生成的索引
+#[derive(Debug, Clone, Serialize)]
+pub struct RepositoryIndex {
+    // index.toml 的路径
+    pub index: String,
+    // archive-version，按名字排列
+    pub packages: Vec<String>,
+    // 签名文件的路径和对应的公钥，没有签名时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+// hex 编码的 32 字节密钥
+fn key_bytes(content: &str) -> Option<[u8; 32]> {
+    return hex::decode(content.trim()).ok()?.try_into().ok();
+}
+// 用软件源配置中的公钥检查索引的签名
+pub fn verify_signature(content: &str, signature: &str, public_key: &str) -> Result<(), String> {
+    let key = match key_bytes(public_key).map(|bytes| VerifyingKey::from_bytes(&bytes)) {
+        Some(Ok(k)) => k,
+        _ => return Err("the public key of the source is not a hex-encoded ed25519 key".to_string()),
+    };
+    let signature = match hex::decode(signature.trim()).ok().and_then(|bytes| Signature::from_slice(&bytes).ok()) {
+        Some(s) => s,
+        None => return Err("the signature is not a hex-encoded ed25519 signature".to_string()),
+    };
+    match key.verify_strict(content.as_bytes(), &signature) {
+        Ok(_) => return Ok(()),
+        Err(_) => return Err("the signature does not match the index".to_string()),
+    }
+}
+fn read_signing_key(path: &Path) -> Result<SigningKey, PackageManagerError> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => return Err(PackageManagerError::Io(path.display().to_string(), e)),
+    };
+    match key_bytes(&content) {
+        Some(bytes) => return Ok(SigningKey::from_bytes(&bytes)),
+        None => return Err(PackageManagerError::InvalidKey(path.display().to_string())),
+    }
+}
+fn write(path: &Path, content: &[u8]) -> Result<(), PackageManagerError> {
+    match fs::write(path, content) {
+        Ok(_) => return Ok(()),
+        Err(e) => return Err(PackageManagerError::Io(path.display().to_string(), e)),
+    }
+}
+fn write_toml<T: serde::Serialize>(path: &Path, value: &T) -> Result<String, PackageManagerError> {
+    let content = match toml::to_string(value) {
+        Ok(c) => c,
+        Err(e) => return Err(PackageManagerError::WriteLocalFileError(path.display().to_string(), e)),
+    };
+    write(path, content.as_bytes())?;
+    return Ok(content);
+}
+// build 打出的包中 .package/软件名/ 下的软件包信息和依赖列表，不是 build 打出的包时为空
+fn read_metadata(data: &[u8]) -> Result<Option<(Package, Vec<String>)>, std::io::Error> {
+    let mut package: Option<Package> = None;
+    let mut dependencies = Vec::new();
+    let mut archive = Archive::new(data);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().trim_start_matches("./").to_string();
+        let name = match path.strip_prefix(&format!("{}/", METADATA_DIRECTORY)).and_then(|rest| rest.split_once('/')) {
+            Some((_, name)) => name.to_string(),
+            // 元数据在压缩包的开头，读到其他文件时就结束了
+            None if path == METADATA_DIRECTORY || path == format!("{}/", METADATA_DIRECTORY) => continue,
+            None => break,
+        };
+        let mut content = String::new();
+        match name.as_str() {
+            "package.toml" => {
+                entry.read_to_string(&mut content)?;
+                package = Some(toml::from_str(&content).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path, e)))?);
+            }
+            "dependencies.toml" => {
+                entry.read_to_string(&mut content)?;
+                let list: DependencyListTemp = toml::from_str(&content).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path, e)))?;
+                dependencies = list.dependencies;
+            }
+            _ => {},
+        }
+    }
+    return Ok(package.map(|package| (package, dependencies)));
+}
+impl RepositoryUnit {
+    pub fn new() -> RepositoryUnit {
+        return RepositoryUnit {};
+    }
+    // 压缩包对应的软件包信息和依赖列表，找不到时为空
+    fn read_package(&self, directory: &Path, file_name: &str, data: Vec<u8>) -> Result<Option<(Package, Vec<String>)>, PackageManagerError> {
+        let path = directory.join(file_name);
+        let decoded = match decompress_unit().decode(data) {
+            Ok(d) => d,
+            Err(e) => return Err(PackageManagerError::DecompressError(path.display().to_string(), e)),
+        };
+        match read_metadata(&decoded) {
+            Ok(Some(found)) => return Ok(Some(found)),
+            Ok(None) => {},
+            Err(e) => return Err(PackageManagerError::DecompressError(path.display().to_string(), e)),
+        }
+        let stem = file_name.trim_end_matches(".gz").trim_end_matches(".tar");
+        let information = directory.join(format!("{}.toml", stem));
+        let content = match fs::read_to_string(&information) {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(PackageManagerError::Io(information.display().to_string(), e)),
+        };
+        let package: Package = match toml::from_str(&content) {
+            Ok(p) => p,
+            Err(e) => return Err(PackageManagerError::ParseLocalFileError(information.display().to_string(), e)),
+        };
+        let list = directory.join(format!("{}.txt", stem));
+        let dependencies = match fs::read_to_string(&list) {
+            Ok(content) => match toml::from_str::<DependencyListTemp>(&content) {
+                Ok(l) => l.dependencies,
+                Err(e) => return Err(PackageManagerError::ParseLocalFileError(list.display().to_string(), e)),
+            },
+            // 没有时使用旧格式的 archive-主版本号.txt
+            Err(e) if e.kind() == ErrorKind::NotFound => match read_legacy_dependency_list(directory, &package.to_dependency())? {
+                Some((_, dependencies)) => dependencies,
+                None => Vec::new(),
+            },
+            Err(e) => return Err(PackageManagerError::Io(list.display().to_string(), e)),
+        };
+        return Ok(Some((package, dependencies)));
+    }
+    // 为 directory 中的压缩包生成索引，key 是 hex 编码的 ed25519 私钥文件
+    pub fn index(&self, directory: &Path, key: Option<&Path>) -> Result<RepositoryIndex, PackageManagerError> {
+        let signing_key = match key {
+            Some(path) => Some(read_signing_key(path)?),
+            None => None,
+        };
+        let mut names = Vec::new();
+        match fs::read_dir(directory) {
+            Ok(entries) => for entry in entries {
+                match entry {
+                    Ok(e) => names.push(e.file_name().to_string_lossy().to_string()),
+                    Err(e) => return Err(PackageManagerError::Io(directory.display().to_string(), e)),
+                }
+            },
+            Err(e) => return Err(PackageManagerError::Io(directory.display().to_string(), e)),
+        }
+        names.retain(|name| name.ends_with(".tar") || name.ends_with(".tar.gz"));
+        names.sort();
+        let mut entries: Vec<IndexEntryTemp> = Vec::new();
+        let mut seen = HashSet::new();
+        for name in names.iter() {
+            let path = directory.join(name);
+            let data = match fs::read(&path) {
+                Ok(d) => d,
+                Err(e) => return Err(PackageManagerError::Io(path.display().to_string(), e)),
+            };
+            let size = data.len() as u64;
+            let sha256 = sha256_hex(&data);
+            let (mut package, dependencies) = match self.read_package(directory, name, data)? {
+                Some(found) => found,
+                None => {
+                    log::warn!("ignore {}, it has no package information", path.display());
+                    continue;
+                }
+            };
+            // 每个软件包的文件名是 archive-version，不同架构的同一个版本会互相覆盖，不能放在同一个目录中
+            if !seen.insert(package.to_string()) {
+                return Err(PackageManagerError::DuplicatePackage(path.display().to_string(), package.to_string()));
+            }
+            package.size = size;
+            package.sha256 = sha256;
+            package.download = name.clone();
+            write_toml(&directory.join(format!("{}.toml", package.to_string())), &package)?;
+            write_toml(&directory.join(format!("{}.txt", package.to_string())), &DependencyListTemp { dependencies: dependencies.clone() })?;
+            entries.push(IndexEntryTemp { package, dependencies });
+        }
+        entries.sort_by_key(|a| a.package.to_string());
+        let index = directory.join(INDEX_FILE);
+        let content = write_toml(&index, &RepositoryIndexTemp { packages: entries.clone() })?;
+        // 不签名时删掉旧的签名，否则客户端会用旧签名检查新索引
+        let signature_path = directory.join(SIGNATURE_FILE);
+        let mut output = RepositoryIndex {
+            index: index.display().to_string(),
+            packages: entries.iter().map(|entry| entry.package.to_string()).collect(),
+            signature: None,
+            public_key: None,
+        };
+        match signing_key {
+            Some(key) => {
+                let signature = key.sign(content.as_bytes());
+                write(&signature_path, format!("{}\n", hex::encode(signature.to_bytes())).as_bytes())?;
+                output.signature = Some(signature_path.display().to_string());
+                output.public_key = Some(hex::encode(key.verifying_key().to_bytes()));
+            }
+            None => match fs::remove_file(&signature_path) {
+                Ok(_) => {},
+                Err(e) if e.kind() == ErrorKind::NotFound => {},
+                Err(e) => return Err(PackageManagerError::Io(signature_path.display().to_string(), e)),
+            },
+        }
+        return Ok(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use super::verify_signature;
+
+    fn sign(key: &SigningKey, content: &str) -> String {
+        return hex::encode(key.sign(content.as_bytes()).to_bytes());
+    }
+    #[test]
+    fn a_signed_index_is_accepted() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = hex::encode(key.verifying_key().to_bytes());
+        let content = "[[packages]]\narchive = \"a\"\n";
+        assert_eq!(verify_signature(content, &sign(&key, content), &public_key), Ok(()));
+        // 签名文件末尾的换行不影响
+        assert_eq!(verify_signature(content, &format!("{}\n", sign(&key, content)), &format!(" {}\n", public_key)), Ok(()));
+    }
+    #[test]
+    fn a_changed_index_or_another_key_is_rejected() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let public_key = hex::encode(key.verifying_key().to_bytes());
+        let content = "[[packages]]\narchive = \"a\"\n";
+        let mismatch = Err("the signature does not match the index".to_string());
+        assert_eq!(verify_signature("[[packages]]\narchive = \"b\"\n", &sign(&key, content), &public_key), mismatch);
+        assert_eq!(verify_signature(content, &sign(&other, content), &public_key), mismatch);
+    }
+    #[test]
+    fn malformed_keys_and_signatures_are_rejected() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = hex::encode(key.verifying_key().to_bytes());
+        let signature = sign(&key, "index");
+        assert!(verify_signature("index", &signature, "not hex").unwrap_err().contains("public key"));
+        assert!(verify_signature("index", &signature, &public_key[..32]).unwrap_err().contains("public key"));
+        assert!(verify_signature("index", "not hex", &public_key).unwrap_err().contains("signature is not"));
+        assert!(verify_signature("index", &signature[..64], &public_key).unwrap_err().contains("signature is not"));
+    }
+}
@@ -1,10 +1,10 @@
 use std::str::FromStr;
-use std::{fs, sync::Arc};
+use std::{fs, io::ErrorKind, path::Path, sync::Arc};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use semver::Version;
-use serde_derive::{Deserialize, Serialize};
-use serde_json::Value;
-use crate::entity::dependency::{self, BootstrapConfiguration, BootstrapConfigurationTemp, Dependency, DependencyListTemp, Package, PackageList, PackageListTemp};
-use crate::entity::software::{self, Software, SoftwareListTemp};
+use crate::entity::dependency::{ConfigurationListTemp, BootstrapConfiguration, BootstrapConfigurationTemp, Dependency, DependencyListTemp, Package, PackageListTemp};
+use crate::entity::software::{Software, SoftwareListTemp};
+use crate::entity::source_config::{CredentialList, SourceConfiguration};
 use crate::entity::version_wrapper::VersionWrapper;
 use crate::error::software_error::SoftwareManagerError;
 use crate::manager::package_manager::PackageManagerError;
@@ -25,6 +25,7 @@ impl ProfileHandler{
     
     
     // 读取本地文件并解析
+    #[allow(dead_code)]
     pub fn get_local_file (&self, path : String) -> Result<String, SoftwareManagerError>{
         match fs::read_to_string(path) {
             Ok(c) => {
@@ -33,11 +34,12 @@ impl ProfileHandler{
         };
     }
     // 解析string
+    #[allow(clippy::wrong_self_convention)]
     pub fn from_string_to_dependencies (&self, content : String) -> Result<Vec<Arc<Dependency>>, SoftwareManagerError>{
         if content.is_empty() {
             return Ok(Vec::new());
         }
-        println!("{}", content);
+        log::debug!("{}", content);
         // 解析 TOML 格式数据
         let vec : DependencyListTemp = match toml::from_str(&content) {
             Ok(d) => d ,
@@ -66,7 +68,13 @@ impl ProfileHandler{
             Ok(c) => c,
             Err(e) => return Err(SoftwareManagerError::ReadLocalOtherFileError(e.to_string())),
         };
-        let temp : BootstrapConfigurationTemp = match toml::from_str(&toml_content) {
+        let value : toml::Value = match toml::from_str(&toml_content) {
+            Ok(v) => v,
+            Err(e) => return Err(SoftwareManagerError::ParseDependencyError(e.to_string())),
+        };
+        // 配置写在 [bootstrap_configuration] 表中，也兼容直接写在顶层
+        let table = value.get("bootstrap_configuration").cloned().unwrap_or(value);
+        let temp : BootstrapConfigurationTemp = match table.try_into() {
             Ok(list) => list,
             Err(e) => return Err(SoftwareManagerError::ParseDependencyError(e.to_string())),
         };
@@ -89,15 +97,78 @@ impl ProfileHandler{
     }
     // 解析本地的package数据文件。在初始化时调用
     pub fn analyse_package_file (&self, path : String) -> Result<Vec<Arc<Package>>, PackageManagerError>{
-        let toml_content = match fs::read_to_string(path) {
+        let toml_content = match fs::read_to_string(&path) {
             Ok(c) => c,
-            Err(e) => return Err(PackageManagerError::ReadLocalPackageFileError(e.to_string())),
+            Err(e) => return Err(PackageManagerError::Io(path, e)),
         };
         let packages : PackageListTemp = match toml::from_str(&toml_content) {
             Ok(list) => list,
-            Err(e) => return Err(PackageManagerError::ReadLocalPackageFileError(e.to_string())),
+            Err(e) => return Err(PackageManagerError::ParseLocalFileError(path, e)),
         };
         return Ok(packages.packages.into_iter().map(Arc::new).collect());
     }
+    // 解析本地的configuration数据文件，文件不存在时返回空列表
+    pub fn analyse_configuration_file (&self, path : String) -> Result<ConfigurationListTemp, PackageManagerError>{
+        let toml_content = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ConfigurationListTemp::default()),
+            Err(e) => return Err(PackageManagerError::Io(path, e)),
+        };
+        match toml::from_str(&toml_content) {
+            Ok(list) => return Ok(list),
+            Err(e) => return Err(PackageManagerError::ParseLocalFileError(path, e)),
+        }
+    }
+    // 写入本地文件，先写临时文件再改名，避免写到一半时留下损坏的数据文件
+    pub fn write_local_file (&self, path : &str, content : &str) -> Result<(), PackageManagerError>{
+        let tmp_path = format!("{}.tmp", path);
+        if let Some(parent) = Path::new(path).parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                return Err(PackageManagerError::Io(parent.display().to_string(), e));
+            }
+        }
+        if let Err(e) = fs::write(&tmp_path, content) {
+            return Err(PackageManagerError::Io(tmp_path, e));
+        }
+        match fs::rename(&tmp_path, path) {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(PackageManagerError::Io(path.to_string(), e)),
+        }
+    }
+    // 解析软件源和网络设置的配置文件
+    pub fn analyse_source_file (&self, path : &str) -> Result<SourceConfiguration, PackageManagerError>{
+        let toml_content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::Io(path.to_string(), e)),
+        };
+        let mut configuration : SourceConfiguration = match toml::from_str(&toml_content) {
+            Ok(c) => c,
+            Err(e) => return Err(PackageManagerError::ParseLocalFileError(path.to_string(), e)),
+        };
+        configuration.network.apply_env();
+        return Ok(configuration);
+    }
+    // 解析私有软件源的认证信息，文件只能由root读写，否则拒绝使用
+    // 错误信息中不包含文件内容，避免泄露认证信息
+    pub fn analyse_credential_file (&self, path : String) -> Result<CredentialList, SoftwareManagerError>{
+        let metadata = match fs::metadata(&path) {
+            Ok(m) => m,
+            Err(e) => return Err(SoftwareManagerError::ReadLocalOtherFileError(format!("{}: {}", path, e))),
+        };
+        if metadata.permissions().mode() & 0o077 != 0 {
+            return Err(SoftwareManagerError::ReadLocalOtherFileError(format!("{} must only be accessible by its owner (chmod 600)", path)));
+        }
+        if metadata.uid() != 0 {
+            return Err(SoftwareManagerError::ReadLocalOtherFileError(format!("{} must be owned by root (chown root)", path)));
+        }
+        let toml_content = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(e) => return Err(SoftwareManagerError::ReadLocalOtherFileError(format!("{}: {}", path, e))),
+        };
+        match toml::from_str(&toml_content) {
+            Ok(list) => return Ok(list),
+            Err(_) => return Err(SoftwareManagerError::ReadLocalOtherFileError(format!("{} is not a valid credentials file", path))),
+        }
+    }
     //pub fn from_string_to_dependencies
 }
\ No newline at end of file
@@ -0,0 +1,256 @@
+use std::{io::{BufRead, BufReader, ErrorKind, Write}, net::{TcpListener, TcpStream}, path::{Component, Path, PathBuf}, str::FromStr, sync::Arc, thread, time::Duration};
+use base64::encode;
+use semver::Version;
+use crate::entity::dependency::{Dependency, Package};
+use crate::entity::response::{DependencyResponse, DownloadResponse, ErrorResponse, IndexResponse};
+use crate::entity::version_wrapper::VersionWrapper;
+use crate::manager::package_manager::PackageManagerError;
+use super::transport::{FileTransport, Transport};
+
+// 本地软件源服务器，用客户端访问 http 软件源的接口提供一个目录中的软件包，用于测试和小规模部署
+// 目录结构和 software-package/ 一样，可以先用 index 命令生成索引：
+//   /api/v1/software/list         软件包列表和 index.toml 的签名
+//   /api/v1/dependency/get        依赖列表
+//   /api/v1/software/information  软件包的详细信息，toml 格式
+//   其他路径                       目录中的压缩包，base64 编码
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+// 读取请求的超时时间
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct RepositoryServer {
+    listener: TcpListener,
+    // 提供的目录
+    directory: PathBuf,
+    transport: FileTransport,
+}
+// 一个请求的返回
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+impl Response {
+    fn json<T: serde::Serialize>(value: &T) -> Response {
+        match serde_json::to_string(value) {
+            Ok(body) => return Response { status: 200, content_type: "application/json", body },
+            Err(e) => return Response::error(500, e.to_string()),
+        }
+    }
+    fn error(status: u16, message: String) -> Response {
+        let body = serde_json::to_string(&ErrorResponse { status_code: status, message: message.clone() }).unwrap_or(message);
+        return Response { status, content_type: "application/json", body };
+    }
+    // 软件源中没有时返回 404，客户端会继续查找下一个源
+    fn from_error(error: &PackageManagerError) -> Response {
+        if error.is_not_found() {
+            return Response::error(404, error.to_string());
+        }
+        return Response::error(500, error.to_string());
+    }
+}
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+// 解码 %XX，plus 为真时把 + 换成空格，只有查询参数这样编码空格，路径中的 + 就是 +
+fn percent_decode(value: &str, plus: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    let hex = |b: u8| (b as char).to_digit(16);
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1).and_then(|b| hex(*b)), bytes.get(i + 2).and_then(|b| hex(*b))) {
+            (b'+', _, _) if plus => decoded.push(b' '),
+            (b'%', Some(high), Some(low)) => {
+                decoded.push((high * 16 + low) as u8);
+                i += 2;
+            }
+            (b, _, _) => decoded.push(b),
+        }
+        i += 1;
+    }
+    return String::from_utf8_lossy(&decoded).to_string();
+}
+// 解码查询参数中的 %XX 和 +
+fn decode_component(value: &str) -> String {
+    return percent_decode(value, true);
+}
+// 解码路径中的 %XX
+fn decode_path(value: &str) -> String {
+    return percent_decode(value, false);
+}
+fn query_value(query: &str, key: &str) -> Option<String> {
+    return query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| decode_component(value));
+}
+// 查询参数中的 archive 和 version
+fn query_dependency(query: &str) -> Result<Dependency, Response> {
+    let archive = match query_value(query, "archive").filter(|archive| !archive.is_empty()) {
+        Some(a) => a,
+        None => return Err(Response::error(400, "archive is required".to_string())),
+    };
+    let version = match query_value(query, "version").map(|version| Version::from_str(&version)) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => return Err(Response::error(400, format!("invalid version: {}", e))),
+        None => return Err(Response::error(400, "version is required".to_string())),
+    };
+    return Ok(Dependency::new(archive, VersionWrapper::new(version)));
+}
+impl RepositoryServer {
+    // 监听 listen 地址，提供 directory 中的软件包
+    pub fn bind(directory: &Path, listen: &str) -> Result<RepositoryServer, PackageManagerError> {
+        if !directory.is_dir() {
+            return Err(PackageManagerError::PackageNotFound(directory.display().to_string()));
+        }
+        let listener = match TcpListener::bind(listen) {
+            Ok(l) => l,
+            Err(e) => return Err(PackageManagerError::Io(listen.to_string(), e)),
+        };
+        let path = directory.display().to_string();
+        let transport = FileTransport::new(&path, &path);
+        return Ok(RepositoryServer { listener, directory: directory.to_path_buf(), transport });
+    }
+    // 实际监听的地址，端口为 0 时由系统分配
+    pub fn address(&self) -> String {
+        match self.listener.local_addr() {
+            Ok(address) => return address.to_string(),
+            Err(_) => return String::new(),
+        }
+    }
+    // 处理请求直到进程被结束，每个连接一个线程
+    pub fn run(self) -> Result<(), PackageManagerError> {
+        let server = Arc::new(self);
+        for stream in server.listener.incoming() {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    log::warn!("accept failed: {}", e);
+                    continue;
+                }
+            };
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.handle(stream) {
+                    log::warn!("{}", e);
+                }
+            });
+        }
+        return Ok(());
+    }
+    fn handle(&self, mut stream: TcpStream) -> Result<(), std::io::Error> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // 不需要请求头，读完丢掉
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+        let mut parts = request.split_whitespace();
+        let response = match (parts.next(), parts.next()) {
+            (Some("GET"), Some(target)) => self.route(target),
+            (Some(_), Some(_)) => Response::error(405, "only GET is supported".to_string()),
+            _ => Response::error(400, "malformed request".to_string()),
+        };
+        log::info!("{} {}", request.trim(), response.status);
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            response.status, reason(response.status), response.content_type, response.body.len());
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(response.body.as_bytes())?;
+        return stream.flush();
+    }
+    fn route(&self, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        match path {
+            "/api/v1/software/list" => match self.transport.fetch_index() {
+                Ok(fetched) => return Response::json(&IndexResponse { data: fetched.content, signature: fetched.signature.unwrap_or_default() }),
+                Err(e) => return Response::from_error(&e),
+            },
+            "/api/v1/dependency/get" => {
+                let dependency = match query_dependency(query) {
+                    Ok(d) => d,
+                    Err(response) => return response,
+                };
+                match self.transport.fetch_dependency_list(&dependency) {
+                    Ok(fetched) => return Response::json(&DependencyResponse { data: fetched.content }),
+                    Err(e) if e.is_not_found() => return Response::error(404, format!("{} not found", dependency.to_string())),
+                    Err(e) => return Response::error(500, e.to_string()),
+                }
+            }
+            "/api/v1/software/information" => {
+                let dependency = match query_dependency(query) {
+                    Ok(d) => d,
+                    Err(response) => return response,
+                };
+                let fetched = match self.transport.fetch_package_information(&dependency) {
+                    Ok(f) => f,
+                    Err(e) if e.is_not_found() => return Response::error(404, format!("{} not found", dependency.to_string())),
+                    Err(e) => return Response::from_error(&e),
+                };
+                return self.information(&fetched.content);
+            }
+            _ => return self.archive(&decode_path(path)),
+        }
+    }
+    // 没有下载地址的包用目录中的 archive-version.tar，客户端会到这个服务器下载
+    fn information(&self, content: &str) -> Response {
+        let mut package: Package = match toml::from_str(content) {
+            Ok(p) => p,
+            Err(e) => return Response::error(500, format!("invalid package information: {}", e)),
+        };
+        if !package.download.is_empty() {
+            return Response { status: 200, content_type: "application/toml", body: content.to_string() };
+        }
+        package.download = format!("{}.tar", package.to_string());
+        match toml::to_string(&package) {
+            Ok(body) => return Response { status: 200, content_type: "application/toml", body },
+            Err(e) => return Response::error(500, e.to_string()),
+        }
+    }
+    // 目录中的压缩包，路径不能跳出目录
+    fn archive(&self, path: &str) -> Response {
+        let relative = Path::new(path.trim_start_matches('/'));
+        if relative.as_os_str().is_empty() || relative.components().any(|component| !matches!(component, Component::Normal(_))) {
+            return Response::error(404, format!("{} not found", path));
+        }
+        let file = self.directory.join(relative);
+        match std::fs::read(&file) {
+            Ok(data) => return Response::json(&DownloadResponse { data: encode(data) }),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Response::error(404, format!("{} not found", path)),
+            Err(e) => return Response::error(500, format!("{}: {}", path, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_component, decode_path, query_value};
+
+    #[test]
+    fn escapes_and_plus_are_decoded() {
+        assert_eq!(decode_component("lib%2Dgreet"), "lib-greet");
+        assert_eq!(decode_component("a+b%20c"), "a b c");
+        assert_eq!(decode_component("1.0.0%2bbuild"), "1.0.0+build");
+        assert_eq!(query_value("archive=hello&version=1.0.0%2Brc", "version"), Some("1.0.0+rc".to_string()));
+        // 路径中的 + 不是空格
+        assert_eq!(decode_path("/hello-1.0.0+build.tar"), "/hello-1.0.0+build.tar");
+        assert_eq!(decode_path("/hello%201.0.0%2Bbuild.tar"), "/hello 1.0.0+build.tar");
+    }
+    #[test]
+    fn broken_escapes_are_kept() {
+        assert_eq!(decode_component("%zz"), "%zz");
+        assert_eq!(decode_component("a%4"), "a%4");
+        assert_eq!(decode_component("%"), "%");
+    }
+}
@@ -0,0 +1,644 @@
+use std::{env, fs, io::ErrorKind, path::{Path, PathBuf}, sync::OnceLock};
+use base64::decode;
+use tokio::runtime::Runtime;
+use crate::entity::dependency::{Dependency, DependencyListTemp, LegacyDependencyListTemp, Package, PackageListTemp};
+use crate::entity::response::{ApiResponse, DependencyResponse, DownloadResponse, ErrorResponse, IndexResponse};
+use crate::entity::source_config::{Credential, NetworkConfiguration, Source};
+use crate::error::software_error::SoftwareManagerError;
+use crate::manager::package_manager::PackageManagerError;
+use super::arch_unit::architecture;
+use super::repository_unit::{INDEX_FILE, SIGNATURE_FILE};
+
+// 传输层，负责从一个软件源取回原始内容，解析由 DownloadUnit 完成
+// http(s):// 的源使用服务器接口，file:// 或者本地目录的源直接读取文件
+pub trait Transport: Send + Sync {
+    // 软件源的地址
+    fn location(&self) -> &str;
+    // 获取依赖列表，内容为toml格式
+    fn fetch_dependency_list(&self, dependency: &Dependency) -> Result<Fetched, SoftwareManagerError>;
+    // 获取软件包详细信息，内容为toml格式的 Package
+    fn fetch_package_information(&self, dependency: &Dependency) -> Result<Fetched, PackageManagerError>;
+    // 下载压缩包
+    fn fetch_archive(&self, package: &Package) -> Result<Vec<u8>, PackageManagerError>;
+    // 获取源中所有软件包的列表，内容为toml格式的 PackageListTemp
+    fn fetch_index(&self) -> Result<Fetched, PackageManagerError>;
+}
+// 取回的内容和它的具体地址，地址用于错误信息
+pub struct Fetched {
+    pub location: String,
+    pub content: String,
+    // 索引的签名，只有 fetch_index 会取回
+    pub signature: Option<String>,
+}
+// 根据软件源的地址选择传输方式
+pub fn transport_for(source: &Source, network: &NetworkConfiguration, credential: Option<Credential>) -> Box<dyn Transport> {
+    let url = source.url.trim_end_matches('/');
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return Box::new(HttpTransport::new(url, network.clone(), credential));
+    }
+    let path = url.strip_prefix("file://").unwrap_or(url);
+    return Box::new(FileTransport::new(url, path));
+}
+// 错误信息中保留的返回内容长度
+const BODY_EXCERPT_LENGTH: usize = 200;
+// 截取返回内容的开头部分，放进错误信息里
+pub fn body_excerpt(body: &str) -> String {
+    if body.chars().count() <= BODY_EXCERPT_LENGTH {
+        return body.to_string();
+    }
+    let excerpt : String = body.chars().take(BODY_EXCERPT_LENGTH).collect();
+    return format!("{}...", excerpt);
+}
+
+// url 是否在软件源 source 下面：协议、主机和端口都相同，路径在源的路径下面
+// 用于判断能否带上源的认证信息，下载地址指向其他主机时不能带
+fn within_source(source: &str, url: &str) -> bool {
+    let (source, url) = match (reqwest::Url::parse(source), reqwest::Url::parse(url)) {
+        (Ok(s), Ok(u)) => (s, u),
+        _ => return false,
+    };
+    if source.scheme() != url.scheme() || source.host_str() != url.host_str() || source.port_or_known_default() != url.port_or_known_default() {
+        return false;
+    }
+    // 路径要在分隔符处对齐，http://host/repo 不包括 http://host/repository
+    let base = source.path().trim_end_matches('/');
+    match url.path().strip_prefix(base) {
+        Some(rest) => return rest.is_empty() || rest.starts_with('/'),
+        None => return false,
+    }
+}
+
+// 要使用的代理，(适用的协议, 代理地址)，协议为空表示所有协议
+// 配置中有代理时只用它，否则和 reqwest 一样读取环境变量，http_proxy 只认小写
+fn proxy_urls(network: &NetworkConfiguration, var: &dyn Fn(&str) -> Option<String>) -> Vec<(&'static str, String)> {
+    if !network.proxy.is_empty() {
+        return vec![("", network.proxy.clone())];
+    }
+    let first = |names: &[&str]| names.iter().filter_map(|name| var(name)).find(|value| !value.is_empty());
+    let mut proxies = Vec::new();
+    if let Some(url) = first(&["http_proxy"]) {
+        proxies.push(("http", url));
+    }
+    if let Some(url) = first(&["HTTPS_PROXY", "https_proxy"]) {
+        proxies.push(("https", url));
+    }
+    if let Some(url) = first(&["ALL_PROXY", "all_proxy"]) {
+        proxies.push(("", url));
+    }
+    return proxies;
+}
+// 不走代理的地址：配置中的和环境变量 NO_PROXY 中的
+fn no_proxy_list(network: &NetworkConfiguration, var: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut list = network.no_proxy.clone();
+    if let Some(value) = ["NO_PROXY", "no_proxy"].iter().filter_map(|name| var(name)).find(|value| !value.is_empty()) {
+        list.push(value);
+    }
+    return list.join(",");
+}
+
+// 通过服务器接口访问的软件源
+pub struct HttpTransport {
+    // 源的地址，如 http://127.0.0.1:8080
+    url: String,
+    // 网络设置
+    network: NetworkConfiguration,
+    // 认证信息，只发给这个源
+    credential: Option<Credential>,
+    // 第一次请求时创建，之后的请求共用同一个运行时和客户端
+    client: OnceLock<(Runtime, reqwest::Client)>,
+}
+impl HttpTransport {
+    pub fn new(url: &str, network: NetworkConfiguration, credential: Option<Credential>) -> HttpTransport {
+        return HttpTransport { url: url.to_string(), network, credential, client: OnceLock::new() };
+    }
+    // 运行时和客户端，还没有创建时按网络设置创建
+    fn client(&self) -> Result<&(Runtime, reqwest::Client), PackageManagerError> {
+        if let Some(client) = self.client.get() {
+            return Ok(client);
+        }
+        let client = self.build_client()?;
+        // 创建一个新的 tokio 运行时环境
+        let rt = match Runtime::new() {
+            Ok(rt) => rt,
+            Err(err) => return Err(PackageManagerError::Io(self.url.clone(), err)),
+        };
+        return Ok(self.client.get_or_init(|| (rt, client)));
+    }
+    // 根据网络设置创建客户端
+    fn build_client(&self) -> Result<reqwest::Client, PackageManagerError> {
+        let network = &self.network;
+        // 不让 reqwest 自己读取环境变量中的代理，否则配置中的 no_proxy 对它不生效
+        let mut builder = reqwest::Client::builder().no_proxy();
+        let var = |name: &str| env::var(name).ok();
+        let no_proxy = no_proxy_list(network, &var);
+        for (scheme, url) in proxy_urls(network, &var) {
+            let proxy = match scheme {
+                "http" => reqwest::Proxy::http(&url),
+                "https" => reqwest::Proxy::https(&url),
+                _ => reqwest::Proxy::all(&url),
+            };
+            match proxy {
+                Ok(p) => builder = builder.proxy(p.no_proxy(reqwest::NoProxy::from_string(&no_proxy))),
+                Err(err) => return Err(PackageManagerError::ClientError(url, err)),
+            }
+        }
+        for path in network.ca_certificates.iter() {
+            let pem = match fs::read(path) {
+                Ok(p) => p,
+                Err(err) => return Err(PackageManagerError::Io(path.clone(), err)),
+            };
+            let certificates = match reqwest::Certificate::from_pem_bundle(&pem) {
+                Ok(c) => c,
+                Err(err) => return Err(PackageManagerError::ClientError(path.clone(), err)),
+            };
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if !network.client_certificate.is_empty() {
+            let certificate = match fs::read(&network.client_certificate) {
+                Ok(c) => c,
+                Err(err) => return Err(PackageManagerError::Io(network.client_certificate.clone(), err)),
+            };
+            let key = match fs::read(&network.client_key) {
+                Ok(k) => k,
+                Err(err) => return Err(PackageManagerError::Io(network.client_key.clone(), err)),
+            };
+            match reqwest::Identity::from_pkcs8_pem(&certificate, &key) {
+                Ok(identity) => builder = builder.identity(identity),
+                Err(err) => return Err(PackageManagerError::ClientError(network.client_certificate.clone(), err)),
+            }
+        }
+        match builder.build() {
+            Ok(client) => return Ok(client),
+            Err(err) => return Err(PackageManagerError::ClientError(self.url.clone(), err)),
+        }
+    }
+    // 发送get请求，返回状态码和返回内容，请求失败时返回带地址和原因的错误
+    fn send_get(&self, url: &str) -> Result<(u16, String), PackageManagerError> {
+        let (rt, client) = self.client()?;
+        // 在异步上下文中执行异步函数并等待结果返回
+        return rt.block_on(async {
+            self.send_get_async(client, url).await
+        });
+    }
+    async fn send_get_async(&self, client: &reqwest::Client, url: &str) -> Result<(u16, String), PackageManagerError> {
+        let mut request = client.get(url);
+        // 认证信息只发给这个源下面的地址
+        if let Some(credential) = self.credential.as_ref().filter(|_| within_source(&self.url, url)) {
+            if !credential.token.is_empty() {
+                request = request.bearer_auth(&credential.token);
+            } else {
+                request = request.basic_auth(&credential.username, Some(&credential.password));
+            }
+        }
+        let response = match request.send().await {
+            Ok(r) => r,
+            Err(err) => return Err(PackageManagerError::RequestError(url.to_string(), err)),
+        };
+        let status = response.status().as_u16();
+        match response.text().await {
+            Ok(body) => return Ok((status, body)),
+            Err(err) => return Err(PackageManagerError::RequestError(url.to_string(), err)),
+        }
+    }
+}
+impl Transport for HttpTransport {
+    fn location(&self) -> &str {
+        return &self.url;
+    }
+    fn fetch_dependency_list(&self, dependency: &Dependency) -> Result<Fetched, SoftwareManagerError> {
+        let url = format!("{}/api/v1/dependency/get?archive={}&version={}", self.url, dependency.archive, dependency.version_wrapper.version);
+        let (status, body) = match self.send_get(&url) {
+            Ok(r) => r,
+            Err(err) => return Err(SoftwareManagerError::from(err)),
+        };
+        if status == 401 || status == 403 {
+            return Err(PackageManagerError::AuthenticationError(url, status).into());
+        }
+        if !(200..300).contains(&status) {
+            return Err(PackageManagerError::HttpStatusError(url, status, body_excerpt(&body)).into());
+        }
+        match serde_json::from_str::<ApiResponse<DependencyResponse>>(&body) {
+            Ok(ApiResponse::Success(d)) => return Ok(Fetched { location: url, content: d.data, signature: None }),
+            Ok(ApiResponse::Failure(e)) => return Err(PackageManagerError::ServerError(url, e.status_code, e.message).into()),
+            Err(_) => return Err(PackageManagerError::MalformedResponse(url, body_excerpt(&body)).into()),
+        }
+    }
+    fn fetch_package_information(&self, dependency: &Dependency) -> Result<Fetched, PackageManagerError> {
+        // 服务器可以根据架构返回对应的包
+        let url = format!("{}/api/v1/software/information?archive={}&version={}&architecture={}", self.url, dependency.archive, dependency.version_wrapper.version, architecture());
+        let (status, body) = match self.send_get(&url) {
+            Ok(r) => r,
+            Err(err) => return Err(err),
+        };
+        if status == 401 || status == 403 {
+            return Err(PackageManagerError::AuthenticationError(url, status));
+        }
+        // 出错时服务器返回json格式的错误信息，成功时返回toml格式的 Package
+        if let Ok(e) = serde_json::from_str::<ErrorResponse>(&body) {
+            return Err(PackageManagerError::ServerError(url, e.status_code, e.message));
+        }
+        if !(200..300).contains(&status) {
+            return Err(PackageManagerError::HttpStatusError(url, status, body_excerpt(&body)));
+        }
+        return Ok(Fetched { location: url, content: body, signature: None });
+    }
+    fn fetch_archive(&self, package: &Package) -> Result<Vec<u8>, PackageManagerError> {
+        // 下载地址可以是完整地址，也可以是相对于源的路径
+        let download = package.download();
+        let url = if download.starts_with("http://") || download.starts_with("https://") {
+            download
+        } else {
+            format!("{}/{}", self.url, download.trim_start_matches('/'))
+        };
+        let (status, body) = match self.send_get(&url) {
+            Ok(r) => r,
+            Err(err) => return Err(err),
+        };
+        if status == 401 || status == 403 {
+            return Err(PackageManagerError::AuthenticationError(url, status));
+        }
+        if !(200..300).contains(&status) {
+            return Err(PackageManagerError::HttpStatusError(url, status, body_excerpt(&body)));
+        }
+        // 根据返回结果操作
+        let file : DownloadResponse = match serde_json::from_str::<ApiResponse<DownloadResponse>>(&body) {
+            Ok(ApiResponse::Success(f)) => f,
+            Ok(ApiResponse::Failure(e)) => return Err(PackageManagerError::ServerError(url, e.status_code, e.message)),
+            Err(_) => return Err(PackageManagerError::MalformedResponse(url, body_excerpt(&body))),
+        };
+        // 获得返回结果，解码压缩包
+        match decode(&file.data) {
+            Ok(decoded) => return Ok(decoded),
+            Err(_) => {
+                return Err(PackageManagerError::InvalidArchiveEncoding(url, body_excerpt(&file.data)));
+            }
+        }
+    }
+    fn fetch_index(&self) -> Result<Fetched, PackageManagerError> {
+        let url = format!("{}/api/v1/software/list", self.url);
+        let (status, body) = match self.send_get(&url) {
+            Ok(r) => r,
+            Err(err) => return Err(err),
+        };
+        if status == 401 || status == 403 {
+            return Err(PackageManagerError::AuthenticationError(url, status));
+        }
+        if !(200..300).contains(&status) {
+            return Err(PackageManagerError::HttpStatusError(url, status, body_excerpt(&body)));
+        }
+        match serde_json::from_str::<ApiResponse<IndexResponse>>(&body) {
+            Ok(ApiResponse::Success(d)) => {
+                let signature = Some(d.signature).filter(|s| !s.is_empty());
+                return Ok(Fetched { location: url, content: d.data, signature });
+            }
+            Ok(ApiResponse::Failure(e)) => return Err(PackageManagerError::ServerError(url, e.status_code, e.message)),
+            Err(_) => return Err(PackageManagerError::MalformedResponse(url, body_excerpt(&body))),
+        }
+    }
+}
+
+// 目录中旧格式的依赖列表 archive-主版本号.txt，转换成 archive-version 格式，没有这个文件时为空
+pub fn read_legacy_dependency_list(directory: &Path, dependency: &Dependency) -> Result<Option<(PathBuf, Vec<String>)>, PackageManagerError> {
+    let path = directory.join(format!("{}-{}.txt", dependency.archive, dependency.version_wrapper.version.major));
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(PackageManagerError::Io(path.display().to_string(), err)),
+    };
+    match toml::from_str::<LegacyDependencyListTemp>(&content) {
+        Ok(list) => return Ok(Some((path, list.to_dependencies()))),
+        Err(err) => return Err(PackageManagerError::ParseLocalFileError(path.display().to_string(), err)),
+    }
+}
+
+// 本地目录形式的软件源，目录结构和 software-package/ 一样：
+// archive-version.txt 为依赖列表，archive-version.toml 为详细信息，archive-version.tar 为压缩包
+// 没有 archive-version.txt 时使用旧格式的 archive-主版本号.txt
+// 有 index 命令生成的 index.toml 时直接使用它和它的签名
+pub struct FileTransport {
+    // 配置中的地址
+    url: String,
+    // 目录
+    path: PathBuf,
+}
+impl FileTransport {
+    pub fn new(url: &str, path: &str) -> FileTransport {
+        // 使用绝对路径，切换到 sysroot 后仍然能找到目录
+        let path = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        return FileTransport { url: url.to_string(), path };
+    }
+    // 目录中软件包对应的文件
+    fn file_path(&self, dependency: &Dependency, extension: &str) -> PathBuf {
+        return self.path.join(format!("{}.{}", dependency.to_string(), extension));
+    }
+    // 压缩包的路径，下载地址是 file:// 地址、绝对路径或者相对于目录的路径
+    fn archive_path(&self, package: &Package) -> Option<PathBuf> {
+        let download = package.download();
+        if download.starts_with("http://") || download.starts_with("https://") {
+            return None;
+        }
+        if download.is_empty() {
+            return Some(self.path.join(format!("{}.tar", package.to_string())));
+        }
+        let path = download.strip_prefix("file://").unwrap_or(&download);
+        if Path::new(path).is_absolute() {
+            return Some(PathBuf::from(path));
+        }
+        return Some(self.path.join(path));
+    }
+}
+impl Transport for FileTransport {
+    fn location(&self) -> &str {
+        return &self.url;
+    }
+    fn fetch_dependency_list(&self, dependency: &Dependency) -> Result<Fetched, SoftwareManagerError> {
+        let path = self.file_path(dependency, "txt");
+        let location = path.display().to_string();
+        match fs::read_to_string(&path) {
+            Ok(content) => return Ok(Fetched { location, content, signature: None }),
+            Err(err) if err.kind() == ErrorKind::NotFound => {},
+            Err(err) => return Err(PackageManagerError::Io(location, err).into()),
+        }
+        let (path, dependencies) = match read_legacy_dependency_list(&self.path, dependency)? {
+            Some(found) => found,
+            None => return Err(PackageManagerError::PackageNotFound(location).into()),
+        };
+        match toml::to_string(&DependencyListTemp { dependencies }) {
+            Ok(content) => return Ok(Fetched { location: path.display().to_string(), content, signature: None }),
+            Err(err) => return Err(PackageManagerError::MalformedResponse(path.display().to_string(), err.to_string()).into()),
+        }
+    }
+    fn fetch_package_information(&self, dependency: &Dependency) -> Result<Fetched, PackageManagerError> {
+        let path = self.file_path(dependency, "toml");
+        let location = path.display().to_string();
+        match fs::read_to_string(&path) {
+            Ok(content) => return Ok(Fetched { location, content, signature: None }),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(PackageManagerError::PackageNotFound(location)),
+            Err(err) => return Err(PackageManagerError::Io(location, err)),
+        }
+    }
+    fn fetch_archive(&self, package: &Package) -> Result<Vec<u8>, PackageManagerError> {
+        let path = match self.archive_path(package) {
+            Some(p) => p,
+            None => return Err(PackageManagerError::PackageNotFound(package.download())),
+        };
+        let location = path.display().to_string();
+        match fs::read(&path) {
+            Ok(data) => return Ok(data),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(PackageManagerError::PackageNotFound(location)),
+            Err(err) => return Err(PackageManagerError::Io(location, err)),
+        }
+    }
+    fn fetch_index(&self) -> Result<Fetched, PackageManagerError> {
+        let index = self.path.join(INDEX_FILE);
+        match fs::read_to_string(&index) {
+            Ok(content) => {
+                let signature = fs::read_to_string(self.path.join(SIGNATURE_FILE)).ok();
+                return Ok(Fetched { location: index.display().to_string(), content, signature });
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {},
+            Err(err) => return Err(PackageManagerError::Io(index.display().to_string(), err)),
+        }
+        // 目录中每个 archive-version.toml 都是一个软件包的详细信息
+        let location = self.path.display().to_string();
+        let dir = match fs::read_dir(&self.path) {
+            Ok(d) => d,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Err(PackageManagerError::PackageNotFound(location)),
+            Err(err) => return Err(PackageManagerError::Io(location, err)),
+        };
+        let mut packages = Vec::new();
+        for entry in dir.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|e| e != "toml") {
+                continue;
+            }
+            let content = match fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(err) => return Err(PackageManagerError::Io(path.display().to_string(), err)),
+            };
+            match toml::from_str::<Package>(&content) {
+                Ok(package) => packages.push(package),
+                Err(_) => log::warn!("ignore {}, not a package description", path.display()),
+            }
+        }
+        packages.sort_by_key(|a| a.to_string());
+        match toml::to_string(&PackageListTemp { packages }) {
+            Ok(content) => return Ok(Fetched { location, content, signature: None }),
+            Err(err) => return Err(PackageManagerError::MalformedResponse(location, err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{BufRead, BufReader, Write}, net::TcpListener, thread};
+    use crate::entity::dependency::{Dependency, Package};
+    use crate::entity::source_config::NetworkConfiguration;
+    use crate::error::software_error::SoftwareManagerError;
+    use crate::manager::package_manager::PackageManagerError;
+    use crate::entity::dependency::{DependencyListTemp, PackageListTemp};
+use crate::entity::source_config::Source;
+use crate::test::fixture;
+use super::{body_excerpt, no_proxy_list, proxy_urls, transport_for, within_source, FileTransport, HttpTransport, Transport, BODY_EXCERPT_LENGTH};
+
+    // 对每个请求都返回同样状态码和内容的服务器，返回它的地址
+    fn serve(status: u16, body: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let body = body.to_string();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let response = format!("HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        return url;
+    }
+    fn responding(status: u16, body: &str) -> (String, HttpTransport) {
+        let url = serve(status, body);
+        let transport = HttpTransport::new(&url, NetworkConfiguration::default(), None);
+        return (url, transport);
+    }
+    fn package(download: &str) -> Package {
+        let dependency = Dependency::from_string("hello-1.0.0").unwrap();
+        return Package::new(dependency.archive, dependency.version_wrapper, String::new(), String::new(), String::new(), String::new(), download.to_string(), String::new());
+    }
+
+    #[test]
+    fn a_body_that_is_not_the_schema_is_malformed() {
+        let (url, transport) = responding(200, "<html>maintenance</html>");
+        match transport.fetch_index() {
+            Err(PackageManagerError::MalformedResponse(location, body)) => {
+                assert_eq!(location, format!("{}/api/v1/software/list", url));
+                assert_eq!(body, "<html>maintenance</html>");
+            }
+            other => panic!("unexpected result {:?}", other.map(|fetched| fetched.content)),
+        }
+        // 字段类型不对也不是合法的返回
+        let (_, transport) = responding(200, r#"{"data": 1}"#);
+        let dependency = Dependency::from_string("hello-1.0.0").unwrap();
+        assert!(matches!(transport.fetch_dependency_list(&dependency), Err(SoftwareManagerError::Package(PackageManagerError::MalformedResponse(_, _)))));
+        assert!(matches!(transport.fetch_archive(&package("hello-1.0.0.tar")), Err(PackageManagerError::MalformedResponse(_, _))));
+    }
+    #[test]
+    fn server_errors_keep_the_status_and_message() {
+        let (_, transport) = responding(200, r#"{"status_code": 404, "message": "no such package"}"#);
+        let dependency = Dependency::from_string("hello-1.0.0").unwrap();
+        match transport.fetch_package_information(&dependency) {
+            Err(err @ PackageManagerError::ServerError(_, 404, _)) => {
+                assert!(err.is_not_found());
+                assert!(err.to_string().contains("no such package"));
+            }
+            other => panic!("unexpected result {:?}", other.map(|fetched| fetched.content)),
+        }
+        assert!(matches!(transport.fetch_index(), Err(PackageManagerError::ServerError(_, 404, _))));
+    }
+    #[test]
+    fn unexpected_statuses_are_errors() {
+        let (_, transport) = responding(502, "bad gateway");
+        assert!(matches!(transport.fetch_index(), Err(PackageManagerError::HttpStatusError(_, 502, body)) if body == "bad gateway"));
+        assert!(matches!(transport.fetch_archive(&package("hello-1.0.0.tar")), Err(PackageManagerError::HttpStatusError(_, 502, _))));
+        let (_, transport) = responding(401, "");
+        assert!(matches!(transport.fetch_index(), Err(PackageManagerError::AuthenticationError(_, 401))));
+    }
+    #[test]
+    fn an_archive_that_is_not_base64_is_rejected() {
+        let (url, transport) = responding(200, r#"{"data": "not base64!"}"#);
+        match transport.fetch_archive(&package("pool/hello-1.0.0.tar")) {
+            Err(PackageManagerError::InvalidArchiveEncoding(location, _)) => assert_eq!(location, format!("{}/pool/hello-1.0.0.tar", url)),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+    #[test]
+    fn long_bodies_are_cut_in_errors() {
+        assert_eq!(body_excerpt("short"), "short");
+        let body = "é".repeat(BODY_EXCERPT_LENGTH + 1);
+        assert_eq!(body_excerpt(&body), format!("{}...", "é".repeat(BODY_EXCERPT_LENGTH)));
+    }
+
+    #[test]
+    fn credentials_stay_within_the_source() {
+        let source = "https://repo.example.com/packages";
+        assert!(within_source(source, "https://repo.example.com/packages"));
+        assert!(within_source(source, "https://repo.example.com/packages/"));
+        assert!(within_source(source, "https://repo.example.com/packages/a-1.0.0.tar"));
+        // 默认端口写出来也是同一个源
+        assert!(within_source(source, "https://repo.example.com:443/packages/a-1.0.0.tar"));
+        assert!(within_source("https://repo.example.com/packages/", "https://repo.example.com/packages/a-1.0.0.tar"));
+    }
+    #[test]
+    fn credentials_are_not_sent_elsewhere() {
+        let source = "https://repo.example.com/packages";
+        // 路径只是前缀相同
+        assert!(!within_source(source, "https://repo.example.com/packages2/a-1.0.0.tar"));
+        assert!(!within_source(source, "https://repo.example.com/other/a-1.0.0.tar"));
+        // 协议、主机或端口不同
+        assert!(!within_source(source, "http://repo.example.com/packages/a-1.0.0.tar"));
+        assert!(!within_source(source, "https://mirror.example.com/packages/a-1.0.0.tar"));
+        assert!(!within_source(source, "https://repo.example.com.evil.org/packages/a-1.0.0.tar"));
+        assert!(!within_source(source, "https://repo.example.com:8443/packages/a-1.0.0.tar"));
+        assert!(!within_source(source, "https://user@evil.org/packages/a-1.0.0.tar"));
+        assert!(!within_source(source, "not a url"));
+    }
+    #[test]
+    fn a_root_source_covers_the_whole_host() {
+        assert!(within_source("http://127.0.0.1:8080", "http://127.0.0.1:8080/a-1.0.0.tar"));
+        assert!(within_source("http://127.0.0.1:8080/", "http://127.0.0.1:8080/pool/a-1.0.0.tar"));
+        assert!(!within_source("http://127.0.0.1:8080", "http://127.0.0.1:8081/a-1.0.0.tar"));
+    }
+    #[test]
+    fn the_configured_proxy_replaces_the_environment() {
+        let var = |name: &str| match name {
+            "HTTPS_PROXY" => Some("http://env-proxy:3128".to_string()),
+            "http_proxy" => Some("http://env-proxy:3128".to_string()),
+            "NO_PROXY" => Some("internal.example.com".to_string()),
+            _ => None,
+        };
+        let mut network = NetworkConfiguration { no_proxy: vec!["127.0.0.1".to_string()], ..NetworkConfiguration::default() };
+        assert_eq!(proxy_urls(&network, &var), vec![("http", "http://env-proxy:3128".to_string()), ("https", "http://env-proxy:3128".to_string())]);
+        // 配置中的 no_proxy 对环境变量中的代理也生效
+        assert_eq!(no_proxy_list(&network, &var), "127.0.0.1,internal.example.com");
+        network.proxy = "http://proxy.example.com:3128".to_string();
+        assert_eq!(proxy_urls(&network, &var), vec![("", "http://proxy.example.com:3128".to_string())]);
+        assert!(proxy_urls(&NetworkConfiguration::default(), &|_| None).is_empty());
+    }
+    #[test]
+    fn no_proxy_hosts_are_reached_directly() {
+        let proxy = serve(502, "from the proxy");
+        let repository = serve(200, r#"{"data": "packages = []", "signature": ""}"#);
+        let network = NetworkConfiguration { proxy: proxy.clone(), ..NetworkConfiguration::default() };
+        let transport = HttpTransport::new(&repository, network.clone(), None);
+        assert!(matches!(transport.fetch_index(), Err(PackageManagerError::HttpStatusError(_, 502, _))));
+        let network = NetworkConfiguration { no_proxy: vec!["127.0.0.1".to_string()], ..network };
+        let transport = HttpTransport::new(&repository, network, None);
+        assert_eq!(transport.fetch_index().unwrap().content, "packages = []");
+    }
+    // software-package/ 目录形式的软件源
+    fn fixtures() -> FileTransport {
+        let path = fixture("software-package").display().to_string();
+        return FileTransport::new(&path, &path);
+    }
+    #[test]
+    fn sources_pick_a_transport_by_url() {
+        let source = |url: &str| Source { name: "test".to_string(), url: url.to_string(), public_key: String::new() };
+        let network = NetworkConfiguration::default();
+        assert_eq!(transport_for(&source("https://repo.example.com/"), &network, None).location(), "https://repo.example.com");
+        // file:// 地址和本地目录读取的是同一个目录
+        let dependency = Dependency::from_string("a-1.0.0").unwrap();
+        let path = fixture("software-package").display().to_string();
+        for url in [path.clone(), format!("file://{}", path), format!("{}/", path)] {
+            let transport = transport_for(&source(&url), &network, None);
+            assert!(transport.fetch_package_information(&dependency).unwrap().content.contains("archive = \"a\""));
+        }
+    }
+    #[test]
+    fn a_directory_serves_package_files() {
+        let transport = fixtures();
+        let dependency = Dependency::from_string("a-1.0.0").unwrap();
+        let package: Package = toml::from_str(&transport.fetch_package_information(&dependency).unwrap().content).unwrap();
+        assert_eq!(package.download, "a-1.0.0.tar");
+        assert_eq!(transport.fetch_archive(&package).unwrap().len() as u64, package.size);
+        // 没有下载地址时使用目录中的 archive-version.tar
+        let package = Package { download: String::new(), ..package };
+        assert_eq!(transport.fetch_archive(&package).unwrap().len() as u64, package.size);
+    }
+    #[test]
+    fn old_dependency_lists_are_converted() {
+        let dependency = Dependency::from_string("a-1.0.0").unwrap();
+        let fetched = fixtures().fetch_dependency_list(&dependency).unwrap();
+        assert!(fetched.location.ends_with("a-1.txt"));
+        let list: DependencyListTemp = toml::from_str(&fetched.content).unwrap();
+        assert_eq!(list.dependencies, vec!["b-1.0.0".to_string(), "c-1.0.0".to_string()]);
+        // 旧格式的空文件表示没有依赖
+        let dependency = Dependency::from_string("c-1.0.0").unwrap();
+        let list: DependencyListTemp = toml::from_str(&fixtures().fetch_dependency_list(&dependency).unwrap().content).unwrap();
+        assert!(list.dependencies.is_empty());
+    }
+    #[test]
+    fn missing_packages_are_not_found() {
+        let transport = fixtures();
+        let dependency = Dependency::from_string("missing-1.0.0").unwrap();
+        assert!(matches!(transport.fetch_package_information(&dependency), Err(e) if e.is_not_found()));
+        assert!(matches!(transport.fetch_dependency_list(&dependency), Err(e) if e.is_not_found()));
+        assert!(matches!(transport.fetch_archive(&package("missing-1.0.0.tar")), Err(e) if e.is_not_found()));
+        // 指向网络地址的压缩包不能从目录中读取
+        assert!(matches!(transport.fetch_archive(&package("https://example.com/a-1.0.0.tar")), Err(e) if e.is_not_found()));
+        let missing = FileTransport::new("missing", "/nonexistent/repository");
+        assert!(matches!(missing.fetch_index(), Err(e) if e.is_not_found()));
+    }
+    #[test]
+    fn a_directory_without_an_index_lists_its_packages() {
+        let fetched = fixtures().fetch_index().unwrap();
+        assert_eq!(fetched.signature, None);
+        let list: PackageListTemp = toml::from_str(&fetched.content).unwrap();
+        let names: Vec<String> = list.packages.iter().map(|package| package.to_string()).collect();
+        assert_eq!(names, vec!["a-1.0.0", "b-1.0.0", "b-1.1.0", "c-1.0.0", "d-1.0.0", "e-1.0.0"]);
+    }
+}